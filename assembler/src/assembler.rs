@@ -2,11 +2,11 @@ use std::{collections::HashMap, fs::File, process::exit, vec};
 
 use svm_lang::{
   lexer::code::{self, Code},
-  opcodes::OpCode,
+  opcodes::{BinaryOp, OpCode, Overflow},
   Program, Type, Value,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Operand {
   Number(String),
   Register(String),
@@ -15,11 +15,20 @@ pub enum Operand {
   Type(String),
   Char(String),
   Bool(String),
+  /// Dotted name, such as the qualified name of an extern (`std.io.print`).
+  Symbol(String),
 }
 
+/// Mnemonics that accept an overflow mode suffix (`.chk`, `.wrap` or `.sat`).
+const OVERFLOW_MNEMONICS: [&str; 12] = [
+  "INC", "DEC", "ADD", "SUB", "MUL", "DIV", "MOD", "NEG", "POW", "SHL", "SHR", "CAST",
+];
+
 #[derive(Debug)]
 pub struct OpcodeToken {
   mnemonic: String,
+  /// Set with a suffix such as `.sat` in `ADD.sat`.
+  overflow: Option<Overflow>,
   operands: Vec<Operand>,
 }
 
@@ -27,6 +36,8 @@ pub struct OpcodeToken {
 pub enum InstructionToken {
   Opcode(OpcodeToken),
   Label(String),
+  /// Assembler directive such as `.local i32 x`, named without the dot.
+  Directive(String, Vec<Operand>),
 }
 
 pub struct AssemblerTokenizer {
@@ -110,6 +121,9 @@ fn parse_value(value: String, number_type: Option<Type>) -> Value {
       Type::I64 => i64::from_str_radix(&digits, base)
         .map(|x| Value::I64(x as i64))
         .expect("Invalid number"),
+      Type::Isize => isize::from_str_radix(&digits, base)
+        .map(Value::Isize)
+        .expect("Invalid number"),
       _ => panic!("Invalid number type"),
     };
   }
@@ -132,6 +146,12 @@ fn parse_value(value: String, number_type: Option<Type>) -> Value {
     Type::U64 => u64::from_str_radix(&digits, base)
       .map(|x| Value::U64(x as u64))
       .expect("Invalid number"),
+    Type::Usize => usize::from_str_radix(&digits, base)
+      .map(Value::Usize)
+      .expect("Invalid number"),
+    Type::Isize => isize::from_str_radix(&digits, base)
+      .map(Value::Isize)
+      .expect("Invalid number"),
     _ => panic!("Invalid number type"),
   };
 }
@@ -165,236 +185,277 @@ fn to_usize(number: Value) -> usize {
 }
 
 impl AssemblerTokenizer {
-  pub fn next_token(&mut self) -> Option<InstructionToken> {
+  /// Operands up to the end of the line.
+  fn operands(&mut self) -> Vec<Operand> {
+    let mut operands: Vec<Operand> = vec![];
+
     loop {
-      self.code.consume_while(|c| c.is_whitespace());
       let ch = self.code.peek();
 
       if ch.is_none() {
-        return None;
+        break;
       }
 
       let ch = ch.unwrap();
 
       match ch {
+        ' ' => {
+          self.code.consume();
+          continue;
+        }
+        '\n' => {
+          self.code.consume();
+          break;
+        }
         ';' => {
           self.code.consume_while(|c| *c != '\n');
-          continue;
+          break;
         }
-        c if c.is_alphabetic() => {
+        '%' => {
+          self.code.consume();
           let text = self
             .code
-            .consume_while(|c| c.is_alphanumeric() || *c == ':')
+            .consume_while(|c| c.is_alphanumeric())
             .iter()
             .collect::<String>();
 
-          if text.ends_with(":") {
-            return Some(InstructionToken::Label(text));
-          }
-
-          let mut operands: Vec<Operand> = vec![];
+          operands.push(Operand::Register(text));
+        }
+        '.' => {
+          self.code.consume();
+          let text = self
+            .code
+            .consume_while(|c| c.is_alphanumeric())
+            .iter()
+            .collect::<String>();
 
-          loop {
-            let ch = self.code.peek();
+          operands.push(Operand::Label(text));
+        }
+        '0' | '-' => {
+          let mut text = self.code.consume().unwrap().to_string();
 
-            if ch.is_none() {
-              break;
+          if text == "-" {
+            match self.code.consume() {
+              Some('0') => text.push('0'),
+              Some(c) if c.is_digit(10) => text.push(c),
+              _ => {
+                println!("Expected number after '-'");
+                exit(1)
+              }
             }
+          }
 
-            let ch = ch.unwrap();
+          match self.code.peek() {
+            Some('x') => {
+              text.push(self.code.consume().unwrap());
 
-            match ch {
-              ' ' => {
-                self.code.consume();
-                continue;
-              }
-              '\n' => {
-                self.code.consume();
-                break;
-              }
-              ';' => {
-                self.code.consume_while(|c| *c != '\n');
-                break;
-              }
-              '%' => {
-                self.code.consume();
-                let text = self
+              text.push_str(
+                &self
                   .code
-                  .consume_while(|c| c.is_alphanumeric())
+                  .consume_while(|c| c.is_digit(16))
                   .iter()
-                  .collect::<String>();
+                  .collect::<String>(),
+              );
+            }
+            Some('b') => {
+              text.push(self.code.consume().unwrap());
 
-                operands.push(Operand::Register(text));
-              }
-              '.' => {
-                self.code.consume();
-                let text = self
+              text.push_str(
+                &self
                   .code
-                  .consume_while(|c| c.is_alphanumeric())
+                  .consume_while(|c| c.is_digit(2))
                   .iter()
-                  .collect::<String>();
-
-                operands.push(Operand::Label(text));
-              }
-              '0' | '-' => {
-                let mut text = self.code.consume().unwrap().to_string();
-
-                if text == "-" {
-                  match self.code.consume() {
-                    Some('0') => text.push('0'),
-                    Some(c) if c.is_digit(10) => text.push(c),
-                    _ => {
-                      println!("Expected number after '-'");
-                      exit(1)
-                    }
-                  }
-                }
-
-                match self.code.peek() {
-                  Some('x') => {
-                    text.push(self.code.consume().unwrap());
-
-                    text.push_str(
-                      &self
-                        .code
-                        .consume_while(|c| c.is_digit(16))
-                        .iter()
-                        .collect::<String>(),
-                    );
-                  }
-                  Some('b') => {
-                    text.push(self.code.consume().unwrap());
-
-                    text.push_str(
-                      &self
-                        .code
-                        .consume_while(|c| c.is_digit(2))
-                        .iter()
-                        .collect::<String>(),
-                    );
-                  }
-                  Some('o') => {
-                    text.push(self.code.consume().unwrap());
-
-                    text.push_str(
-                      &self
-                        .code
-                        .consume_while(|c| c.is_digit(8))
-                        .iter()
-                        .collect::<String>(),
-                    );
-                  }
-                  Some(c) if c.is_digit(10) => {
-                    text.push_str(
-                      &self
-                        .code
-                        .consume_while(|c| c.is_digit(10))
-                        .iter()
-                        .collect::<String>(),
-                    );
-                  }
-                  _ => {}
-                }
+                  .collect::<String>(),
+              );
+            }
+            Some('o') => {
+              text.push(self.code.consume().unwrap());
 
-                operands.push(Operand::Number(text));
-              }
-              c if c.is_digit(10) => {
-                let text = self
+              text.push_str(
+                &self
                   .code
-                  .consume_while(|c| c.is_digit(10) || *c == '-')
+                  .consume_while(|c| c.is_digit(8))
                   .iter()
-                  .collect::<String>();
+                  .collect::<String>(),
+              );
+            }
+            Some(c) if c.is_digit(10) => {
+              text.push_str(
+                &self
+                  .code
+                  .consume_while(|c| c.is_digit(10))
+                  .iter()
+                  .collect::<String>(),
+              );
+            }
+            _ => {}
+          }
 
-                operands.push(Operand::Number(text));
-              }
-              '\'' => {
-                self.code.consume();
+          operands.push(Operand::Number(text));
+        }
+        c if c.is_digit(10) => {
+          let text = self
+            .code
+            .consume_while(|c| c.is_digit(10) || *c == '-')
+            .iter()
+            .collect::<String>();
 
-                let mut text = String::new();
+          operands.push(Operand::Number(text));
+        }
+        '\'' => {
+          self.code.consume();
 
-                while let Some(ch) = self.code.consume() {
-                  if ch == '\'' {
-                    operands.push(Operand::Char(text));
-                    break;
-                  }
+          let mut text = String::new();
 
-                  if text.len() > 0 {
-                    println!("Expected single character");
-                    exit(1)
-                  }
+          while let Some(ch) = self.code.consume() {
+            if ch == '\'' {
+              operands.push(Operand::Char(text));
+              break;
+            }
 
-                  if ch == '\\' {
-                    let next = self
-                      .code
-                      .consume()
-                      .expect("Expected character after escape");
-
-                    match next {
-                      'n' => text.push('\n'),
-                      'r' => text.push('\r'),
-                      't' => text.push('\t'),
-                      '0' => text.push('\0'),
-                      c => text.push(c),
-                    }
-                  }
+            if text.len() > 0 {
+              println!("Expected single character");
+              exit(1)
+            }
 
-                  text.push(ch);
-                }
+            if ch == '\\' {
+              let next = self
+                .code
+                .consume()
+                .expect("Expected character after escape");
+
+              match next {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                '0' => text.push('\0'),
+                c => text.push(c),
               }
-              '"' => {
-                self.code.consume();
+            }
 
-                let mut text = String::new();
+            text.push(ch);
+          }
+        }
+        '"' => {
+          self.code.consume();
 
-                while let Some(ch) = self.code.consume() {
-                  if ch == '\"' {
-                    operands.push(Operand::String(text));
-                    break;
-                  }
+          let mut text = String::new();
 
-                  if ch == '\\' {
-                    let next = self
-                      .code
-                      .consume()
-                      .expect("Expected character after escape");
-
-                    match next {
-                      'n' => text.push('\n'),
-                      'r' => text.push('\r'),
-                      't' => text.push('\t'),
-                      '0' => text.push('\0'),
-                      c => text.push(c),
-                    }
-
-                    continue;
-                  }
+          while let Some(ch) = self.code.consume() {
+            if ch == '\"' {
+              operands.push(Operand::String(text));
+              break;
+            }
 
-                  text.push(ch);
-                }
+            if ch == '\\' {
+              let next = self
+                .code
+                .consume()
+                .expect("Expected character after escape");
+
+              match next {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                '0' => text.push('\0'),
+                c => text.push(c),
               }
-              c if c.is_alphabetic() => {
-                let text = self
-                  .code
-                  .consume_while(|c| c.is_alphanumeric())
-                  .iter()
-                  .collect::<String>();
 
-                if text == "true" || text == "false" {
-                  operands.push(Operand::Bool(text));
-                  continue;
-                }
+              continue;
+            }
 
-                operands.push(Operand::Type(text));
-              }
-              _ => {
-                println!("Unknown character: {}", ch);
+            text.push(ch);
+          }
+        }
+        c if c.is_alphabetic() => {
+          let text = self
+            .code
+            .consume_while(|c| c.is_alphanumeric() || *c == '.' || *c == '_')
+            .iter()
+            .collect::<String>();
+
+          if text == "true" || text == "false" {
+            operands.push(Operand::Bool(text));
+            continue;
+          }
+
+          if text.contains('.') {
+            operands.push(Operand::Symbol(text));
+            continue;
+          }
+
+          operands.push(Operand::Type(text));
+        }
+        _ => {
+          println!("Unknown character: {}", ch);
+          exit(1)
+        }
+      }
+    }
+
+    return operands;
+  }
+
+  pub fn next_token(&mut self) -> Option<InstructionToken> {
+    loop {
+      self.code.consume_while(|c| c.is_whitespace());
+      let ch = self.code.peek();
+
+      if ch.is_none() {
+        return None;
+      }
+
+      let ch = ch.unwrap();
+
+      match ch {
+        ';' => {
+          self.code.consume_while(|c| *c != '\n');
+          continue;
+        }
+        '.' => {
+          self.code.consume();
+
+          let name = self
+            .code
+            .consume_while(|c| c.is_alphanumeric() || *c == '_')
+            .iter()
+            .collect::<String>();
+
+          return Some(InstructionToken::Directive(name, self.operands()));
+        }
+        c if c.is_alphabetic() => {
+          let text = self
+            .code
+            .consume_while(|c| c.is_alphanumeric() || *c == '_' || *c == ':' || *c == '.')
+            .iter()
+            .collect::<String>();
+
+          if text.ends_with(":") {
+            return Some(InstructionToken::Label(text));
+          }
+
+          let (text, overflow) = match text.split_once('.') {
+            Some((mnemonic, suffix)) => {
+              let Some(overflow) = Overflow::from_suffix(suffix) else {
+                println!("Unknown overflow mode: {}", suffix);
+                exit(1)
+              };
+
+              if !OVERFLOW_MNEMONICS.contains(&mnemonic) {
+                println!("{} does not take an overflow mode", mnemonic);
                 exit(1)
               }
+
+              (mnemonic.to_string(), Some(overflow))
             }
-          }
+            None => (text, None),
+          };
+
+          let operands = self.operands();
 
           return Some(InstructionToken::Opcode(OpcodeToken {
             mnemonic: text,
+            overflow,
             operands,
           }));
         }
@@ -448,6 +509,27 @@ fn get_register(operands: &mut Vec<Operand>) -> Option<Operand> {
   }
 }
 
+/// Index of a register operand: one of the aliases `addr`, `a`, `b` and `c`, or a numbered
+/// register `r0`, `r1`, ... where `r0` is `addr`, `r1` is `a` and so on.
+fn register_index(name: &str) -> Option<u8> {
+  match name {
+    "addr" => Some(0x01),
+    "a" => Some(0x02),
+    "b" => Some(0x03),
+    "c" => Some(0x04),
+    _ => name.strip_prefix('r')?.parse::<u8>().ok()?.checked_add(1),
+  }
+}
+
+/// Whether `opcode` is the three-register form of an arithmetic or comparison instruction.
+fn has_register_operands(opcode: &OpcodeToken) -> bool {
+  return BinaryOp::from_mnemonic(&opcode.mnemonic).is_some()
+    && opcode
+      .operands
+      .iter()
+      .any(|operand| matches!(operand, Operand::Register(_)));
+}
+
 pub fn get_bool(operands: &mut Vec<Operand>) -> Option<Operand> {
   let next_operand = operands.get(0)?;
 
@@ -457,19 +539,92 @@ pub fn get_bool(operands: &mut Vec<Operand>) -> Option<Operand> {
   }
 }
 
+/// Type and offset from the frame pointer of a local, given by a name declared with `.local`
+/// (`LGET x`) or by a type followed by a name or offset (`LGET i32 x`, `LGET i32 -4`).
+fn get_local(
+  operands: &mut Vec<Operand>,
+  locals: &HashMap<String, (Type, isize)>,
+) -> (Type, isize) {
+  let find = |name: &str| {
+    *locals
+      .get(name)
+      .unwrap_or_else(|| panic!("Local \"{}\" not found!", name))
+  };
+
+  let data_type = match get_type(operands) {
+    Some(Operand::Type(name)) if locals.contains_key(&name) => return find(&name),
+    Some(Operand::Type(data_type)) => Type::from_str(&data_type),
+    _ => panic!("Expected type or local"),
+  };
+
+  let offset = match get_number(operands).or_else(|| get_type(operands)) {
+    Some(Operand::Number(number)) => to_isize(parse_and_infer_number_type(number)),
+    Some(Operand::Type(name)) => find(&name).1,
+    _ => panic!("Expected offset or local"),
+  };
+
+  return (data_type, offset);
+}
+
+/// Type and index of a global, given by a name declared with `.global` (`GGET count`) or by a
+/// type followed by an index (`GGET u64 0`).
+fn get_global(
+  operands: &mut Vec<Operand>,
+  names: &HashMap<String, usize>,
+  globals: &[Value],
+) -> (Type, usize) {
+  let data_type = match get_type(operands) {
+    Some(Operand::Type(name)) if names.contains_key(&name) => {
+      let index = names[&name];
+
+      return (globals[index].data_type(), index);
+    }
+    Some(Operand::Type(data_type)) => Type::from_str(&data_type),
+    _ => panic!("Expected type or global"),
+  };
+
+  let index = match get_number(operands) {
+    Some(Operand::Number(number)) => to_usize(parse_and_infer_number_type(number)),
+    _ => panic!("Expected index"),
+  };
+
+  return (data_type, index);
+}
+
+/// Value of a `.const` or `.global`, written like a `PUSH` operand with an optional type.
+fn get_value(operands: &mut Vec<Operand>) -> Value {
+  let data_type = match get_type(operands) {
+    Some(Operand::Type(data_type)) => Some(Type::from_str(&data_type)),
+    _ => None,
+  };
+
+  if operands.is_empty() {
+    panic!("Expected value");
+  }
+
+  match operands.remove(0) {
+    Operand::Number(number) => parse_value(number, data_type),
+    Operand::Bool(b) => parse_value(b, Some(Type::Bool)),
+    Operand::String(text) => Value::String(text),
+    v => panic!("Invalid operand: {:?}", v),
+  }
+}
+
 pub fn compile(file: File) -> Program {
   let mut labels = HashMap::new();
+  let mut locals: HashMap<String, (Type, isize)> = HashMap::new();
+  let mut next_local: isize = 0;
+  let mut constants: Vec<Value> = Vec::new();
+  let mut constant_names: HashMap<String, usize> = HashMap::new();
+  let mut globals: Vec<Value> = Vec::new();
+  let mut global_names: HashMap<String, usize> = HashMap::new();
   let tokens = AssemblerTokenizer {
     code: code::from_file(file),
   }
   .collect::<Vec<InstructionToken>>();
   let mut opcodes: Vec<OpCode> = Vec::new();
-  let mut registers = HashMap::new();
+  let mut imports: Vec<String> = Vec::new();
   let mut pc = 0;
-  registers.insert("addr", 0x01);
-  registers.insert("a", 0x02);
-  registers.insert("b", 0x03);
-  registers.insert("c", 0x04);
 
   for token in &tokens {
     match token {
@@ -477,13 +632,84 @@ pub fn compile(file: File) -> Program {
         labels.insert(label[0..label.len() - 1].to_string(), pc);
       }
       InstructionToken::Opcode(_) => pc += 1,
+      // `.const <name> [type] <value>` and `.global <name> [type] <value>` can be used anywhere
+      // in the file, like labels.
+      InstructionToken::Directive(directive, operands)
+        if directive == "const" || directive == "global" =>
+      {
+        let mut operands = operands.clone();
+        let name = match get_type(&mut operands) {
+          Some(Operand::Type(name)) => name,
+          _ => panic!("Expected {} name", directive),
+        };
+        let value = get_value(&mut operands);
+
+        if directive == "const" {
+          constant_names.insert(name, constants.len());
+          constants.push(value);
+        } else {
+          global_names.insert(name, globals.len());
+          globals.push(value);
+        }
+      }
+      InstructionToken::Directive(..) => {}
     }
   }
 
   for token in tokens {
     match token {
       InstructionToken::Label(_) => continue,
+      // `.local <type> <name> [offset]` names a local at `offset` bytes from the frame pointer,
+      // by default right after the previous one. Give an offset of 0 to start a new frame.
+      InstructionToken::Directive(name, mut operands) => match name.as_str() {
+        "local" => {
+          let data_type = match get_type(&mut operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(&data_type),
+            _ => panic!("Expected type"),
+          };
+          let name = match get_type(&mut operands) {
+            Some(Operand::Type(name)) => name,
+            _ => panic!("Expected local name"),
+          };
+          let offset = match get_number(&mut operands) {
+            Some(Operand::Number(number)) => to_isize(parse_and_infer_number_type(number)),
+            _ => next_local,
+          };
+
+          next_local = offset + data_type.size() as isize;
+          locals.insert(name, (data_type, offset));
+        }
+        "const" | "global" => continue,
+        _ => panic!("Unknown directive: .{}", name),
+      },
       InstructionToken::Opcode(mut opcode) => match opcode.mnemonic.as_str() {
+        // `ADD u64 %a %b %c` and friends operate on registers instead of the stack.
+        _ if has_register_operands(&opcode) => {
+          let op = BinaryOp::from_mnemonic(&opcode.mnemonic).unwrap();
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          let mut next_register = || match get_register(&mut opcode.operands) {
+            Some(Operand::Register(register)) => register_index(register.as_str())
+              .unwrap_or_else(|| panic!("Register \"{}\" is invalid!", register)),
+            _ => panic!("Expected 3 registers for {}", op.mnemonic()),
+          };
+
+          let lhs = next_register();
+          let rhs = next_register();
+          let dst = next_register();
+
+          opcodes.push(OpCode::RegisterOp(
+            op,
+            opcode.overflow.unwrap_or_default(),
+            data_type,
+            lhs,
+            rhs,
+            dst,
+          ));
+        }
         "NOP" => opcodes.push(OpCode::NoOperation),
         "HALT" => opcodes.push(OpCode::Halt),
         "MOV" => {
@@ -529,12 +755,9 @@ pub fn compile(file: File) -> Program {
               }),
               _ => panic!("Unknown data type {data_type}"),
             },
-            Operand::Register(register) => Value::U8(
-              registers
-                .get(register.as_str())
-                .expect("Register not found!")
-                .clone(),
-            ),
+            Operand::Register(register) => {
+              Value::U8(register_index(register.as_str()).expect("Register not found!"))
+            }
             Operand::String(str) => {
               let bytes = vec![0 as u8]
                 .into_iter()
@@ -547,10 +770,8 @@ pub fn compile(file: File) -> Program {
           };
 
           opcodes.push(OpCode::Move(
-            registers
-              .get(reg.as_str())
-              .expect(format!("Register \"{}\" is invalid!", reg).as_str())
-              .clone(),
+            register_index(reg.as_str())
+              .expect(format!("Register \"{}\" is invalid!", reg).as_str()),
             value,
           ));
         }
@@ -565,7 +786,7 @@ pub fn compile(file: File) -> Program {
           };
 
           opcodes.push(OpCode::Register(
-            registers.get(reg.as_str()).unwrap().clone(),
+            register_index(reg.as_str()).expect("Register not found!"),
             data_type,
           ));
         }
@@ -618,7 +839,8 @@ pub fn compile(file: File) -> Program {
             | Type::I32
             | Type::I64
             | Type::Bool
-            | Type::Usize => |n: String| parse_value(n, Some(data_type.clone())),
+            | Type::Usize
+            | Type::Isize => |n: String| parse_value(n, Some(data_type.clone())),
             _ => panic!("Unknown data type {data_type}"),
           };
 
@@ -650,22 +872,15 @@ pub fn compile(file: File) -> Program {
                   .clone(),
               )),
               Operand::Register(register) => stack_values.push(Value::U8(
-                registers
-                  .get(register.as_str())
-                  .expect("Register not found!")
-                  .clone(),
+                register_index(register.as_str()).expect("Register not found!"),
               )),
               Operand::String(str) => {
                 write_operands(&mut opcodes, &mut stack_values);
 
-                let bytes = vec![0 as u8]
-                  .into_iter()
-                  .chain(str.as_bytes().iter().map(|x| *x))
-                  .collect::<Vec<u8>>();
-
-                opcodes.push(OpCode::PushBytes(bytes));
+                opcodes.push(OpCode::Push(Value::String(str)));
               }
               Operand::Type(t) => panic!("Unexpected type: {}", t),
+              Operand::Symbol(name) => panic!("Unexpected name: {}", name),
             }
           }
           write_operands(&mut opcodes, &mut stack_values);
@@ -691,12 +906,7 @@ pub fn compile(file: File) -> Program {
 
           opcodes.push(OpCode::Pop(
             data_type,
-            reg.map(|r| {
-              registers
-                .get(r.as_str())
-                .expect("Register not found!")
-                .clone()
-            }),
+            reg.map(|r| register_index(r.as_str()).expect("Register not found!")),
           ));
         }
         "COPY" => {
@@ -713,7 +923,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Increment(data_type));
+          opcodes.push(OpCode::Increment(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "DEC" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -721,7 +934,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Decrement(data_type));
+          opcodes.push(OpCode::Decrement(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "ADD" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -729,7 +945,7 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Add(data_type));
+          opcodes.push(OpCode::Add(data_type, opcode.overflow.unwrap_or_default()));
         }
         "SUB" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -737,7 +953,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Subtraction(data_type));
+          opcodes.push(OpCode::Subtraction(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "MUL" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -745,7 +964,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Multiply(data_type));
+          opcodes.push(OpCode::Multiply(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "DIV" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -753,14 +975,20 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Divide(data_type));
+          opcodes.push(OpCode::Divide(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "MOD" => {
           let data_type = match get_type(&mut opcode.operands) {
             Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
             _ => Type::I32,
           };
-          opcodes.push(OpCode::Modulo(data_type));
+          opcodes.push(OpCode::Modulo(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "NEG" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -768,7 +996,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Negative(data_type));
+          opcodes.push(OpCode::Negative(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "POW" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -776,7 +1007,26 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::Power(data_type));
+          opcodes.push(OpCode::Power(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
+        }
+        "CAST" => {
+          let mut next_type = || match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => panic!("Expected type"),
+          };
+
+          let from = next_type();
+          let to = next_type();
+
+          // Without a suffix CAST behaves like `as` in Rust.
+          opcodes.push(OpCode::Cast(
+            from,
+            to,
+            opcode.overflow.unwrap_or(Overflow::Wrapping),
+          ));
         }
         "AND" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -816,7 +1066,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::ShiftLeft(data_type));
+          opcodes.push(OpCode::ShiftLeft(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "SHR" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -824,7 +1077,10 @@ pub fn compile(file: File) -> Program {
             _ => Type::I32,
           };
 
-          opcodes.push(OpCode::ShiftRight(data_type));
+          opcodes.push(OpCode::ShiftRight(
+            data_type,
+            opcode.overflow.unwrap_or_default(),
+          ));
         }
         "EQ" => {
           let data_type = match get_type(&mut opcode.operands) {
@@ -923,16 +1179,28 @@ pub fn compile(file: File) -> Program {
           opcodes.push(OpCode::GotoIfNotZero);
         }
         "EXT" => {
-          let address = match get_number(&mut opcode.operands) {
-            Some(Operand::Number(number)) => to_usize(parse_and_infer_number_type(number)),
-            _ => panic!("Expected address"),
+          let name = match opcode.operands.remove(0) {
+            Operand::Symbol(name) | Operand::Type(name) => name,
+            _ => panic!("Expected extern name"),
           };
 
-          opcodes.push(OpCode::External(address));
+          let index = match imports.iter().position(|import| *import == name) {
+            Some(index) => index,
+            None => {
+              imports.push(name);
+              imports.len() - 1
+            }
+          };
+
+          opcodes.push(OpCode::External(index));
         }
         "CALL" => {
-          let address = match get_number(&mut opcode.operands) {
-            Some(Operand::Number(number)) => to_usize(parse_and_infer_number_type(number)),
+          let address = match opcode.operands.remove(0) {
+            Operand::Number(number) => to_usize(parse_and_infer_number_type(number)),
+            Operand::Label(label) => labels
+              .get(label.as_str())
+              .expect(format!("Label \"{}\" not found!", label).as_str())
+              .clone(),
             _ => panic!("Expected address"),
           };
 
@@ -941,10 +1209,156 @@ pub fn compile(file: File) -> Program {
         "RET" => {
           opcodes.push(OpCode::Return);
         }
+        "SPAWN" => {
+          let address = match opcode.operands.remove(0) {
+            Operand::Number(number) => to_usize(parse_and_infer_number_type(number)),
+            Operand::Label(label) => labels
+              .get(label.as_str())
+              .expect(format!("Label \"{}\" not found!", label).as_str())
+              .clone(),
+            _ => panic!("Expected address"),
+          };
+
+          opcodes.push(OpCode::Spawn(address));
+        }
+        "YIELD" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Yield(data_type));
+        }
+        "RESUME" => {
+          opcodes.push(OpCode::Resume);
+        }
+        "SEND" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Send(data_type));
+        }
+        "RECV" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Receive(data_type));
+        }
+        "PID" => {
+          opcodes.push(OpCode::ProcessId);
+        }
+        "TRY" => {
+          let address = match opcode.operands.remove(0) {
+            Operand::Number(number) => to_usize(parse_and_infer_number_type(number)),
+            Operand::Label(label) => labels
+              .get(label.as_str())
+              .expect(format!("Label \"{}\" not found!", label).as_str())
+              .clone(),
+            _ => panic!("Expected address"),
+          };
+
+          opcodes.push(OpCode::Try(address));
+        }
+        "END_TRY" => {
+          opcodes.push(OpCode::EndTry);
+        }
+        "THROW" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Throw(data_type));
+        }
+        "ALLOC" => {
+          opcodes.push(OpCode::Alloc);
+        }
+        "FREE" => {
+          opcodes.push(OpCode::Free);
+        }
+        "LOAD" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Load(data_type));
+        }
+        "STORE" => {
+          let data_type = match get_type(&mut opcode.operands) {
+            Some(Operand::Type(data_type)) => Type::from_str(data_type.as_str()),
+            _ => Type::I32,
+          };
+
+          opcodes.push(OpCode::Store(data_type));
+        }
+        "FP" => {
+          opcodes.push(OpCode::FramePointer);
+        }
+        "LGET" => {
+          let (data_type, offset) = get_local(&mut opcode.operands, &locals);
+
+          opcodes.push(OpCode::LocalGet(data_type, offset));
+        }
+        "LSET" => {
+          let (data_type, offset) = get_local(&mut opcode.operands, &locals);
+
+          opcodes.push(OpCode::LocalSet(data_type, offset));
+        }
+        "CONST" => {
+          let index =
+            match get_number(&mut opcode.operands).or_else(|| get_type(&mut opcode.operands)) {
+              Some(Operand::Number(number)) => to_usize(parse_and_infer_number_type(number)),
+              Some(Operand::Type(name)) => *constant_names
+                .get(&name)
+                .unwrap_or_else(|| panic!("Constant \"{}\" not found!", name)),
+              _ => panic!("Expected constant"),
+            };
+
+          opcodes.push(OpCode::PushConstant(index));
+        }
+        "GGET" => {
+          let (data_type, index) = get_global(&mut opcode.operands, &global_names, &globals);
+
+          opcodes.push(OpCode::GlobalGet(data_type, index));
+        }
+        "GSET" => {
+          let (data_type, index) = get_global(&mut opcode.operands, &global_names, &globals);
+
+          opcodes.push(OpCode::GlobalSet(data_type, index));
+        }
+        "CONCAT" => {
+          opcodes.push(OpCode::Concat);
+        }
+        "LEN" => {
+          opcodes.push(OpCode::Length);
+        }
+        "CHARS" => {
+          opcodes.push(OpCode::CharCount);
+        }
+        "SLICE" => {
+          opcodes.push(OpCode::Slice);
+        }
+        "ORD" => {
+          opcodes.push(OpCode::CharCode);
+        }
+        "CHR" => {
+          opcodes.push(OpCode::FromCharCode);
+        }
         _ => panic!("Unknown mnemonic: {}", opcode.mnemonic),
       },
     }
   }
 
-  return Program { opcodes };
+  return Program {
+    opcodes,
+    imports,
+    constants,
+    globals,
+    labels,
+  };
 }
@@ -22,7 +22,7 @@ pub enum Value {
   String(String),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Type {
   Bool,
   U8,
@@ -43,7 +43,7 @@ pub enum Type {
 
 impl Display for Type {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    return self.to_string().fmt(f);
+    return self.to_str().fmt(f);
   }
 }
 
@@ -75,6 +75,7 @@ impl Type {
       "f32" => Type::F32,
       "f64" => Type::F64,
       "usize" => Type::Usize,
+      "isize" => Type::Isize,
       "str" => Type::String,
       "bytes" => Type::Bytes,
       _ => panic!("Unknown data type: {}", str),
@@ -122,28 +123,36 @@ impl Type {
   }
 
   pub fn from_u8(code: u8) -> Self {
+    return Self::try_from_u8(code).unwrap_or_else(|| panic!("Unknown type_code: [0x{:x}]", code));
+  }
+
+  pub fn try_from_u8(code: u8) -> Option<Self> {
     return match code {
-      0x00 => Type::U8,
-      0x01 => Type::I8,
-      0x02 => Type::U16,
-      0x03 => Type::I16,
-      0x04 => Type::U32,
-      0x05 => Type::I32,
-      0x06 => Type::U64,
-      0x07 => Type::I64,
-      0x08 => Type::F32,
-      0x09 => Type::F64,
-      0x0A => Type::Usize,
-      0x0C => Type::Bool,
-      0x0D => Type::Isize,
-      0x0E => Type::String,
-      0x0F => Type::Bytes,
-      _ => panic!("Unknown type_code: [0x{:x}]", code),
+      0x00 => Some(Type::U8),
+      0x01 => Some(Type::I8),
+      0x02 => Some(Type::U16),
+      0x03 => Some(Type::I16),
+      0x04 => Some(Type::U32),
+      0x05 => Some(Type::I32),
+      0x06 => Some(Type::U64),
+      0x07 => Some(Type::I64),
+      0x08 => Some(Type::F32),
+      0x09 => Some(Type::F64),
+      0x0A => Some(Type::Usize),
+      0x0C => Some(Type::Bool),
+      0x0D => Some(Type::Isize),
+      0x0E => Some(Type::String),
+      0x0F => Some(Type::Bytes),
+      _ => None,
     };
   }
 }
 
 impl Value {
+  /// Bytes of the value as laid out on the stack: numbers big-endian, `bool` as one byte and
+  /// `bytes` as is. A string is a `0` marker followed by its UTF-8 bytes, so that `POP str`
+  /// can find where it starts by scanning down to the marker; this is the layout `PUSH "..."`
+  /// has always produced. Messages, snapshots and the constant pool store values this way.
   pub fn to_bytes(&self) -> Vec<u8> {
     return match self {
       Value::Bool(value) => {
@@ -167,10 +176,12 @@ impl Value {
       Value::Usize(value) => value.to_be_bytes().to_vec(),
       Value::Isize(value) => value.to_be_bytes().to_vec(),
       Value::Bytes(value) => value.clone(),
-      Value::String(value) => value.clone().into_bytes(),
+      Value::String(value) => vec![0].into_iter().chain(value.bytes()).collect(),
     };
   }
 
+  /// Reads a value laid out as by [`Value::to_bytes`]. Only the first bytes of `data` are used
+  /// for fixed-size types.
   pub fn from_stack_bytes(data: Vec<u8>, data_type: &Type) -> Self {
     return match data_type {
       Type::U8 => Value::U8(u8::from_be_bytes(
@@ -217,6 +228,18 @@ impl Value {
     };
   }
 
+  /// Like [`Value::from_stack_bytes`], but `None` unless `data` is exactly one valid value of
+  /// `data_type`.
+  pub fn try_from_stack_bytes(data: Vec<u8>, data_type: &Type) -> Option<Self> {
+    let valid = match data_type {
+      Type::String => data.first() == Some(&0) && std::str::from_utf8(&data[1..]).is_ok(),
+      Type::Bytes => true,
+      _ => data.len() == data_type.size(),
+    };
+
+    return valid.then(|| Value::from_stack_bytes(data, data_type));
+  }
+
   pub fn vec_to_bytes(value: Vec<Value>) -> Vec<u8> {
     return value.iter().flat_map(|x| x.to_bytes()).collect::<Vec<u8>>();
   }
@@ -241,3 +264,61 @@ impl Value {
     };
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stack_bytes_round_trip() {
+    let values = [
+      Value::Bool(true),
+      Value::U8(200),
+      Value::I8(-100),
+      Value::U16(60000),
+      Value::I16(-30000),
+      Value::U32(4_000_000_000),
+      Value::I32(-2_000_000_000),
+      Value::U64(u64::MAX),
+      Value::I64(i64::MIN),
+      Value::F32(1.5),
+      Value::F64(-0.25),
+      Value::Usize(usize::MAX),
+      Value::Isize(isize::MIN),
+      Value::Bytes(vec![1, 2, 3]),
+      Value::String(String::from("héllo")),
+    ];
+
+    for value in values {
+      let bytes = value.to_bytes();
+
+      assert_eq!(Value::from_stack_bytes(bytes, &value.data_type()), value);
+    }
+  }
+
+  #[test]
+  fn strings_start_with_a_null_marker() {
+    assert_eq!(
+      Value::String(String::from("hi")).to_bytes(),
+      vec![0, b'h', b'i']
+    );
+    assert_eq!(Value::String(String::new()).to_bytes(), vec![0]);
+  }
+
+  #[test]
+  fn try_from_stack_bytes_rejects_invalid_encodings() {
+    assert_eq!(Value::try_from_stack_bytes(vec![1, 2], &Type::U8), None);
+    assert_eq!(
+      Value::try_from_stack_bytes(b"hi".to_vec(), &Type::String),
+      None
+    );
+    assert_eq!(
+      Value::try_from_stack_bytes(vec![0, 0xFF], &Type::String),
+      None
+    );
+    assert_eq!(
+      Value::try_from_stack_bytes(vec![0, b'h', b'i'], &Type::String),
+      Some(Value::String(String::from("hi")))
+    );
+  }
+}
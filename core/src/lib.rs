@@ -1,7 +1,9 @@
 mod data;
 mod program;
+mod verifier;
 
 pub use data::*;
 pub mod lexer;
 pub mod opcodes;
 pub use program::*;
+pub use verifier::*;
@@ -1,8 +1,6 @@
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 
-use super::{Type, Value};
-
-const USIZE_LEN: usize = std::mem::size_of::<usize>();
+use super::{DecodeError, Type, Value};
 
 pub const NOP: u8 = 0x00;
 pub const HALT: u8 = 0x01;
@@ -52,6 +50,197 @@ pub const PUSH_BYTES_U8: u8 = 0x38;
 pub const PUSH_BYTES_U16: u8 = 0x39;
 pub const PUSH_BYTES_U32: u8 = 0x40;
 pub const PUSH_BYTES_U64: u8 = 0x41;
+pub const ALLOC: u8 = 0x42;
+pub const FREE: u8 = 0x43;
+pub const LOAD: u8 = 0x44;
+pub const STORE: u8 = 0x45;
+pub const SPAWN: u8 = 0x46;
+pub const YIELD: u8 = 0x47;
+pub const RESUME: u8 = 0x48;
+pub const SEND: u8 = 0x49;
+pub const RECEIVE: u8 = 0x4A;
+pub const PROCESS_ID: u8 = 0x4B;
+pub const TRY: u8 = 0x4C;
+pub const END_TRY: u8 = 0x4D;
+pub const THROW: u8 = 0x4E;
+pub const REGISTER_OPERATION: u8 = 0x4F;
+pub const CAST: u8 = 0x50;
+pub const CONCAT: u8 = 0x51;
+pub const LENGTH: u8 = 0x52;
+pub const CHAR_COUNT: u8 = 0x53;
+pub const SLICE: u8 = 0x54;
+pub const CHAR_CODE: u8 = 0x55;
+pub const FROM_CHAR_CODE: u8 = 0x56;
+pub const FRAME_POINTER: u8 = 0x57;
+pub const LOCAL_GET: u8 = 0x58;
+pub const LOCAL_SET: u8 = 0x59;
+pub const PUSH_CONSTANT: u8 = 0x5A;
+pub const GLOBAL_GET: u8 = 0x5B;
+pub const GLOBAL_SET: u8 = 0x5C;
+
+/// What integer arithmetic does when the result does not fit in its type. Floats ignore it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+  /// Stops execution with an error. Used when an arithmetic mnemonic has no suffix; `CAST`
+  /// defaults to [`Overflow::Wrapping`] instead.
+  #[default]
+  Checked,
+  /// Wraps around at the bounds of the type, like two's complement hardware.
+  Wrapping,
+  /// Clamps the result to the minimum or maximum value of the type.
+  Saturating,
+}
+
+impl Overflow {
+  pub fn to_u8(&self) -> u8 {
+    match self {
+      Overflow::Checked => 0x00,
+      Overflow::Wrapping => 0x01,
+      Overflow::Saturating => 0x02,
+    }
+  }
+
+  pub fn from_u8(byte: u8) -> Overflow {
+    return Self::try_from_u8(byte)
+      .unwrap_or_else(|| panic!("Unknown overflow mode: [0x{:x}]", byte));
+  }
+
+  pub fn try_from_u8(byte: u8) -> Option<Overflow> {
+    match byte {
+      0x00 => Some(Overflow::Checked),
+      0x01 => Some(Overflow::Wrapping),
+      0x02 => Some(Overflow::Saturating),
+      _ => None,
+    }
+  }
+
+  /// Parses the mnemonic suffix without the dot, e.g. `sat` in `ADD.sat`.
+  pub fn from_suffix(suffix: &str) -> Option<Overflow> {
+    match suffix {
+      "chk" => Some(Overflow::Checked),
+      "wrap" => Some(Overflow::Wrapping),
+      "sat" => Some(Overflow::Saturating),
+      _ => None,
+    }
+  }
+
+  /// Suffix appended to the mnemonic, empty for the default checked mode.
+  pub fn suffix(&self) -> &'static str {
+    match self {
+      Overflow::Checked => "",
+      Overflow::Wrapping => ".wrap",
+      Overflow::Saturating => ".sat",
+    }
+  }
+}
+
+/// Arithmetic and comparison operations that also have a three-register form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+  Add,
+  Subtraction,
+  Multiply,
+  Divide,
+  Modulo,
+  Power,
+  Equals,
+  NotEquals,
+  GreaterThan,
+  GreaterThanOrEqual,
+  LessThan,
+  LessThanOrEqual,
+}
+
+impl BinaryOp {
+  /// Byte of the stack form of the operation, e.g. [`ADD`].
+  pub fn to_u8(&self) -> u8 {
+    match self {
+      BinaryOp::Add => ADD,
+      BinaryOp::Subtraction => SUBTRACTION,
+      BinaryOp::Multiply => MULTIPLY,
+      BinaryOp::Divide => DIVIDE,
+      BinaryOp::Modulo => MODULO,
+      BinaryOp::Power => POWER,
+      BinaryOp::Equals => EQUALS,
+      BinaryOp::NotEquals => NOT_EQUALS,
+      BinaryOp::GreaterThan => GREATER_THAN,
+      BinaryOp::GreaterThanOrEqual => GREATER_THAN_OR_EQUAL,
+      BinaryOp::LessThan => LESS_THAN,
+      BinaryOp::LessThanOrEqual => LESS_THAN_OR_EQUAL,
+    }
+  }
+
+  pub fn from_u8(byte: u8) -> BinaryOp {
+    return Self::try_from_u8(byte)
+      .unwrap_or_else(|| panic!("Unknown register operation: [0x{:x}]", byte));
+  }
+
+  pub fn try_from_u8(byte: u8) -> Option<BinaryOp> {
+    match byte {
+      ADD => Some(BinaryOp::Add),
+      SUBTRACTION => Some(BinaryOp::Subtraction),
+      MULTIPLY => Some(BinaryOp::Multiply),
+      DIVIDE => Some(BinaryOp::Divide),
+      MODULO => Some(BinaryOp::Modulo),
+      POWER => Some(BinaryOp::Power),
+      EQUALS => Some(BinaryOp::Equals),
+      NOT_EQUALS => Some(BinaryOp::NotEquals),
+      GREATER_THAN => Some(BinaryOp::GreaterThan),
+      GREATER_THAN_OR_EQUAL => Some(BinaryOp::GreaterThanOrEqual),
+      LESS_THAN => Some(BinaryOp::LessThan),
+      LESS_THAN_OR_EQUAL => Some(BinaryOp::LessThanOrEqual),
+      _ => None,
+    }
+  }
+
+  pub fn from_mnemonic(mnemonic: &str) -> Option<BinaryOp> {
+    match mnemonic {
+      "ADD" => Some(BinaryOp::Add),
+      "SUB" => Some(BinaryOp::Subtraction),
+      "MUL" => Some(BinaryOp::Multiply),
+      "DIV" => Some(BinaryOp::Divide),
+      "MOD" => Some(BinaryOp::Modulo),
+      "POW" => Some(BinaryOp::Power),
+      "EQ" => Some(BinaryOp::Equals),
+      "NEQ" => Some(BinaryOp::NotEquals),
+      "GT" => Some(BinaryOp::GreaterThan),
+      "GTE" => Some(BinaryOp::GreaterThanOrEqual),
+      "LT" => Some(BinaryOp::LessThan),
+      "LTE" => Some(BinaryOp::LessThanOrEqual),
+      _ => None,
+    }
+  }
+
+  pub fn mnemonic(&self) -> &'static str {
+    match self {
+      BinaryOp::Add => "ADD",
+      BinaryOp::Subtraction => "SUB",
+      BinaryOp::Multiply => "MUL",
+      BinaryOp::Divide => "DIV",
+      BinaryOp::Modulo => "MOD",
+      BinaryOp::Power => "POW",
+      BinaryOp::Equals => "EQ",
+      BinaryOp::NotEquals => "NEQ",
+      BinaryOp::GreaterThan => "GT",
+      BinaryOp::GreaterThanOrEqual => "GTE",
+      BinaryOp::LessThan => "LT",
+      BinaryOp::LessThanOrEqual => "LTE",
+    }
+  }
+
+  /// Comparisons produce a `bool`, the other operations a value of the operand type.
+  pub fn result_type(&self, operand: Type) -> Type {
+    match self {
+      BinaryOp::Equals
+      | BinaryOp::NotEquals
+      | BinaryOp::GreaterThan
+      | BinaryOp::GreaterThanOrEqual
+      | BinaryOp::LessThan
+      | BinaryOp::LessThanOrEqual => Type::Bool,
+      _ => operand,
+    }
+  }
+}
 
 #[derive(Clone, Debug)]
 pub enum OpCode {
@@ -67,28 +256,29 @@ pub enum OpCode {
   MoveStackPointer(isize),
   Push(Value),
   PushBytes(Vec<u8>),
+  /// Values of the same type, pushed in order.
   PushAll(Vec<Value>),
   Pop(Type, Option<u8>),
   Copy(Type),
 
   // Arithmetic
-  Increment(Type),
-  Decrement(Type),
-  Add(Type),
-  Subtraction(Type),
-  Multiply(Type),
-  Divide(Type),
-  Modulo(Type),
-  Negative(Type),
-  Power(Type),
+  Increment(Type, Overflow),
+  Decrement(Type, Overflow),
+  Add(Type, Overflow),
+  Subtraction(Type, Overflow),
+  Multiply(Type, Overflow),
+  Divide(Type, Overflow),
+  Modulo(Type, Overflow),
+  Negative(Type, Overflow),
+  Power(Type, Overflow),
 
   // Bitwise
   And(Type),
   Or(Type),
   XOr(Type),
   Not(Type),
-  ShiftLeft(Type),
-  ShiftRight(Type),
+  ShiftLeft(Type, Overflow),
+  ShiftRight(Type, Overflow),
 
   // Comparison
   Equals(Type),
@@ -98,6 +288,10 @@ pub enum OpCode {
   LessThan(Type),
   LessThanOrEqual(Type),
 
+  // Registers
+  /// `op` on the `lhs` and `rhs` registers, storing the result in `dst`.
+  RegisterOp(BinaryOp, Overflow, Type, u8, u8, u8),
+
   // Control Flow
   Jump(usize),
   JumpIfZero(usize),
@@ -112,6 +306,59 @@ pub enum OpCode {
   // Function
   Call(usize),
   Return,
+
+  // Memory
+  Alloc,
+  Free,
+  Load(Type),
+  Store(Type),
+
+  // Coroutines
+  Spawn(usize),
+  Yield(Type),
+  Resume,
+
+  // Messages
+  Send(Type),
+  Receive(Type),
+  ProcessId,
+
+  // Exceptions
+  Try(usize),
+  EndTry,
+  Throw(Type),
+
+  // Conversion
+  /// Converts a value from the first type to the second. `Wrapping` follows Rust's `as`,
+  /// `Checked` fails when the value changes and `Saturating` clamps integers to the target.
+  Cast(Type, Type, Overflow),
+
+  // Strings
+  Concat,
+  /// Length in bytes.
+  Length,
+  /// Length in chars.
+  CharCount,
+  /// Chars from a start index up to, but not including, an end index.
+  Slice,
+  /// Code of the char at an index.
+  CharCode,
+  /// String with the single char of a code.
+  FromCharCode,
+
+  // Locals
+  /// Stack pointer saved by the innermost `CALL`, or 0 outside of functions.
+  FramePointer,
+  /// Pushes the value at a byte offset from the frame pointer.
+  LocalGet(Type, isize),
+  /// Pops a value and stores it at a byte offset from the frame pointer.
+  LocalSet(Type, isize),
+
+  // Data
+  /// Pushes a value from the constant pool of the program.
+  PushConstant(usize),
+  GlobalGet(Type, usize),
+  GlobalSet(Type, usize),
 }
 
 impl OpCode {
@@ -123,7 +370,7 @@ impl OpCode {
         .into_iter()
         .chain(register.to_be_bytes())
         .chain(value.data_type().to_bytes())
-        .chain(value.to_bytes())
+        .chain(value_to_bytes(value))
         .collect(),
       OpCode::Register(a, b) => vec![REGISTER]
         .into_iter()
@@ -139,7 +386,7 @@ impl OpCode {
       OpCode::Push(a) => vec![PUSH]
         .into_iter()
         .chain(a.data_type().to_bytes())
-        .chain(a.to_bytes())
+        .chain(value_to_bytes(a))
         .collect(),
       OpCode::PushBytes(a) => match a.len() {
         len if len <= u8::MAX as usize => vec![PUSH_BYTES_U8]
@@ -164,27 +411,34 @@ impl OpCode {
           .collect::<Vec<u8>>(),
         _ => panic!("Maximum number of values exceeded!"),
       },
+      OpCode::PushAll(items) if items.len() == 1 => OpCode::Push(items[0].clone()).to_bytes(),
       OpCode::PushAll(items) => {
-        let bytes = match items.len() {
-          len if len == 1 => vec![PUSH],
+        let data_type = items.first().map_or(Type::U8, |item| item.data_type());
+
+        assert!(
+          items.iter().all(|item| item.data_type() == data_type),
+          "PUSH_ALL values must have the same type"
+        );
+
+        let bytes: Vec<u8> = match items.len() {
           len if len <= u8::MAX as usize => vec![PUSH_ALL_U8]
             .into_iter()
-            .chain(items[0].data_type().to_bytes())
+            .chain(data_type.to_bytes())
             .chain((len as u8).to_be_bytes())
             .collect(),
           len if len <= u16::MAX as usize => vec![PUSH_ALL_U16]
             .into_iter()
-            .chain(items[0].data_type().to_bytes())
+            .chain(data_type.to_bytes())
             .chain((len as u16).to_be_bytes())
             .collect(),
           len if len <= u32::MAX as usize => vec![PUSH_ALL_U32]
             .into_iter()
-            .chain(items[0].data_type().to_bytes())
+            .chain(data_type.to_bytes())
             .chain((len as u32).to_be_bytes())
             .collect(),
           len if len <= u64::MAX as usize => vec![PUSH_ALL_U64]
             .into_iter()
-            .chain(items[0].data_type().to_bytes())
+            .chain(data_type.to_bytes())
             .chain((len as u64).to_be_bytes())
             .collect(),
 
@@ -193,7 +447,7 @@ impl OpCode {
 
         bytes
           .into_iter()
-          .chain(items.iter().flat_map(|v| v.to_bytes()))
+          .chain(items.iter().flat_map(value_to_bytes))
           .collect()
       }
       OpCode::Pop(a, b) => vec![POP]
@@ -202,21 +456,96 @@ impl OpCode {
         .chain(b.unwrap_or(0).to_be_bytes())
         .collect(),
       OpCode::Copy(a) => vec![COPY].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Increment(a) => vec![INCREMENT].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Decrement(a) => vec![DECREMENT].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Add(a) => vec![ADD].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Subtraction(a) => vec![SUBTRACTION].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Multiply(a) => vec![MULTIPLY].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Divide(a) => vec![DIVIDE].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Modulo(a) => vec![MODULO].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Negative(a) => vec![NEGATIVE].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::Power(a) => vec![POWER].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Increment(a, overflow) => vec![INCREMENT]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Concat => vec![CONCAT],
+      OpCode::Length => vec![LENGTH],
+      OpCode::CharCount => vec![CHAR_COUNT],
+      OpCode::Slice => vec![SLICE],
+      OpCode::CharCode => vec![CHAR_CODE],
+      OpCode::FromCharCode => vec![FROM_CHAR_CODE],
+      OpCode::FramePointer => vec![FRAME_POINTER],
+      OpCode::LocalGet(t, offset) => vec![LOCAL_GET]
+        .into_iter()
+        .chain(t.to_bytes())
+        .chain(offset.to_be_bytes())
+        .collect(),
+      OpCode::LocalSet(t, offset) => vec![LOCAL_SET]
+        .into_iter()
+        .chain(t.to_bytes())
+        .chain(offset.to_be_bytes())
+        .collect(),
+      OpCode::PushConstant(index) => vec![PUSH_CONSTANT]
+        .into_iter()
+        .chain(index.to_be_bytes())
+        .collect(),
+      OpCode::GlobalGet(t, index) => vec![GLOBAL_GET]
+        .into_iter()
+        .chain(t.to_bytes())
+        .chain(index.to_be_bytes())
+        .collect(),
+      OpCode::GlobalSet(t, index) => vec![GLOBAL_SET]
+        .into_iter()
+        .chain(t.to_bytes())
+        .chain(index.to_be_bytes())
+        .collect(),
+      OpCode::Decrement(a, overflow) => vec![DECREMENT]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Add(a, overflow) => vec![ADD]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Subtraction(a, overflow) => vec![SUBTRACTION]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Multiply(a, overflow) => vec![MULTIPLY]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Divide(a, overflow) => vec![DIVIDE]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Modulo(a, overflow) => vec![MODULO]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Negative(a, overflow) => vec![NEGATIVE]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::Power(a, overflow) => vec![POWER]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
       OpCode::And(a) => vec![AND].into_iter().chain(a.to_bytes()).collect(),
       OpCode::Or(a) => vec![OR].into_iter().chain(a.to_bytes()).collect(),
       OpCode::XOr(a) => vec![XOR].into_iter().chain(a.to_bytes()).collect(),
       OpCode::Not(a) => vec![NOT].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::ShiftLeft(a) => vec![SHIFT_LEFT].into_iter().chain(a.to_bytes()).collect(),
-      OpCode::ShiftRight(a) => vec![SHIFT_RIGHT].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::ShiftLeft(a, overflow) => vec![SHIFT_LEFT]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::ShiftRight(a, overflow) => vec![SHIFT_RIGHT]
+        .into_iter()
+        .chain(a.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
       OpCode::Equals(a) => vec![EQUALS].into_iter().chain(a.to_bytes()).collect(),
       OpCode::NotEquals(a) => vec![NOT_EQUALS].into_iter().chain(a.to_bytes()).collect(),
       OpCode::GreaterThan(a) => vec![GREATER_THAN].into_iter().chain(a.to_bytes()).collect(),
@@ -244,269 +573,458 @@ impl OpCode {
       OpCode::External(a) => vec![EXTERNAL].into_iter().chain(a.to_be_bytes()).collect(),
       OpCode::Call(a) => vec![CALL].into_iter().chain(a.to_be_bytes()).collect(),
       OpCode::Return => vec![RETURN],
+      OpCode::Alloc => vec![ALLOC],
+      OpCode::Free => vec![FREE],
+      OpCode::Load(a) => vec![LOAD].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Store(a) => vec![STORE].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Spawn(a) => vec![SPAWN].into_iter().chain(a.to_be_bytes()).collect(),
+      OpCode::Yield(a) => vec![YIELD].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Resume => vec![RESUME],
+      OpCode::Send(a) => vec![SEND].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Receive(a) => vec![RECEIVE].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::ProcessId => vec![PROCESS_ID],
+      OpCode::Try(a) => vec![TRY].into_iter().chain(a.to_be_bytes()).collect(),
+      OpCode::EndTry => vec![END_TRY],
+      OpCode::Throw(a) => vec![THROW].into_iter().chain(a.to_bytes()).collect(),
+      OpCode::Cast(from, to, overflow) => vec![CAST]
+        .into_iter()
+        .chain(from.to_bytes())
+        .chain(to.to_bytes())
+        .chain([overflow.to_u8()])
+        .collect(),
+      OpCode::RegisterOp(op, overflow, t, lhs, rhs, dst) => {
+        vec![REGISTER_OPERATION, op.to_u8(), overflow.to_u8()]
+          .into_iter()
+          .chain(t.to_bytes())
+          .chain([*lhs, *rhs, *dst])
+          .collect()
+      }
     }
   }
 
+  /// Decodes the instruction at the start of `bytes` and removes it. Panics on malformed
+  /// bytecode; see [`OpCode::try_from_bytes`].
   pub fn from_bytes(bytes: &mut Vec<u8>) -> OpCode {
-    let opcode = bytes.remove(0);
+    return Self::try_from_bytes(bytes).unwrap_or_else(|error| panic!("{}", error));
+  }
 
-    match opcode {
+  /// Decodes the instruction at the start of `bytes` and removes it.
+  pub fn try_from_bytes(bytes: &mut Vec<u8>) -> Result<OpCode, DecodeError> {
+    let opcode = take_u8(bytes)?;
+
+    let opcode = match opcode {
       NOP => OpCode::NoOperation,
       HALT => OpCode::Halt,
       MOVE => {
-        let reg = bytes.remove(0);
-        let data_type = Type::from_u8(bytes.remove(0));
-
-        let value = Value::from_stack_bytes(bytes.clone(), &data_type);
-
-        bytes.splice(0..data_type.size(), vec![]);
+        let reg = take_u8(bytes)?;
+        let data_type = take_type(bytes)?;
 
-        OpCode::Move(reg, value)
+        OpCode::Move(reg, take_value(bytes, data_type)?)
       }
       REGISTER => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let reg = bytes.remove(0);
+        let data_type = take_type(bytes)?;
+        let reg = take_u8(bytes)?;
 
         OpCode::Register(reg, data_type)
       }
       PROGRAM_COUNTER => OpCode::ProgramCounter,
       STACK_POINTER => OpCode::StackPointer,
-      MOVE_STACK_POINTER => {
-        let len_bytes = bytes.splice(0..USIZE_LEN, vec![]).collect::<Vec<u8>>();
-        let value = isize::from_be_bytes(len_bytes.as_slice().try_into().unwrap());
-
-        OpCode::MoveStackPointer(value)
-      }
+      MOVE_STACK_POINTER => OpCode::MoveStackPointer(take_isize(bytes)?),
       PUSH => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let value = Value::from_stack_bytes(bytes.clone(), &data_type);
-
-        bytes.splice(0..data_type.size(), vec![]);
+        let data_type = take_type(bytes)?;
 
-        OpCode::Push(value)
+        OpCode::Push(take_value(bytes, data_type)?)
       }
-      PUSH_ALL_U8 => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let count = bytes.remove(0);
+      PUSH_ALL_U8 | PUSH_ALL_U16 | PUSH_ALL_U32 | PUSH_ALL_U64 => {
+        let data_type = take_type(bytes)?;
+        let len = match opcode {
+          PUSH_ALL_U8 => take_u8(bytes)? as usize,
+          PUSH_ALL_U16 => u16::from_be_bytes(take_array(bytes)?) as usize,
+          PUSH_ALL_U32 => u32::from_be_bytes(take_array(bytes)?) as usize,
+          _ => take_u64_len(bytes)?,
+        };
 
-        let values = bytes
-          .splice(0..(count as usize * data_type.size()), vec![])
-          .collect::<Vec<u8>>()
-          .chunks(data_type.size())
-          .map(|v| Value::from_stack_bytes(v.to_vec(), &data_type))
-          .collect::<Vec<Value>>();
+        let values = (0..len)
+          .map(|_| take_value(bytes, data_type))
+          .collect::<Result<Vec<Value>, DecodeError>>()?;
 
         OpCode::PushAll(values)
       }
-      PUSH_ALL_U16 => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let len = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
-
-        let values = bytes
-          .splice(0..(len * data_type.size()), vec![])
-          .collect::<Vec<u8>>()
-          .chunks(data_type.size())
-          .map(|v| Value::from_stack_bytes(v.to_vec(), &data_type))
-          .collect::<Vec<Value>>();
+      PUSH_BYTES_U8 | PUSH_BYTES_U16 | PUSH_BYTES_U32 | PUSH_BYTES_U64 => {
+        let len = match opcode {
+          PUSH_BYTES_U8 => take_u8(bytes)? as usize,
+          PUSH_BYTES_U16 => u16::from_be_bytes(take_array(bytes)?) as usize,
+          PUSH_BYTES_U32 => u32::from_be_bytes(take_array(bytes)?) as usize,
+          _ => take_u64_len(bytes)?,
+        };
 
-        OpCode::PushAll(values)
+        OpCode::PushBytes(take(bytes, len)?)
       }
-      PUSH_ALL_U32 => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let len = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+      POP => {
+        let data_type = take_type(bytes)?;
+        let reg = take_u8(bytes)?;
 
-        let values = bytes
-          .splice(0..(len * data_type.size()), vec![])
-          .collect::<Vec<u8>>()
-          .chunks(data_type.size())
-          .map(|v| Value::from_stack_bytes(v.to_vec(), &data_type))
-          .collect::<Vec<Value>>();
+        OpCode::Pop(data_type, if reg == 0 { None } else { Some(reg) })
+      }
+      COPY => OpCode::Copy(take_type(bytes)?),
+      INCREMENT => OpCode::Increment(take_type(bytes)?, take_overflow(bytes)?),
+      DECREMENT => OpCode::Decrement(take_type(bytes)?, take_overflow(bytes)?),
+      ADD => OpCode::Add(take_type(bytes)?, take_overflow(bytes)?),
+      SUBTRACTION => OpCode::Subtraction(take_type(bytes)?, take_overflow(bytes)?),
+      MULTIPLY => OpCode::Multiply(take_type(bytes)?, take_overflow(bytes)?),
+      DIVIDE => OpCode::Divide(take_type(bytes)?, take_overflow(bytes)?),
+      MODULO => OpCode::Modulo(take_type(bytes)?, take_overflow(bytes)?),
+      NEGATIVE => OpCode::Negative(take_type(bytes)?, take_overflow(bytes)?),
+      POWER => OpCode::Power(take_type(bytes)?, take_overflow(bytes)?),
+      AND => OpCode::And(take_type(bytes)?),
+      OR => OpCode::Or(take_type(bytes)?),
+      XOR => OpCode::XOr(take_type(bytes)?),
+      NOT => OpCode::Not(take_type(bytes)?),
+      SHIFT_LEFT => OpCode::ShiftLeft(take_type(bytes)?, take_overflow(bytes)?),
+      SHIFT_RIGHT => OpCode::ShiftRight(take_type(bytes)?, take_overflow(bytes)?),
+      EQUALS => OpCode::Equals(take_type(bytes)?),
+      NOT_EQUALS => OpCode::NotEquals(take_type(bytes)?),
+      GREATER_THAN => OpCode::GreaterThan(take_type(bytes)?),
+      GREATER_THAN_OR_EQUAL => OpCode::GreaterThanOrEqual(take_type(bytes)?),
+      LESS_THAN => OpCode::LessThan(take_type(bytes)?),
+      LESS_THAN_OR_EQUAL => OpCode::LessThanOrEqual(take_type(bytes)?),
+      GOTO => OpCode::Goto,
+      GOTO_IF_ZERO => OpCode::GotoIfZero,
+      GOTO_IF_NOT_ZERO => OpCode::GotoIfNotZero,
+      JUMP => OpCode::Jump(take_usize(bytes)?),
+      JUMP_IF_ZERO => OpCode::JumpIfZero(take_usize(bytes)?),
+      JUMP_IF_NOT_ZERO => OpCode::JumpIfNotZero(take_usize(bytes)?),
+      EXTERNAL => OpCode::External(take_usize(bytes)?),
+      CALL => OpCode::Call(take_usize(bytes)?),
+      RETURN => OpCode::Return,
+      ALLOC => OpCode::Alloc,
+      FREE => OpCode::Free,
+      LOAD => OpCode::Load(take_type(bytes)?),
+      STORE => OpCode::Store(take_type(bytes)?),
+      SPAWN => OpCode::Spawn(take_usize(bytes)?),
+      YIELD => OpCode::Yield(take_type(bytes)?),
+      RESUME => OpCode::Resume,
+      SEND => OpCode::Send(take_type(bytes)?),
+      RECEIVE => OpCode::Receive(take_type(bytes)?),
+      PROCESS_ID => OpCode::ProcessId,
+      TRY => OpCode::Try(take_usize(bytes)?),
+      END_TRY => OpCode::EndTry,
+      THROW => OpCode::Throw(take_type(bytes)?),
+      REGISTER_OPERATION => {
+        let code = take_u8(bytes)?;
+        let op = BinaryOp::try_from_u8(code).ok_or(DecodeError::UnknownOperation(code))?;
+        let overflow = take_overflow(bytes)?;
+        let item_type = take_type(bytes)?;
+        let [lhs, rhs, dst] = take_array(bytes)?;
 
-        OpCode::PushAll(values)
+        OpCode::RegisterOp(op, overflow, item_type, lhs, rhs, dst)
       }
-      PUSH_ALL_U64 => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let len = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+      CAST => OpCode::Cast(take_type(bytes)?, take_type(bytes)?, take_overflow(bytes)?),
+      CONCAT => OpCode::Concat,
+      LENGTH => OpCode::Length,
+      CHAR_COUNT => OpCode::CharCount,
+      SLICE => OpCode::Slice,
+      CHAR_CODE => OpCode::CharCode,
+      FROM_CHAR_CODE => OpCode::FromCharCode,
+      FRAME_POINTER => OpCode::FramePointer,
+      LOCAL_GET => OpCode::LocalGet(take_type(bytes)?, take_isize(bytes)?),
+      LOCAL_SET => OpCode::LocalSet(take_type(bytes)?, take_isize(bytes)?),
+      PUSH_CONSTANT => OpCode::PushConstant(take_usize(bytes)?),
+      GLOBAL_GET => OpCode::GlobalGet(take_type(bytes)?, take_usize(bytes)?),
+      GLOBAL_SET => OpCode::GlobalSet(take_type(bytes)?, take_usize(bytes)?),
+      byte => return Err(DecodeError::UnknownOpcode(byte)),
+    };
 
-        let values = bytes
-          .splice(0..(len * data_type.size()), vec![])
-          .collect::<Vec<u8>>()
-          .chunks(data_type.size())
-          .map(|v| Value::from_stack_bytes(v.to_vec(), &data_type))
-          .collect::<Vec<Value>>();
+    return Ok(opcode);
+  }
+}
 
-        OpCode::PushAll(values)
-      }
-      PUSH_BYTES_U8 => {
-        let len = u8::from_be_bytes(
-          bytes
-            .splice(0..1, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+/// Removes the first `len` bytes.
+pub(crate) fn take(bytes: &mut Vec<u8>, len: usize) -> Result<Vec<u8>, DecodeError> {
+  if bytes.len() < len {
+    return Err(DecodeError::Truncated);
+  }
 
-        let value = bytes.splice(0..len as usize, vec![]).collect::<Vec<u8>>();
+  return Ok(bytes.drain(..len).collect());
+}
 
-        OpCode::PushBytes(value)
-      }
-      PUSH_BYTES_U16 => {
-        let len = u16::from_be_bytes(
-          bytes
-            .splice(0..2, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+pub(crate) fn take_array<const N: usize>(bytes: &mut Vec<u8>) -> Result<[u8; N], DecodeError> {
+  return Ok(take(bytes, N)?.try_into().unwrap());
+}
 
-        let value = bytes.splice(0..len as usize, vec![]).collect::<Vec<u8>>();
+pub(crate) fn take_u8(bytes: &mut Vec<u8>) -> Result<u8, DecodeError> {
+  return Ok(take_array::<1>(bytes)?[0]);
+}
 
-        OpCode::PushBytes(value)
-      }
-      PUSH_BYTES_U32 => {
-        let len = u32::from_be_bytes(
-          bytes
-            .splice(0..4, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+fn take_usize(bytes: &mut Vec<u8>) -> Result<usize, DecodeError> {
+  return Ok(usize::from_be_bytes(take_array(bytes)?));
+}
 
-        let value = bytes.splice(0..len as usize, vec![]).collect::<Vec<u8>>();
+fn take_isize(bytes: &mut Vec<u8>) -> Result<isize, DecodeError> {
+  return Ok(isize::from_be_bytes(take_array(bytes)?));
+}
 
-        OpCode::PushBytes(value)
-      }
-      PUSH_BYTES_U64 => {
-        let len = u64::from_be_bytes(
-          bytes
-            .splice(0..8, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+/// A `u64` length, which cannot fit in the remaining bytes if it does not fit in a `usize`.
+fn take_u64_len(bytes: &mut Vec<u8>) -> Result<usize, DecodeError> {
+  return usize::try_from(u64::from_be_bytes(take_array(bytes)?))
+    .map_err(|_| DecodeError::Truncated);
+}
 
-        let value = bytes.splice(0..len as usize, vec![]).collect::<Vec<u8>>();
+fn take_type(bytes: &mut Vec<u8>) -> Result<Type, DecodeError> {
+  let code = take_u8(bytes)?;
 
-        OpCode::PushBytes(value)
-      }
-      POP => {
-        let data_type = Type::from_u8(bytes.remove(0));
-        let reg = bytes.remove(0);
+  return Type::try_from_u8(code).ok_or(DecodeError::UnknownType(code));
+}
 
-        OpCode::Pop(data_type, if reg == 0 { None } else { Some(reg) })
-      }
-      COPY => OpCode::Copy(Type::from_u8(bytes.remove(0))),
-      INCREMENT => OpCode::Increment(Type::from_u8(bytes.remove(0))),
-      DECREMENT => OpCode::Decrement(Type::from_u8(bytes.remove(0))),
-      ADD => OpCode::Add(Type::from_u8(bytes.remove(0))),
-      SUBTRACTION => OpCode::Subtraction(Type::from_u8(bytes.remove(0))),
-      MULTIPLY => OpCode::Multiply(Type::from_u8(bytes.remove(0))),
-      DIVIDE => OpCode::Divide(Type::from_u8(bytes.remove(0))),
-      MODULO => OpCode::Modulo(Type::from_u8(bytes.remove(0))),
-      NEGATIVE => OpCode::Negative(Type::from_u8(bytes.remove(0))),
-      POWER => OpCode::Power(Type::from_u8(bytes.remove(0))),
-      AND => OpCode::And(Type::from_u8(bytes.remove(0))),
-      OR => OpCode::Or(Type::from_u8(bytes.remove(0))),
-      XOR => OpCode::XOr(Type::from_u8(bytes.remove(0))),
-      NOT => OpCode::Not(Type::from_u8(bytes.remove(0))),
-      SHIFT_LEFT => OpCode::ShiftLeft(Type::from_u8(bytes.remove(0))),
-      SHIFT_RIGHT => OpCode::ShiftRight(Type::from_u8(bytes.remove(0))),
-      EQUALS => OpCode::Equals(Type::from_u8(bytes.remove(0))),
-      NOT_EQUALS => OpCode::NotEquals(Type::from_u8(bytes.remove(0))),
-      GREATER_THAN => OpCode::GreaterThan(Type::from_u8(bytes.remove(0))),
-      GREATER_THAN_OR_EQUAL => OpCode::GreaterThanOrEqual(Type::from_u8(bytes.remove(0))),
-      LESS_THAN => OpCode::LessThan(Type::from_u8(bytes.remove(0))),
-      LESS_THAN_OR_EQUAL => OpCode::LessThanOrEqual(Type::from_u8(bytes.remove(0))),
-      GOTO => OpCode::Goto,
-      GOTO_IF_ZERO => OpCode::GotoIfZero,
-      GOTO_IF_NOT_ZERO => OpCode::GotoIfNotZero,
-      JUMP => {
-        let address = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+fn take_overflow(bytes: &mut Vec<u8>) -> Result<Overflow, DecodeError> {
+  let code = take_u8(bytes)?;
 
-        OpCode::Jump(address)
-      }
-      JUMP_IF_ZERO => {
-        let address = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+  return Overflow::try_from_u8(code).ok_or(DecodeError::UnknownOverflow(code));
+}
 
-        OpCode::JumpIfZero(address)
-      }
-      JUMP_IF_NOT_ZERO => {
-        let address = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+/// Bytes of a value operand. Strings and byte arrays have no fixed size, so their bytes are
+/// preceded by a `u32` length.
+fn value_to_bytes(value: &Value) -> Vec<u8> {
+  let bytes = value.to_bytes();
 
-        OpCode::JumpIfNotZero(address)
-      }
-      EXTERNAL => {
-        let address = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+  return match value {
+    Value::String(_) | Value::Bytes(_) => (bytes.len() as u32)
+      .to_be_bytes()
+      .into_iter()
+      .chain(bytes)
+      .collect(),
+    _ => bytes,
+  };
+}
+
+/// Removes a value operand written by `value_to_bytes`.
+fn take_value(bytes: &mut Vec<u8>, data_type: Type) -> Result<Value, DecodeError> {
+  let len = match data_type {
+    Type::String | Type::Bytes => u32::from_be_bytes(take_array(bytes)?) as usize,
+    _ => data_type.size(),
+  };
 
-        OpCode::External(address)
+  return Value::try_from_stack_bytes(take(bytes, len)?, &data_type)
+    .ok_or(DecodeError::InvalidValue(data_type));
+}
+
+impl OpCode {
+  /// Assembler mnemonic of the instruction.
+  pub fn mnemonic(&self) -> &'static str {
+    match self {
+      OpCode::NoOperation => "NOP",
+      OpCode::Halt => "HALT",
+      OpCode::Move(_, _) => "MOV",
+      OpCode::Register(_, _) => "REG",
+      OpCode::ProgramCounter => "PC",
+      OpCode::StackPointer => "SP",
+      OpCode::MoveStackPointer(_) => "MSP",
+      OpCode::Push(_) | OpCode::PushBytes(_) | OpCode::PushAll(_) => "PUSH",
+      OpCode::Pop(_, _) => "POP",
+      OpCode::Copy(_) => "COPY",
+      OpCode::Increment(..) => "INC",
+      OpCode::Decrement(..) => "DEC",
+      OpCode::Add(..) => "ADD",
+      OpCode::Subtraction(..) => "SUB",
+      OpCode::Multiply(..) => "MUL",
+      OpCode::Divide(..) => "DIV",
+      OpCode::Modulo(..) => "MOD",
+      OpCode::Negative(..) => "NEG",
+      OpCode::Power(..) => "POW",
+      OpCode::And(_) => "AND",
+      OpCode::Or(_) => "OR",
+      OpCode::XOr(_) => "XOR",
+      OpCode::Not(_) => "NOT",
+      OpCode::ShiftLeft(..) => "SHL",
+      OpCode::ShiftRight(..) => "SHR",
+      OpCode::Equals(_) => "EQ",
+      OpCode::NotEquals(_) => "NEQ",
+      OpCode::GreaterThan(_) => "GT",
+      OpCode::GreaterThanOrEqual(_) => "GTE",
+      OpCode::LessThan(_) => "LT",
+      OpCode::LessThanOrEqual(_) => "LTE",
+      OpCode::Jump(_) => "JMP",
+      OpCode::JumpIfZero(_) => "JZ",
+      OpCode::JumpIfNotZero(_) => "JNZ",
+      OpCode::Goto => "GOTO",
+      OpCode::GotoIfZero => "GZ",
+      OpCode::GotoIfNotZero => "GNZ",
+      OpCode::External(_) => "EXT",
+      OpCode::Call(_) => "CALL",
+      OpCode::Return => "RET",
+      OpCode::Alloc => "ALLOC",
+      OpCode::Free => "FREE",
+      OpCode::Load(_) => "LOAD",
+      OpCode::Store(_) => "STORE",
+      OpCode::Spawn(_) => "SPAWN",
+      OpCode::Yield(_) => "YIELD",
+      OpCode::Resume => "RESUME",
+      OpCode::Send(_) => "SEND",
+      OpCode::Receive(_) => "RECV",
+      OpCode::ProcessId => "PID",
+      OpCode::Try(_) => "TRY",
+      OpCode::EndTry => "END_TRY",
+      OpCode::Throw(_) => "THROW",
+      OpCode::RegisterOp(op, ..) => op.mnemonic(),
+      OpCode::Cast(..) => "CAST",
+      OpCode::Concat => "CONCAT",
+      OpCode::Length => "LEN",
+      OpCode::CharCount => "CHARS",
+      OpCode::Slice => "SLICE",
+      OpCode::CharCode => "ORD",
+      OpCode::FromCharCode => "CHR",
+      OpCode::FramePointer => "FP",
+      OpCode::LocalGet(..) => "LGET",
+      OpCode::LocalSet(..) => "LSET",
+      OpCode::PushConstant(_) => "CONST",
+      OpCode::GlobalGet(..) => "GGET",
+      OpCode::GlobalSet(..) => "GSET",
+    }
+  }
+}
+
+/// Name used by the assembler for a register index.
+pub fn register_name(register: u8) -> String {
+  match register {
+    1 => String::from("addr"),
+    2 => String::from("a"),
+    3 => String::from("b"),
+    4 => String::from("c"),
+    n => format!("r{}", n as usize - 1),
+  }
+}
+
+fn format_value(value: &Value) -> String {
+  match value {
+    Value::Bool(v) => v.to_string(),
+    Value::U8(v) => v.to_string(),
+    Value::I8(v) => v.to_string(),
+    Value::U16(v) => v.to_string(),
+    Value::I16(v) => v.to_string(),
+    Value::U32(v) => v.to_string(),
+    Value::I32(v) => v.to_string(),
+    Value::U64(v) => v.to_string(),
+    Value::I64(v) => v.to_string(),
+    Value::F32(v) => v.to_string(),
+    Value::F64(v) => v.to_string(),
+    Value::Usize(v) => v.to_string(),
+    Value::Isize(v) => v.to_string(),
+    Value::String(v) => format!("{:?}", v),
+    Value::Bytes(v) => format_bytes(v),
+  }
+}
+
+/// Strings are pushed with a leading null byte, so show those as string literals.
+fn format_bytes(bytes: &[u8]) -> String {
+  match bytes.split_first() {
+    Some((0, rest)) => match std::str::from_utf8(rest) {
+      Ok(text) => format!("{:?}", text),
+      Err(_) => format!("{:?}", bytes),
+    },
+    _ => format!("{:?}", bytes),
+  }
+}
+
+impl Display for OpCode {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let mnemonic = self.mnemonic();
+
+    match self {
+      OpCode::Move(reg, value) => write!(
+        f,
+        "{} {} %{} {}",
+        mnemonic,
+        value.data_type(),
+        register_name(*reg),
+        format_value(value)
+      ),
+      OpCode::Register(reg, t) => write!(f, "{} {} %{}", mnemonic, t, register_name(*reg)),
+      OpCode::MoveStackPointer(offset) => write!(f, "{} {}", mnemonic, offset),
+      OpCode::LocalGet(t, offset) | OpCode::LocalSet(t, offset) => {
+        write!(f, "{} {} {}", mnemonic, t, offset)
       }
-      CALL => {
-        let address = usize::from_be_bytes(
-          bytes
-            .splice(0..USIZE_LEN, vec![])
-            .collect::<Vec<u8>>()
-            .as_slice()
-            .try_into()
-            .unwrap(),
-        );
+      OpCode::GlobalGet(t, index) | OpCode::GlobalSet(t, index) => {
+        write!(f, "{} {} {}", mnemonic, t, index)
+      }
+      OpCode::Push(Value::String(text)) => write!(f, "{} {:?}", mnemonic, text),
+      OpCode::Push(value) => write!(
+        f,
+        "{} {} {}",
+        mnemonic,
+        value.data_type(),
+        format_value(value)
+      ),
+      OpCode::PushBytes(bytes) => write!(f, "{} {}", mnemonic, format_bytes(bytes)),
+      OpCode::PushAll(values) => write!(
+        f,
+        "{} {} {}",
+        mnemonic,
+        values.first().map(|v| v.data_type()).unwrap_or(Type::U8),
+        values
+          .iter()
+          .map(format_value)
+          .collect::<Vec<_>>()
+          .join(" ")
+      ),
+      OpCode::Pop(t, Some(reg)) => write!(f, "{} {} %{}", mnemonic, t, register_name(*reg)),
+      OpCode::RegisterOp(_, overflow, t, lhs, rhs, dst) => write!(
+        f,
+        "{}{} {} %{} %{} %{}",
+        mnemonic,
+        overflow.suffix(),
+        t,
+        register_name(*lhs),
+        register_name(*rhs),
+        register_name(*dst)
+      ),
+      OpCode::Increment(t, overflow)
+      | OpCode::Decrement(t, overflow)
+      | OpCode::Add(t, overflow)
+      | OpCode::Subtraction(t, overflow)
+      | OpCode::Multiply(t, overflow)
+      | OpCode::Divide(t, overflow)
+      | OpCode::Modulo(t, overflow)
+      | OpCode::Negative(t, overflow)
+      | OpCode::Power(t, overflow)
+      | OpCode::ShiftLeft(t, overflow)
+      | OpCode::ShiftRight(t, overflow) => write!(f, "{}{} {}", mnemonic, overflow.suffix(), t),
+      OpCode::Cast(from, to, overflow) => {
+        // Unlike arithmetic, a plain CAST wraps.
+        let suffix = match overflow {
+          Overflow::Wrapping => "",
+          Overflow::Checked => ".chk",
+          Overflow::Saturating => ".sat",
+        };
 
-        OpCode::Call(address)
+        write!(f, "{}{} {} {}", mnemonic, suffix, from, to)
       }
-      RETURN => OpCode::Return,
-      byte => panic!("Unknown opcode: [0x{:x}]", byte),
+      OpCode::Pop(t, None)
+      | OpCode::Copy(t)
+      | OpCode::And(t)
+      | OpCode::Or(t)
+      | OpCode::XOr(t)
+      | OpCode::Not(t)
+      | OpCode::Equals(t)
+      | OpCode::NotEquals(t)
+      | OpCode::GreaterThan(t)
+      | OpCode::GreaterThanOrEqual(t)
+      | OpCode::LessThan(t)
+      | OpCode::LessThanOrEqual(t)
+      | OpCode::Load(t)
+      | OpCode::Store(t)
+      | OpCode::Yield(t)
+      | OpCode::Send(t)
+      | OpCode::Receive(t)
+      | OpCode::Throw(t) => write!(f, "{} {}", mnemonic, t),
+      OpCode::Jump(addr)
+      | OpCode::JumpIfZero(addr)
+      | OpCode::JumpIfNotZero(addr)
+      | OpCode::External(addr)
+      | OpCode::Call(addr)
+      | OpCode::Spawn(addr)
+      | OpCode::Try(addr)
+      | OpCode::PushConstant(addr) => write!(f, "{} {}", mnemonic, addr),
+      _ => write!(f, "{}", mnemonic),
     }
   }
 }
@@ -1,16 +1,76 @@
-use std::{fs::File, io::Read};
+use std::{
+  collections::HashMap,
+  fmt::Display,
+  fs::File,
+  io::{BufRead, BufReader, Read},
+};
 
-use crate::opcodes::OpCode;
+use crate::{
+  opcodes::{take, take_array, take_u8, OpCode},
+  Type, Value,
+};
+
+/// Bytecode files start with this magic, followed by the format version.
+pub const MAGIC: &[u8; 4] = b"SVM\0";
+pub const FORMAT_VERSION: u8 = 5;
+
+/// Why bytecode could not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+  MissingHeader,
+  UnsupportedVersion(u8),
+  Truncated,
+  InvalidImport,
+  UnknownOpcode(u8),
+  UnknownType(u8),
+  UnknownOverflow(u8),
+  UnknownOperation(u8),
+  /// The bytes of a value are not a valid encoding of its type.
+  InvalidValue(Type),
+}
+
+impl Display for DecodeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DecodeError::MissingHeader => write!(f, "missing header"),
+      DecodeError::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+      DecodeError::Truncated => write!(f, "unexpected end of bytecode"),
+      DecodeError::InvalidImport => write!(f, "import name is not valid UTF-8"),
+      DecodeError::UnknownOpcode(code) => write!(f, "unknown opcode [0x{code:x}]"),
+      DecodeError::UnknownType(code) => write!(f, "unknown type code [0x{code:x}]"),
+      DecodeError::UnknownOverflow(code) => write!(f, "unknown overflow mode [0x{code:x}]"),
+      DecodeError::UnknownOperation(code) => write!(f, "unknown register operation [0x{code:x}]"),
+      DecodeError::InvalidValue(value_type) => write!(f, "invalid {value_type} value"),
+    }
+  }
+}
+
+impl std::error::Error for DecodeError {}
 
 #[derive(Debug)]
 pub struct Program {
   pub opcodes: Vec<OpCode>,
+  /// Qualified names of the externs used by the program (e.g. `std.io.print`).
+  /// `EXT n` calls the n-th import.
+  pub imports: Vec<String>,
+  /// Constant pool. `CONST n` pushes the n-th constant.
+  pub constants: Vec<Value>,
+  /// Initial values of the globals. `GGET` and `GSET` address them by index, and their types
+  /// never change.
+  pub globals: Vec<Value>,
+  /// Label addresses known to the assembler. They are not part of the bytecode and are
+  /// stored in a separate `.sym` file.
+  pub labels: HashMap<String, usize>,
 }
 
 impl Program {
   pub fn empty() -> Self {
     return Self {
       opcodes: Vec::new(),
+      imports: Vec::new(),
+      constants: Vec::new(),
+      globals: Vec::new(),
+      labels: HashMap::new(),
     };
   }
   pub fn from_file(mut file: File) -> Self {
@@ -20,22 +80,297 @@ impl Program {
 
     return Self::from_bytes(&mut bytes);
   }
+  /// Panics if `bytes` is not a valid program; see [`Program::try_from_bytes`].
   pub fn from_bytes(bytes: &mut Vec<u8>) -> Self {
+    return Self::try_from_bytes(bytes)
+      .unwrap_or_else(|error| panic!("Invalid program: {}", error));
+  }
+
+  /// Decodes a program, consuming `bytes`.
+  pub fn try_from_bytes(bytes: &mut Vec<u8>) -> Result<Self, DecodeError> {
+    if !bytes.starts_with(MAGIC) {
+      return Err(DecodeError::MissingHeader);
+    }
+
+    bytes.drain(..MAGIC.len());
+
+    let version = take_u8(bytes)?;
+
+    if version != FORMAT_VERSION {
+      return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let import_count = take_u32(bytes)?;
+    let mut imports = Vec::new();
+
+    for _ in 0..import_count {
+      let len = u16::from_be_bytes(take_array(bytes)?);
+      let name = take(bytes, len as usize)?;
+
+      imports.push(String::from_utf8(name).map_err(|_| DecodeError::InvalidImport)?);
+    }
+
+    let constants = values_from_bytes(bytes)?;
+    let globals = values_from_bytes(bytes)?;
+
     let mut opcodes = Vec::new();
 
-    while bytes.len() > 0 {
-      opcodes.push(OpCode::from_bytes(bytes));
+    while !bytes.is_empty() {
+      opcodes.push(OpCode::try_from_bytes(bytes)?);
     }
 
-    return Self { opcodes };
+    return Ok(Self {
+      opcodes,
+      imports,
+      constants,
+      globals,
+      labels: HashMap::new(),
+    });
   }
 
   pub fn to_bytes(&self) -> Vec<u8> {
-    return self
-      .opcodes
+    let header = MAGIC
       .iter()
-      .map(|x| x.to_bytes())
-      .flatten()
+      .copied()
+      .chain([FORMAT_VERSION])
+      .chain((self.imports.len() as u32).to_be_bytes());
+
+    let imports = self.imports.iter().flat_map(|name| {
+      (name.len() as u16)
+        .to_be_bytes()
+        .into_iter()
+        .chain(name.bytes())
+    });
+
+    return header
+      .chain(imports)
+      .chain(values_to_bytes(&self.constants))
+      .chain(values_to_bytes(&self.globals))
+      .chain(self.opcodes.iter().flat_map(|x| x.to_bytes()))
       .collect();
   }
+
+  /// Serializes the labels as `<address> <name>` lines, ordered by address.
+  pub fn labels_to_string(&self) -> String {
+    let mut labels = self.labels.iter().collect::<Vec<_>>();
+
+    labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+
+    return labels
+      .iter()
+      .map(|(name, address)| format!("{} {}\n", address, name))
+      .collect();
+  }
+
+  pub fn load_labels(&mut self, file: File) {
+    for line in BufReader::new(file).lines() {
+      let line = line.expect("Could not read symbols file");
+
+      if let Some((address, name)) = line.split_once(' ') {
+        if let Ok(address) = address.parse::<usize>() {
+          self.labels.insert(name.to_string(), address);
+        }
+      }
+    }
+  }
+
+  /// Returns the label at `address`, if any.
+  pub fn label_at(&self, address: usize) -> Option<&str> {
+    return self
+      .labels
+      .iter()
+      .filter(|(_, a)| **a == address)
+      .map(|(name, _)| name.as_str())
+      .min();
+  }
+
+  /// Returns the label of the region containing `address`, i.e. the closest label at or
+  /// before it.
+  pub fn region_of(&self, address: usize) -> Option<&str> {
+    let start = self.labels.values().filter(|a| **a <= address).max()?;
+
+    return self.label_at(*start);
+  }
+}
+
+/// Writes the number of values as a `u32`, then for each value its type code, the length of
+/// its bytes as a `u32` and the bytes.
+fn values_to_bytes(values: &[Value]) -> Vec<u8> {
+  let count = (values.len() as u32).to_be_bytes();
+
+  return count
+    .into_iter()
+    .chain(values.iter().flat_map(|value| {
+      let bytes = value.to_bytes();
+
+      value
+        .data_type()
+        .to_bytes()
+        .into_iter()
+        .chain((bytes.len() as u32).to_be_bytes())
+        .chain(bytes)
+    }))
+    .collect();
+}
+
+fn values_from_bytes(bytes: &mut Vec<u8>) -> Result<Vec<Value>, DecodeError> {
+  let count = take_u32(bytes)?;
+  let mut values = Vec::new();
+
+  for _ in 0..count {
+    let code = take_u8(bytes)?;
+    let value_type = Type::try_from_u8(code).ok_or(DecodeError::UnknownType(code))?;
+    let len = take_u32(bytes)?;
+    let data = take(bytes, len as usize)?;
+
+    values.push(
+      Value::try_from_stack_bytes(data, &value_type)
+        .ok_or(DecodeError::InvalidValue(value_type))?,
+    );
+  }
+
+  return Ok(values);
+}
+
+fn take_u32(bytes: &mut Vec<u8>) -> Result<u32, DecodeError> {
+  return Ok(u32::from_be_bytes(take_array(bytes)?));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::opcodes::{BinaryOp, Overflow};
+
+  fn program() -> Program {
+    return Program {
+      opcodes: vec![
+        OpCode::Move(2, Value::U32(7)),
+        OpCode::Push(Value::I64(-3)),
+        OpCode::Push(Value::String(String::from("héllo"))),
+        OpCode::PushBytes(vec![0, b'h', b'i']),
+        OpCode::PushAll(vec![Value::U8(1), Value::U8(2)]),
+        OpCode::Pop(Type::U8, Some(3)),
+        OpCode::Add(Type::U64, Overflow::Saturating),
+        OpCode::Negative(Type::I8, Overflow::Checked),
+        OpCode::RegisterOp(BinaryOp::Multiply, Overflow::Wrapping, Type::U16, 2, 3, 4),
+        OpCode::Jump(11),
+        OpCode::External(0),
+        OpCode::Cast(Type::F64, Type::U8, Overflow::Checked),
+        OpCode::LocalSet(Type::I32, -4),
+        OpCode::PushConstant(1),
+        OpCode::GlobalGet(Type::U64, 0),
+        OpCode::Halt,
+      ],
+      imports: vec![String::from("std.io.print")],
+      constants: vec![Value::String(String::from("héllo")), Value::F32(1.5)],
+      globals: vec![Value::U64(0), Value::Bool(true)],
+      labels: HashMap::new(),
+    };
+  }
+
+  #[test]
+  fn bytes_round_trip() {
+    let program = program();
+    let decoded = Program::try_from_bytes(&mut program.to_bytes()).unwrap();
+
+    assert_eq!(
+      format!("{:?}", decoded.opcodes),
+      format!("{:?}", program.opcodes)
+    );
+    assert_eq!(decoded.imports, program.imports);
+    assert_eq!(decoded.constants, program.constants);
+    assert_eq!(decoded.globals, program.globals);
+  }
+
+  #[test]
+  fn push_all_round_trips_with_one_or_no_values() {
+    let cases: [(Vec<Value>, &str); 2] = [
+      (vec![Value::U32(7)], "Push(U32(7))"),
+      (vec![], "PushAll([])"),
+    ];
+
+    for (values, decoded) in cases {
+      let program = Program {
+        opcodes: vec![OpCode::PushAll(values), OpCode::Halt],
+        ..Program::empty()
+      };
+      let program = Program::try_from_bytes(&mut program.to_bytes()).unwrap();
+
+      assert_eq!(
+        format!("{:?}", program.opcodes),
+        format!("[{decoded}, Halt]")
+      );
+    }
+  }
+
+  #[test]
+  fn rejects_a_missing_header_or_another_version() {
+    let mut bytes = program().to_bytes();
+
+    assert_eq!(
+      Program::try_from_bytes(&mut bytes[1..].to_vec()).unwrap_err(),
+      DecodeError::MissingHeader
+    );
+
+    bytes[MAGIC.len()] = FORMAT_VERSION + 1;
+
+    assert_eq!(
+      Program::try_from_bytes(&mut bytes).unwrap_err(),
+      DecodeError::UnsupportedVersion(FORMAT_VERSION + 1)
+    );
+  }
+
+  #[test]
+  fn rejects_truncated_bytecode_at_every_length() {
+    let bytes = program().to_bytes();
+    let mut truncated = 0;
+
+    for len in 0..bytes.len() {
+      let result = Program::try_from_bytes(&mut bytes[..len].to_vec());
+
+      if matches!(result, Err(DecodeError::Truncated)) {
+        truncated += 1;
+      }
+    }
+
+    // Cutting at an instruction boundary leaves a shorter, valid program.
+    assert!(truncated > bytes.len() / 2);
+  }
+
+  #[test]
+  fn rejects_unknown_codes() {
+    let empty = Program::empty().to_bytes();
+
+    let mut bytes = empty.iter().copied().chain([0xFF]).collect::<Vec<_>>();
+    assert_eq!(
+      Program::try_from_bytes(&mut bytes).unwrap_err(),
+      DecodeError::UnknownOpcode(0xFF)
+    );
+
+    let mut bytes = empty
+      .iter()
+      .copied()
+      .chain([0x13, 0x0B])
+      .collect::<Vec<_>>();
+    assert_eq!(
+      Program::try_from_bytes(&mut bytes).unwrap_err(),
+      DecodeError::UnknownType(0x0B)
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_constants() {
+    let mut program = Program::empty();
+    program.constants = vec![Value::U16(1)];
+
+    let mut bytes = program.to_bytes();
+    // Type code of the first constant: u16 becomes u32, which needs 4 bytes.
+    let type_at = MAGIC.len() + 1 + 4 + 4;
+    bytes[type_at] = Type::U32.to_bytes()[0];
+
+    assert_eq!(
+      Program::try_from_bytes(&mut bytes).unwrap_err(),
+      DecodeError::InvalidValue(Type::U32)
+    );
+  }
 }
@@ -0,0 +1,642 @@
+use std::fmt::Display;
+
+use crate::{opcodes::OpCode, Program, Type};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+  StackUnderflow,
+  TypeMismatch {
+    expected: Type,
+    found: Type,
+  },
+  /// Two paths reach the same instruction with a different number of values on the stack.
+  InconsistentDepth {
+    expected: usize,
+    found: usize,
+  },
+  /// Two paths reach the same instruction with values of different types at the same depth.
+  InconsistentType {
+    expected: Type,
+    found: Type,
+  },
+  JumpOutOfRange(usize),
+  UnknownImport(usize),
+  UnknownExtern(String),
+  UnknownConstant(usize),
+  UnknownGlobal(usize),
+}
+
+impl VerifyErrorKind {
+  pub fn code(&self) -> &'static str {
+    match self {
+      VerifyErrorKind::StackUnderflow => "StackUnderflow",
+      VerifyErrorKind::TypeMismatch { .. } => "TypeMismatch",
+      VerifyErrorKind::InconsistentDepth { .. } => "InconsistentDepth",
+      VerifyErrorKind::InconsistentType { .. } => "InconsistentType",
+      VerifyErrorKind::JumpOutOfRange(_) => "JumpOutOfRange",
+      VerifyErrorKind::UnknownImport(_) => "UnknownImport",
+      VerifyErrorKind::UnknownExtern(_) => "UnknownExtern",
+      VerifyErrorKind::UnknownConstant(_) => "UnknownConstant",
+      VerifyErrorKind::UnknownGlobal(_) => "UnknownGlobal",
+    }
+  }
+}
+
+impl Display for VerifyErrorKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VerifyErrorKind::StackUnderflow => write!(f, "Stack underflow"),
+      VerifyErrorKind::TypeMismatch { expected, found } => {
+        write!(f, "Expected {expected} on the stack, found {found}")
+      }
+      VerifyErrorKind::InconsistentDepth { expected, found } => write!(
+        f,
+        "Stack depth is {expected} on one path and {found} on another"
+      ),
+      VerifyErrorKind::InconsistentType { expected, found } => write!(
+        f,
+        "Stack holds {expected} on one path and {found} on another"
+      ),
+      VerifyErrorKind::JumpOutOfRange(target) => write!(f, "Jump target {target} out of range"),
+      VerifyErrorKind::UnknownImport(import) => write!(f, "Import {import} does not exist"),
+      VerifyErrorKind::UnknownExtern(name) => write!(f, "Extern {name:?} is not registered"),
+      VerifyErrorKind::UnknownConstant(index) => write!(f, "Constant {index} does not exist"),
+      VerifyErrorKind::UnknownGlobal(index) => write!(f, "Global {index} does not exist"),
+    }
+  }
+}
+
+/// A problem found by [`verify`] at the instruction `pc`.
+#[derive(Debug, Clone)]
+pub struct VerifyError {
+  pub kind: VerifyErrorKind,
+  pub pc: usize,
+  pub opcode: OpCode,
+}
+
+impl Display for VerifyError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "VerifyError [{}]: {} (PC={}, {:?})",
+      self.kind.code(),
+      self.kind,
+      self.pc,
+      self.opcode
+    )
+  }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// A stack entry as seen by the verifier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+  Known(Type),
+  /// A value whose type depends on the execution, e.g. the value caught by a `TRY` handler.
+  Unknown,
+}
+
+/// Simulated stack before an instruction.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+  slots: Vec<Slot>,
+  /// The stack may hold untracked values below `slots`, e.g. inside a function or after an
+  /// extern call. Popping past `slots` is then allowed.
+  open: bool,
+}
+
+impl State {
+  fn closed() -> Self {
+    Self {
+      slots: Vec::new(),
+      open: false,
+    }
+  }
+
+  fn open() -> Self {
+    Self {
+      slots: Vec::new(),
+      open: true,
+    }
+  }
+
+  fn push(&mut self, item_type: Type) {
+    self.slots.push(Slot::Known(item_type));
+  }
+
+  fn pop(&mut self, expected: Type) -> Result<(), VerifyErrorKind> {
+    match self.slots.pop() {
+      Some(Slot::Known(found)) if found != expected => {
+        Err(VerifyErrorKind::TypeMismatch { expected, found })
+      }
+      Some(_) => Ok(()),
+      None if self.open => Ok(()),
+      None => Err(VerifyErrorKind::StackUnderflow),
+    }
+  }
+
+  fn binary(&mut self, operand: Type, result: Type) -> Result<(), VerifyErrorKind> {
+    self.pop(operand)?;
+    self.pop(operand)?;
+    self.push(result);
+
+    Ok(())
+  }
+
+  fn unary(&mut self, item_type: Type) -> Result<(), VerifyErrorKind> {
+    self.pop(item_type)?;
+    self.push(item_type);
+
+    Ok(())
+  }
+
+  /// Joins `other`, the stack of another path reaching the same instruction, into this one.
+  /// Returns whether this state changed, in which case the instruction must be checked again.
+  ///
+  /// Values of different types at the same depth are an error, while a value only known on one
+  /// path becomes unknown. Stacks with untracked values below them are not compared with
+  /// closed ones.
+  fn merge(&mut self, other: &State) -> Result<bool, VerifyErrorKind> {
+    if self.open != other.open {
+      return Ok(false);
+    }
+
+    if self.slots.len() != other.slots.len() {
+      return Err(VerifyErrorKind::InconsistentDepth {
+        expected: self.slots.len(),
+        found: other.slots.len(),
+      });
+    }
+
+    let mut changed = false;
+
+    for (slot, other) in self.slots.iter_mut().zip(&other.slots) {
+      match (*slot, *other) {
+        (Slot::Known(expected), Slot::Known(found)) if expected != found => {
+          return Err(VerifyErrorKind::InconsistentType { expected, found });
+        }
+        (Slot::Known(_), Slot::Unknown) => {
+          *slot = Slot::Unknown;
+          changed = true;
+        }
+        _ => {}
+      }
+    }
+
+    return Ok(changed);
+  }
+}
+
+/// Checks `program` before it runs by following every path from PC 0 and simulating the
+/// stack with the effect of each instruction (the `Pilha` column of `opcodes2.csv`).
+///
+/// Reports stack underflows, pops of the wrong type, different stack depths or types where paths
+/// meet, jump targets past the end of the program, imports missing from `externs` and constants
+/// or globals that do not exist or whose type differs from the instruction. The stack inside
+/// functions and after externs, `MSP` or `RESUME` is only partly known, so underflows below
+/// the known values are not reported, and code only reachable through `GOTO` is not verified.
+pub fn verify(program: &Program, externs: &[&str]) -> Result<(), Vec<VerifyError>> {
+  let opcodes = &program.opcodes;
+  let mut states: Vec<Option<State>> = vec![None; opcodes.len()];
+  let mut pending = Vec::new();
+  let mut errors = Vec::new();
+
+  let mut error = |kind: VerifyErrorKind, pc: usize| {
+    errors.push(VerifyError {
+      kind,
+      pc,
+      opcode: opcodes[pc].clone(),
+    });
+  };
+
+  for (import, name) in program.imports.iter().enumerate() {
+    if !externs.contains(&name.as_str()) {
+      let pc = opcodes
+        .iter()
+        .position(|op| matches!(op, OpCode::External(i) if *i == import));
+
+      if let Some(pc) = pc {
+        error(VerifyErrorKind::UnknownExtern(name.clone()), pc);
+      }
+    }
+  }
+
+  if !opcodes.is_empty() {
+    states[0] = Some(State::closed());
+    pending.push(0);
+  }
+
+  // An instruction is checked again when its state widens, so only the errors of its last
+  // check are kept, and only the first conflict where paths meet.
+  let mut failures: Vec<Option<VerifyErrorKind>> = vec![None; opcodes.len()];
+  let mut conflicts: Vec<Option<VerifyErrorKind>> = vec![None; opcodes.len()];
+
+  while let Some(pc) = pending.pop() {
+    let mut state = states[pc].clone().unwrap();
+    let op = &opcodes[pc];
+
+    failures[pc] = None;
+
+    let successors = match step(program, op, pc, &mut state) {
+      Ok(successors) => successors,
+      Err(kind) => {
+        failures[pc] = Some(kind);
+        continue;
+      }
+    };
+
+    for (target, state) in successors {
+      // Jumping to the end of the program stops it like falling off the last instruction.
+      if target == opcodes.len() {
+        continue;
+      }
+
+      if target > opcodes.len() {
+        failures[pc] = Some(VerifyErrorKind::JumpOutOfRange(target));
+        continue;
+      }
+
+      match &mut states[target] {
+        None => {
+          states[target] = Some(state);
+          pending.push(target);
+        }
+        Some(seen) => match seen.merge(&state) {
+          Ok(true) => pending.push(target),
+          Ok(false) => {}
+          Err(kind) => {
+            conflicts[target].get_or_insert(kind);
+          }
+        },
+      }
+    }
+  }
+
+  for (pc, kind) in failures.into_iter().enumerate() {
+    if let Some(kind) = kind {
+      error(kind, pc);
+    }
+  }
+
+  for (pc, kind) in conflicts.into_iter().enumerate() {
+    if let Some(kind) = kind {
+      error(kind, pc);
+    }
+  }
+
+  if errors.is_empty() {
+    return Ok(());
+  }
+
+  errors.sort_by_key(|error| error.pc);
+
+  return Err(errors);
+}
+
+/// Checks that the global at `index` exists and has the type `expected`.
+fn global(program: &Program, index: usize, expected: Type) -> Result<(), VerifyErrorKind> {
+  let found = program
+    .globals
+    .get(index)
+    .ok_or(VerifyErrorKind::UnknownGlobal(index))?
+    .data_type();
+
+  if found != expected {
+    return Err(VerifyErrorKind::TypeMismatch { expected, found });
+  }
+
+  Ok(())
+}
+
+/// Type of the result of `NEG`, which turns unsigned values into the signed type of the same
+/// size.
+fn signed(item_type: Type) -> Type {
+  match item_type {
+    Type::U8 => Type::I8,
+    Type::U16 => Type::I16,
+    Type::U32 => Type::I32,
+    Type::U64 => Type::I64,
+    Type::Usize => Type::Isize,
+    t => t,
+  }
+}
+
+/// Applies `op` to `state` and returns the instructions that can run next with their stacks.
+fn step(
+  program: &Program,
+  op: &OpCode,
+  pc: usize,
+  state: &mut State,
+) -> Result<Vec<(usize, State)>, VerifyErrorKind> {
+  let next = pc + 1;
+
+  match op {
+    OpCode::NoOperation | OpCode::Move(_, _) | OpCode::RegisterOp(..) | OpCode::EndTry => {}
+    OpCode::Halt | OpCode::Return | OpCode::Goto => return Ok(vec![]),
+    OpCode::Register(_, t) | OpCode::Receive(t) | OpCode::LocalGet(t, _) => state.push(*t),
+    OpCode::ProgramCounter | OpCode::StackPointer | OpCode::FramePointer | OpCode::ProcessId => {
+      state.push(Type::Usize)
+    }
+    OpCode::MoveStackPointer(_) | OpCode::Resume => *state = State::open(),
+    OpCode::Push(value) => state.push(value.data_type()),
+    OpCode::PushAll(values) => values.iter().for_each(|v| state.push(v.data_type())),
+    OpCode::PushBytes(_) => *state = State::open(),
+    OpCode::Pop(t, _) | OpCode::Yield(t) | OpCode::LocalSet(t, _) => state.pop(*t)?,
+    OpCode::Copy(t) => {
+      state.pop(*t)?;
+      state.push(*t);
+      state.push(*t);
+    }
+    OpCode::Increment(t, _) | OpCode::Decrement(t, _) | OpCode::Not(t) => state.unary(*t)?,
+    OpCode::Negative(t, _) => {
+      state.pop(*t)?;
+      state.push(signed(*t));
+    }
+    OpCode::Add(t, _)
+    | OpCode::Subtraction(t, _)
+    | OpCode::Multiply(t, _)
+    | OpCode::Divide(t, _)
+    | OpCode::Modulo(t, _)
+    | OpCode::Power(t, _)
+    | OpCode::And(t)
+    | OpCode::Or(t)
+    | OpCode::XOr(t)
+    | OpCode::ShiftLeft(t, _)
+    | OpCode::ShiftRight(t, _) => state.binary(*t, *t)?,
+    OpCode::Equals(t)
+    | OpCode::NotEquals(t)
+    | OpCode::GreaterThan(t)
+    | OpCode::GreaterThanOrEqual(t)
+    | OpCode::LessThan(t)
+    | OpCode::LessThanOrEqual(t) => state.binary(*t, Type::Bool)?,
+    OpCode::Jump(addr) => return Ok(vec![(*addr, state.clone())]),
+    OpCode::JumpIfZero(addr) | OpCode::JumpIfNotZero(addr) => {
+      state.pop(Type::Bool)?;
+
+      return Ok(vec![(*addr, state.clone()), (next, state.clone())]);
+    }
+    OpCode::GotoIfZero | OpCode::GotoIfNotZero => state.pop(Type::Bool)?,
+    OpCode::External(import) => {
+      if *import >= program.imports.len() {
+        return Err(VerifyErrorKind::UnknownImport(*import));
+      }
+
+      *state = State::open();
+    }
+    // `RET` restores the stack pointer saved by `CALL`, so the caller's stack is unchanged.
+    OpCode::Call(addr) => return Ok(vec![(*addr, State::open()), (next, state.clone())]),
+    OpCode::Alloc => state.unary(Type::Usize)?,
+    OpCode::Free => state.pop(Type::Usize)?,
+    OpCode::Load(t) => {
+      state.pop(Type::Usize)?;
+      state.push(*t);
+    }
+    OpCode::Store(t) => {
+      state.pop(*t)?;
+      state.pop(Type::Usize)?;
+    }
+    OpCode::Spawn(addr) => {
+      state.push(Type::Usize);
+
+      return Ok(vec![(*addr, State::closed()), (next, state.clone())]);
+    }
+    OpCode::Send(t) => {
+      state.pop(*t)?;
+      state.pop(Type::Usize)?;
+    }
+    OpCode::Try(addr) => {
+      let mut handler = state.clone();
+      handler.slots.push(Slot::Unknown);
+      handler.push(Type::U8);
+
+      return Ok(vec![(*addr, handler), (next, state.clone())]);
+    }
+    OpCode::Cast(from, to, _) => {
+      state.pop(*from)?;
+      state.push(*to);
+    }
+    OpCode::Throw(t) => {
+      state.pop(*t)?;
+
+      return Ok(vec![]);
+    }
+    OpCode::Concat => state.binary(Type::String, Type::String)?,
+    OpCode::Length | OpCode::CharCount => {
+      state.pop(Type::String)?;
+      state.push(Type::Usize);
+    }
+    OpCode::Slice => {
+      state.pop(Type::Usize)?;
+      state.pop(Type::Usize)?;
+      state.unary(Type::String)?;
+    }
+    OpCode::CharCode => {
+      state.pop(Type::Usize)?;
+      state.pop(Type::String)?;
+      state.push(Type::U32);
+    }
+    OpCode::FromCharCode => {
+      state.pop(Type::U32)?;
+      state.push(Type::String);
+    }
+    OpCode::PushConstant(index) => {
+      let constant = program
+        .constants
+        .get(*index)
+        .ok_or(VerifyErrorKind::UnknownConstant(*index))?;
+
+      state.push(constant.data_type());
+    }
+    OpCode::GlobalGet(t, index) => {
+      global(program, *index, *t)?;
+      state.push(*t);
+    }
+    OpCode::GlobalSet(t, index) => {
+      global(program, *index, *t)?;
+      state.pop(*t)?;
+    }
+  }
+
+  return Ok(vec![(next, state.clone())]);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{opcodes::Overflow, Value};
+
+  fn check(opcodes: Vec<OpCode>) -> Result<(), Vec<VerifyErrorKind>> {
+    let program = Program {
+      opcodes,
+      globals: vec![Value::U64(0)],
+      ..Program::empty()
+    };
+
+    return verify(&program, &[]).map_err(|errors| errors.into_iter().map(|e| e.kind).collect());
+  }
+
+  #[test]
+  fn accepts_a_loop_with_a_stable_stack() {
+    let result = check(vec![
+      OpCode::Push(Value::U8(3)),
+      OpCode::Decrement(Type::U8, Overflow::Checked),
+      OpCode::Copy(Type::U8),
+      OpCode::Push(Value::U8(0)),
+      OpCode::Equals(Type::U8),
+      OpCode::JumpIfZero(1),
+      OpCode::Pop(Type::U8, None),
+      OpCode::Halt,
+    ]);
+
+    assert_eq!(result, Ok(()));
+  }
+
+  #[test]
+  fn rejects_underflows_and_wrong_types() {
+    assert_eq!(
+      check(vec![OpCode::Pop(Type::U8, None)]),
+      Err(vec![VerifyErrorKind::StackUnderflow])
+    );
+    assert_eq!(
+      check(vec![
+        OpCode::Push(Value::U8(1)),
+        OpCode::Pop(Type::U64, None)
+      ]),
+      Err(vec![VerifyErrorKind::TypeMismatch {
+        expected: Type::U64,
+        found: Type::U8,
+      }])
+    );
+  }
+
+  #[test]
+  fn shifts_take_the_amount_with_the_value_type() {
+    let shift = |amount: Value| {
+      return check(vec![
+        OpCode::Push(Value::U16(1)),
+        OpCode::Push(amount),
+        OpCode::ShiftLeft(Type::U16, Overflow::Checked),
+        OpCode::Pop(Type::U16, None),
+        OpCode::Halt,
+      ]);
+    };
+
+    assert_eq!(shift(Value::U16(9)), Ok(()));
+    assert_eq!(
+      shift(Value::U8(9)),
+      Err(vec![VerifyErrorKind::TypeMismatch {
+        expected: Type::U16,
+        found: Type::U8,
+      }])
+    );
+  }
+
+  #[test]
+  fn rejects_paths_meeting_with_different_depths() {
+    let result = check(vec![
+      OpCode::Push(Value::Bool(true)),
+      OpCode::JumpIfZero(3),
+      OpCode::Push(Value::U8(1)),
+      OpCode::Halt,
+    ]);
+
+    assert_eq!(
+      result,
+      Err(vec![VerifyErrorKind::InconsistentDepth {
+        expected: 0,
+        found: 1,
+      }])
+    );
+  }
+
+  #[test]
+  fn rejects_paths_meeting_with_different_types() {
+    let result = check(vec![
+      OpCode::Push(Value::Bool(true)),
+      OpCode::JumpIfZero(4),
+      OpCode::Push(Value::U8(1)),
+      OpCode::Jump(5),
+      OpCode::Push(Value::U64(1)),
+      OpCode::Halt,
+    ]);
+
+    assert_eq!(
+      result,
+      Err(vec![VerifyErrorKind::InconsistentType {
+        expected: Type::U8,
+        found: Type::U64,
+      }])
+    );
+  }
+
+  #[test]
+  fn widens_a_value_known_on_one_path_only() {
+    // The handler of `TRY` receives a thrown value of unknown type, which meets a `u8` at PC 4.
+    let result = check(vec![
+      OpCode::Try(3),
+      OpCode::Push(Value::U8(1)),
+      OpCode::Jump(4),
+      OpCode::Pop(Type::U8, None),
+      OpCode::Pop(Type::U64, None),
+    ]);
+
+    assert_eq!(result, Ok(()));
+  }
+
+  #[test]
+  fn negating_an_unsigned_value_gives_the_signed_type() {
+    let result = check(vec![
+      OpCode::Push(Value::U32(1)),
+      OpCode::Negative(Type::U32, Overflow::Checked),
+      OpCode::Pop(Type::I32, None),
+    ]);
+
+    assert_eq!(result, Ok(()));
+
+    let result = check(vec![
+      OpCode::Push(Value::U32(1)),
+      OpCode::Negative(Type::U32, Overflow::Checked),
+      OpCode::Pop(Type::U32, None),
+    ]);
+
+    assert_eq!(
+      result,
+      Err(vec![VerifyErrorKind::TypeMismatch {
+        expected: Type::U32,
+        found: Type::I32,
+      }])
+    );
+  }
+
+  #[test]
+  fn rejects_jumps_out_of_range_and_unknown_globals() {
+    assert_eq!(
+      check(vec![OpCode::Jump(5)]),
+      Err(vec![VerifyErrorKind::JumpOutOfRange(5)])
+    );
+    assert_eq!(
+      check(vec![OpCode::GlobalGet(Type::U64, 1)]),
+      Err(vec![VerifyErrorKind::UnknownGlobal(1)])
+    );
+    assert_eq!(
+      check(vec![OpCode::GlobalGet(Type::U8, 0)]),
+      Err(vec![VerifyErrorKind::TypeMismatch {
+        expected: Type::U8,
+        found: Type::U64,
+      }])
+    );
+  }
+
+  #[test]
+  fn call_bodies_start_from_an_unknown_stack() {
+    let result = check(vec![
+      OpCode::Call(2),
+      OpCode::Halt,
+      OpCode::Pop(Type::U64, None),
+      OpCode::Return,
+    ]);
+
+    assert_eq!(result, Ok(()));
+  }
+}
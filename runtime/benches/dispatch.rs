@@ -0,0 +1,77 @@
+//! Measures instruction dispatch on the programs in `examples/bench`.
+//!
+//! Run with `cargo bench -p svm_runtime`.
+
+use std::{
+  fs::File,
+  time::{Duration, Instant},
+};
+
+use svm_asm::compile;
+use svm_lang::{Program, Type, Value};
+use svm_runtime::{StopReason, VmErrorKind, VM};
+
+const PROGRAMS: [&str; 3] = ["factorial", "strings", "externs"];
+const RUNS: usize = 10;
+
+fn load(name: &str) -> Program {
+  let path = format!(
+    "{}/../examples/bench/{}.svm",
+    env!("CARGO_MANIFEST_DIR"),
+    name
+  );
+
+  return compile(File::open(&path).unwrap_or_else(|_| panic!("Could not open {}", path)));
+}
+
+fn new_vm(program: Program) -> VM {
+  let mut vm = VM::new();
+
+  vm.register_extern(
+    "bench.add",
+    |vm: &mut VM| -> Result<Option<Value>, VmErrorKind> {
+      let b = vm.stack.pop_value(&Type::U64)?;
+      let a = vm.stack.pop_value(&Type::U64)?;
+
+      match (a, b) {
+        (Value::U64(a), Value::U64(b)) => Ok(Some(Value::U64(a + b))),
+        _ => unreachable!(),
+      }
+    },
+  )
+  .unwrap();
+
+  vm.load(program).unwrap();
+
+  return vm;
+}
+
+fn main() {
+  for name in PROGRAMS {
+    let mut vm = new_vm(load(name));
+    let mut instructions = 0u64;
+
+    while vm.step().is_none() {
+      instructions += 1;
+    }
+
+    let mut best = Duration::MAX;
+
+    for _ in 0..RUNS {
+      let mut vm = new_vm(load(name));
+      let started = Instant::now();
+      let result = vm.run();
+
+      best = best.min(started.elapsed());
+      assert!(matches!(result, Ok(StopReason::Halted)), "{:?}", result);
+    }
+
+    println!(
+      "{:<10} {:>9} instructions {:>9.2?} {:>7.2} ns/instruction",
+      name,
+      instructions,
+      best,
+      best.as_nanos() as f64 / instructions as f64
+    );
+  }
+}
@@ -0,0 +1,847 @@
+use svm_lang::{
+  opcodes::{BinaryOp, Overflow},
+  Value,
+};
+
+use super::VmErrorKind;
+
+/// Picks the checked, wrapping or saturating form of an integer operation. The result is
+/// `None` when a checked operation overflows.
+macro_rules! integer {
+  ($overflow:expr, $checked:expr, $wrapping:expr, $saturating:expr) => {
+    match $overflow {
+      Overflow::Checked => $checked,
+      Overflow::Wrapping => Some($wrapping),
+      Overflow::Saturating => Some($saturating),
+    }
+  };
+}
+
+/// Applies an integer method such as `checked_add` to two values of the same type, or the
+/// operator `$float` to floats.
+macro_rules! binary_integer {
+  (
+    $a:ident,
+    $b:ident,
+    $overflow:ident,
+    $checked:ident,
+    $wrapping:ident,
+    $saturating:ident,
+    $float:tt,
+    $verb:literal
+  ) => {{
+    let item_type = $a.data_type();
+
+    let result = match ($a, $b) {
+      (Value::U8(a), Value::U8(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::U8)
+      }
+      (Value::U16(a), Value::U16(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::U16)
+      }
+      (Value::U32(a), Value::U32(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::U32)
+      }
+      (Value::U64(a), Value::U64(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::U64)
+      }
+      (Value::I8(a), Value::I8(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::I8)
+      }
+      (Value::I16(a), Value::I16(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::I16)
+      }
+      (Value::I32(a), Value::I32(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::I32)
+      }
+      (Value::I64(a), Value::I64(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::I64)
+      }
+      (Value::Usize(a), Value::Usize(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::Usize)
+      }
+      (Value::Isize(a), Value::Isize(b)) => {
+        integer!($overflow, a.$checked(b), a.$wrapping(b), a.$saturating(b)).map(Value::Isize)
+      }
+      (Value::F32(a), Value::F32(b)) => Some(Value::F32(a $float b)),
+      (Value::F64(a), Value::F64(b)) => Some(Value::F64(a $float b)),
+      (a, b) => {
+        return Err(VmErrorKind::InvalidType(format!(
+          "Cannot {} {:?} and {:?}",
+          $verb, a, b
+        )));
+      }
+    };
+
+    result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+  }};
+}
+
+/// Adds or subtracts 1 with an integer method such as `checked_add`.
+macro_rules! step {
+  (
+    $value:ident,
+    $overflow:ident,
+    $checked:ident,
+    $wrapping:ident,
+    $saturating:ident,
+    $float:tt,
+    $verb:literal
+  ) => {{
+    let item_type = $value.data_type();
+
+    let result = match $value {
+      Value::U8(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::U8)
+      }
+      Value::U16(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::U16)
+      }
+      Value::U32(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::U32)
+      }
+      Value::U64(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::U64)
+      }
+      Value::I8(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::I8)
+      }
+      Value::I16(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::I16)
+      }
+      Value::I32(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::I32)
+      }
+      Value::I64(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::I64)
+      }
+      Value::Usize(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::Usize)
+      }
+      Value::Isize(v) => {
+        integer!($overflow, v.$checked(1), v.$wrapping(1), v.$saturating(1)).map(Value::Isize)
+      }
+      Value::F32(v) => Some(Value::F32(v $float 1.0)),
+      Value::F64(v) => Some(Value::F64(v $float 1.0)),
+      _ => {
+        return Err(VmErrorKind::InvalidType(format!(
+          "Cannot {} non-number value",
+          $verb
+        )));
+      }
+    };
+
+    result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+  }};
+}
+
+/// Negates `$value` into the signed type `$target`, going through `i128` so that unsigned
+/// values and the minimum of signed types are handled like any other value.
+macro_rules! negate {
+  ($overflow:ident, $value:expr, $target:ty) => {{
+    let value = -($value as i128);
+
+    match $overflow {
+      Overflow::Checked => <$target>::try_from(value).ok(),
+      Overflow::Wrapping => Some(value as $target),
+      Overflow::Saturating => {
+        Some(value.clamp(<$target>::MIN as i128, <$target>::MAX as i128) as $target)
+      }
+    }
+  }};
+}
+
+/// Raises an integer to a non-negative exponent that fits in a `u32`.
+macro_rules! pow {
+  ($overflow:ident, $a:ident, $b:ident) => {{
+    let exponent = u32::try_from($b)
+      .map_err(|_| VmErrorKind::InvalidType(format!("Exponent {} out of range", $b)))?;
+
+    integer!(
+      $overflow,
+      $a.checked_pow(exponent),
+      $a.wrapping_pow(exponent),
+      $a.saturating_pow(exponent)
+    )
+  }};
+}
+
+/// Shifts an integer by `$b` bits. Shifting by the width of the type or more, or by a negative
+/// amount, fails when checked, shifts by the amount modulo the width when wrapping and gives
+/// `$saturated`, the value with every bit shifted out, when saturating.
+macro_rules! shift {
+  ($overflow:ident, $a:ident, $b:ident, $checked:ident, $wrapping:ident, $saturated:expr) => {{
+    let amount = u32::try_from($b).unwrap_or(u32::MAX);
+
+    match $overflow {
+      Overflow::Checked => $a.$checked(amount),
+      Overflow::Wrapping => Some($a.$wrapping(amount)),
+      Overflow::Saturating => Some($a.$checked(amount).unwrap_or($saturated)),
+    }
+  }};
+}
+
+/// Applies `op` to two values of the same type, `a` being the one pushed first.
+///
+/// Inlined so that `op` is known at each call site in the dispatch loop, which lets the
+/// compiler drop this match for the stack forms of the operations.
+#[inline]
+pub(crate) fn binary(
+  op: BinaryOp,
+  overflow: Overflow,
+  a: Value,
+  b: Value,
+) -> Result<Value, VmErrorKind> {
+  match op {
+    BinaryOp::Add => add(a, b, overflow),
+    BinaryOp::Subtraction => subtract(a, b, overflow),
+    BinaryOp::Multiply => multiply(a, b, overflow),
+    BinaryOp::Divide => divide(a, b, overflow),
+    BinaryOp::Modulo => modulo(a, b, overflow),
+    BinaryOp::Power => power(a, b, overflow),
+    BinaryOp::Equals => equals(a, b),
+    BinaryOp::NotEquals => not_equals(a, b),
+    BinaryOp::GreaterThan => greater_than(a, b),
+    BinaryOp::GreaterThanOrEqual => greater_than_or_equal(a, b),
+    BinaryOp::LessThan => less_than(a, b),
+    BinaryOp::LessThanOrEqual => less_than_or_equal(a, b),
+  }
+}
+
+pub(crate) fn increment(value: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  step!(
+    value,
+    overflow,
+    checked_add,
+    wrapping_add,
+    saturating_add,
+    +,
+    "increment"
+  )
+}
+
+pub(crate) fn decrement(value: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  step!(
+    value,
+    overflow,
+    checked_sub,
+    wrapping_sub,
+    saturating_sub,
+    -,
+    "decrement"
+  )
+}
+
+/// Negates `value`. Unsigned values become the signed type of the same size.
+pub(crate) fn negative(value: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  let item_type = value.data_type();
+
+  let result = match value {
+    Value::U8(value) => negate!(overflow, value, i8).map(Value::I8),
+    Value::U16(value) => negate!(overflow, value, i16).map(Value::I16),
+    Value::U32(value) => negate!(overflow, value, i32).map(Value::I32),
+    Value::U64(value) => negate!(overflow, value, i64).map(Value::I64),
+    Value::I8(value) => negate!(overflow, value, i8).map(Value::I8),
+    Value::I16(value) => negate!(overflow, value, i16).map(Value::I16),
+    Value::I32(value) => negate!(overflow, value, i32).map(Value::I32),
+    Value::I64(value) => negate!(overflow, value, i64).map(Value::I64),
+    Value::Usize(value) => negate!(overflow, value, isize).map(Value::Isize),
+    Value::Isize(value) => negate!(overflow, value, isize).map(Value::Isize),
+    Value::F32(value) => Some(Value::F32(-value)),
+    Value::F64(value) => Some(Value::F64(-value)),
+    _ => {
+      return Err(VmErrorKind::InvalidType(String::from(
+        "Cannot negate non-integer value",
+      )));
+    }
+  };
+
+  result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+}
+
+fn add(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  binary_integer!(
+    a,
+    b,
+    overflow,
+    checked_add,
+    wrapping_add,
+    saturating_add,
+    +,
+    "add"
+  )
+}
+
+fn subtract(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  binary_integer!(
+    a,
+    b,
+    overflow,
+    checked_sub,
+    wrapping_sub,
+    saturating_sub,
+    -,
+    "subtract"
+  )
+}
+
+fn multiply(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  binary_integer!(
+    a,
+    b,
+    overflow,
+    checked_mul,
+    wrapping_mul,
+    saturating_mul,
+    *,
+    "multiply"
+  )
+}
+
+/// Only `MIN / -1` overflows.
+fn divide(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  if is_integer_zero(&b) {
+    return Err(VmErrorKind::DivisionByZero);
+  }
+
+  binary_integer!(
+    a,
+    b,
+    overflow,
+    checked_div,
+    wrapping_div,
+    saturating_div,
+    /,
+    "divide"
+  )
+}
+
+/// Only `MIN % -1` overflows, and its wrapped result 0 is also the exact one, so saturating
+/// uses the wrapping form.
+fn modulo(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  if is_integer_zero(&b) {
+    return Err(VmErrorKind::DivisionByZero);
+  }
+
+  binary_integer!(
+    a,
+    b,
+    overflow,
+    checked_rem,
+    wrapping_rem,
+    wrapping_rem,
+    %,
+    "modulo"
+  )
+}
+
+fn power(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  let item_type = a.data_type();
+
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => pow!(overflow, a, b).map(Value::U8),
+    (Value::U16(a), Value::U16(b)) => pow!(overflow, a, b).map(Value::U16),
+    (Value::U32(a), Value::U32(b)) => pow!(overflow, a, b).map(Value::U32),
+    (Value::U64(a), Value::U64(b)) => pow!(overflow, a, b).map(Value::U64),
+    (Value::I8(a), Value::I8(b)) => pow!(overflow, a, b).map(Value::I8),
+    (Value::I16(a), Value::I16(b)) => pow!(overflow, a, b).map(Value::I16),
+    (Value::I32(a), Value::I32(b)) => pow!(overflow, a, b).map(Value::I32),
+    (Value::I64(a), Value::I64(b)) => pow!(overflow, a, b).map(Value::I64),
+    (Value::Usize(a), Value::Usize(b)) => pow!(overflow, a, b).map(Value::Usize),
+    (Value::Isize(a), Value::Isize(b)) => pow!(overflow, a, b).map(Value::Isize),
+    (Value::F32(a), Value::F32(b)) => Some(Value::F32(a.powf(b))),
+    (Value::F64(a), Value::F64(b)) => Some(Value::F64(a.powf(b))),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot pow {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+}
+
+/// Shifts `a` left by `b` bits, filling with zeros.
+pub(crate) fn shift_left(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  let item_type = a.data_type();
+
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::U8)
+    }
+    (Value::U16(a), Value::U16(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::U16)
+    }
+    (Value::U32(a), Value::U32(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::U32)
+    }
+    (Value::U64(a), Value::U64(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::U64)
+    }
+    (Value::I8(a), Value::I8(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::I8)
+    }
+    (Value::I16(a), Value::I16(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::I16)
+    }
+    (Value::I32(a), Value::I32(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::I32)
+    }
+    (Value::I64(a), Value::I64(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::I64)
+    }
+    (Value::Usize(a), Value::Usize(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::Usize)
+    }
+    (Value::Isize(a), Value::Isize(b)) => {
+      shift!(overflow, a, b, checked_shl, wrapping_shl, 0).map(Value::Isize)
+    }
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot shl {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+}
+
+/// Shifts `a` right by `b` bits, filling signed values with their sign bit.
+pub(crate) fn shift_right(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  let item_type = a.data_type();
+
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => {
+      shift!(overflow, a, b, checked_shr, wrapping_shr, 0).map(Value::U8)
+    }
+    (Value::U16(a), Value::U16(b)) => {
+      shift!(overflow, a, b, checked_shr, wrapping_shr, 0).map(Value::U16)
+    }
+    (Value::U32(a), Value::U32(b)) => {
+      shift!(overflow, a, b, checked_shr, wrapping_shr, 0).map(Value::U32)
+    }
+    (Value::U64(a), Value::U64(b)) => {
+      shift!(overflow, a, b, checked_shr, wrapping_shr, 0).map(Value::U64)
+    }
+    (Value::I8(a), Value::I8(b)) => shift!(
+      overflow,
+      a,
+      b,
+      checked_shr,
+      wrapping_shr,
+      a >> (i8::BITS - 1)
+    )
+    .map(Value::I8),
+    (Value::I16(a), Value::I16(b)) => shift!(
+      overflow,
+      a,
+      b,
+      checked_shr,
+      wrapping_shr,
+      a >> (i16::BITS - 1)
+    )
+    .map(Value::I16),
+    (Value::I32(a), Value::I32(b)) => shift!(
+      overflow,
+      a,
+      b,
+      checked_shr,
+      wrapping_shr,
+      a >> (i32::BITS - 1)
+    )
+    .map(Value::I32),
+    (Value::I64(a), Value::I64(b)) => shift!(
+      overflow,
+      a,
+      b,
+      checked_shr,
+      wrapping_shr,
+      a >> (i64::BITS - 1)
+    )
+    .map(Value::I64),
+    (Value::Usize(a), Value::Usize(b)) => {
+      shift!(overflow, a, b, checked_shr, wrapping_shr, 0).map(Value::Usize)
+    }
+    (Value::Isize(a), Value::Isize(b)) => shift!(
+      overflow,
+      a,
+      b,
+      checked_shr,
+      wrapping_shr,
+      a >> (isize::BITS - 1)
+    )
+    .map(Value::Isize),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot shr {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  result.ok_or(VmErrorKind::IntegerOverflow(item_type))
+}
+
+fn equals(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => Value::Bool(a == b),
+    (Value::U16(a), Value::U16(b)) => Value::Bool(a == b),
+    (Value::U32(a), Value::U32(b)) => Value::Bool(a == b),
+    (Value::U64(a), Value::U64(b)) => Value::Bool(a == b),
+    (Value::I8(a), Value::I8(b)) => Value::Bool(a == b),
+    (Value::I16(a), Value::I16(b)) => Value::Bool(a == b),
+    (Value::I32(a), Value::I32(b)) => Value::Bool(a == b),
+    (Value::I64(a), Value::I64(b)) => Value::Bool(a == b),
+    (Value::F32(a), Value::F32(b)) => Value::Bool(a == b),
+    (Value::F64(a), Value::F64(b)) => Value::Bool(a == b),
+    (Value::Usize(a), Value::Usize(b)) => Value::Bool(a == b),
+    (Value::Isize(a), Value::Isize(b)) => Value::Bool(a == b),
+    (Value::Bool(a), Value::Bool(b)) => Value::Bool(a == b),
+    (Value::String(a), Value::String(b)) => Value::Bool(a == b),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot compare {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  Ok(result)
+}
+
+fn not_equals(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  Ok(Value::Bool(a != b))
+}
+
+fn greater_than(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => Value::Bool(a > b),
+    (Value::U16(a), Value::U16(b)) => Value::Bool(a > b),
+    (Value::U32(a), Value::U32(b)) => Value::Bool(a > b),
+    (Value::U64(a), Value::U64(b)) => Value::Bool(a > b),
+    (Value::I8(a), Value::I8(b)) => Value::Bool(a > b),
+    (Value::I16(a), Value::I16(b)) => Value::Bool(a > b),
+    (Value::I32(a), Value::I32(b)) => Value::Bool(a > b),
+    (Value::I64(a), Value::I64(b)) => Value::Bool(a > b),
+    (Value::F32(a), Value::F32(b)) => Value::Bool(a > b),
+    (Value::F64(a), Value::F64(b)) => Value::Bool(a > b),
+    (Value::Usize(a), Value::Usize(b)) => Value::Bool(a > b),
+    (Value::Isize(a), Value::Isize(b)) => Value::Bool(a > b),
+    (Value::Bool(a), Value::Bool(b)) => Value::Bool(a & !b),
+    (Value::String(a), Value::String(b)) => Value::Bool(a > b),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot compare {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  Ok(result)
+}
+
+fn greater_than_or_equal(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => Value::Bool(a >= b),
+    (Value::U16(a), Value::U16(b)) => Value::Bool(a >= b),
+    (Value::U32(a), Value::U32(b)) => Value::Bool(a >= b),
+    (Value::U64(a), Value::U64(b)) => Value::Bool(a >= b),
+    (Value::I8(a), Value::I8(b)) => Value::Bool(a >= b),
+    (Value::I16(a), Value::I16(b)) => Value::Bool(a >= b),
+    (Value::I32(a), Value::I32(b)) => Value::Bool(a >= b),
+    (Value::I64(a), Value::I64(b)) => Value::Bool(a >= b),
+    (Value::F32(a), Value::F32(b)) => Value::Bool(a >= b),
+    (Value::F64(a), Value::F64(b)) => Value::Bool(a >= b),
+    (Value::Usize(a), Value::Usize(b)) => Value::Bool(a >= b),
+    (Value::Isize(a), Value::Isize(b)) => Value::Bool(a >= b),
+    (Value::Bool(a), Value::Bool(b)) => Value::Bool(a >= b),
+    (Value::String(a), Value::String(b)) => Value::Bool(a >= b),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot compare {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  Ok(result)
+}
+
+fn less_than(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => Value::Bool(a < b),
+    (Value::U16(a), Value::U16(b)) => Value::Bool(a < b),
+    (Value::U32(a), Value::U32(b)) => Value::Bool(a < b),
+    (Value::U64(a), Value::U64(b)) => Value::Bool(a < b),
+    (Value::I8(a), Value::I8(b)) => Value::Bool(a < b),
+    (Value::I16(a), Value::I16(b)) => Value::Bool(a < b),
+    (Value::I32(a), Value::I32(b)) => Value::Bool(a < b),
+    (Value::I64(a), Value::I64(b)) => Value::Bool(a < b),
+    (Value::F32(a), Value::F32(b)) => Value::Bool(a < b),
+    (Value::F64(a), Value::F64(b)) => Value::Bool(a < b),
+    (Value::Usize(a), Value::Usize(b)) => Value::Bool(a < b),
+    (Value::Isize(a), Value::Isize(b)) => Value::Bool(a < b),
+    (Value::Bool(a), Value::Bool(b)) => Value::Bool(!a & b),
+    (Value::String(a), Value::String(b)) => Value::Bool(a < b),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot compare {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  Ok(result)
+}
+
+fn less_than_or_equal(a: Value, b: Value) -> Result<Value, VmErrorKind> {
+  let result = match (a, b) {
+    (Value::U8(a), Value::U8(b)) => Value::Bool(a <= b),
+    (Value::U16(a), Value::U16(b)) => Value::Bool(a <= b),
+    (Value::U32(a), Value::U32(b)) => Value::Bool(a <= b),
+    (Value::U64(a), Value::U64(b)) => Value::Bool(a <= b),
+    (Value::I8(a), Value::I8(b)) => Value::Bool(a <= b),
+    (Value::I16(a), Value::I16(b)) => Value::Bool(a <= b),
+    (Value::I32(a), Value::I32(b)) => Value::Bool(a <= b),
+    (Value::I64(a), Value::I64(b)) => Value::Bool(a <= b),
+    (Value::F32(a), Value::F32(b)) => Value::Bool(a <= b),
+    (Value::F64(a), Value::F64(b)) => Value::Bool(a <= b),
+    (Value::Usize(a), Value::Usize(b)) => Value::Bool(a <= b),
+    (Value::Isize(a), Value::Isize(b)) => Value::Bool(a <= b),
+    (Value::Bool(a), Value::Bool(b)) => Value::Bool(a <= b),
+    (Value::String(a), Value::String(b)) => Value::Bool(a <= b),
+    (a, b) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot compare {:?} and {:?}",
+        a, b
+      )));
+    }
+  };
+
+  Ok(result)
+}
+
+fn is_integer_zero(value: &Value) -> bool {
+  matches!(
+    value,
+    Value::U8(0)
+      | Value::U16(0)
+      | Value::U32(0)
+      | Value::U64(0)
+      | Value::I8(0)
+      | Value::I16(0)
+      | Value::I32(0)
+      | Value::I64(0)
+      | Value::Usize(0)
+      | Value::Isize(0)
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::Type;
+
+  use super::*;
+
+  fn sum(a: Value, b: Value, overflow: Overflow) -> Result<Value, VmErrorKind> {
+    return binary(BinaryOp::Add, overflow, a, b);
+  }
+
+  #[test]
+  fn checked_arithmetic_fails_past_the_bounds() {
+    assert_eq!(
+      sum(Value::U8(254), Value::U8(1), Overflow::Checked),
+      Ok(Value::U8(255))
+    );
+    assert_eq!(
+      sum(Value::U8(255), Value::U8(1), Overflow::Checked),
+      Err(VmErrorKind::IntegerOverflow(Type::U8))
+    );
+    assert_eq!(
+      binary(
+        BinaryOp::Subtraction,
+        Overflow::Checked,
+        Value::I64(i64::MIN),
+        Value::I64(1)
+      ),
+      Err(VmErrorKind::IntegerOverflow(Type::I64))
+    );
+    assert_eq!(
+      binary(
+        BinaryOp::Divide,
+        Overflow::Checked,
+        Value::I32(i32::MIN),
+        Value::I32(-1)
+      ),
+      Err(VmErrorKind::IntegerOverflow(Type::I32))
+    );
+  }
+
+  #[test]
+  fn wrapping_arithmetic_wraps_around_the_bounds() {
+    assert_eq!(
+      sum(Value::U8(255), Value::U8(1), Overflow::Wrapping),
+      Ok(Value::U8(0))
+    );
+    assert_eq!(
+      sum(Value::I8(i8::MAX), Value::I8(1), Overflow::Wrapping),
+      Ok(Value::I8(i8::MIN))
+    );
+    assert_eq!(
+      binary(
+        BinaryOp::Multiply,
+        Overflow::Wrapping,
+        Value::U16(256),
+        Value::U16(256)
+      ),
+      Ok(Value::U16(0))
+    );
+    assert_eq!(
+      decrement(Value::Usize(0), Overflow::Wrapping),
+      Ok(Value::Usize(usize::MAX))
+    );
+  }
+
+  #[test]
+  fn saturating_arithmetic_clamps_to_the_bounds() {
+    assert_eq!(
+      sum(Value::U8(200), Value::U8(100), Overflow::Saturating),
+      Ok(Value::U8(255))
+    );
+    assert_eq!(
+      binary(
+        BinaryOp::Subtraction,
+        Overflow::Saturating,
+        Value::I16(i16::MIN),
+        Value::I16(1)
+      ),
+      Ok(Value::I16(i16::MIN))
+    );
+    assert_eq!(
+      binary(
+        BinaryOp::Power,
+        Overflow::Saturating,
+        Value::U32(2),
+        Value::U32(40)
+      ),
+      Ok(Value::U32(u32::MAX))
+    );
+    assert_eq!(
+      increment(Value::I64(i64::MAX), Overflow::Saturating),
+      Ok(Value::I64(i64::MAX))
+    );
+  }
+
+  #[test]
+  fn modulo_of_min_by_minus_one() {
+    let result = |overflow| {
+      binary(
+        BinaryOp::Modulo,
+        overflow,
+        Value::I8(i8::MIN),
+        Value::I8(-1),
+      )
+    };
+
+    assert_eq!(
+      result(Overflow::Checked),
+      Err(VmErrorKind::IntegerOverflow(Type::I8))
+    );
+    assert_eq!(result(Overflow::Wrapping), Ok(Value::I8(0)));
+    assert_eq!(result(Overflow::Saturating), Ok(Value::I8(0)));
+  }
+
+  #[test]
+  fn division_by_zero_fails_in_every_mode() {
+    for overflow in [Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+      assert_eq!(
+        binary(BinaryOp::Divide, overflow, Value::U8(1), Value::U8(0)),
+        Err(VmErrorKind::DivisionByZero)
+      );
+    }
+  }
+
+  #[test]
+  fn negating_unsigned_values_gives_the_signed_type() {
+    assert_eq!(
+      negative(Value::U8(128), Overflow::Checked),
+      Ok(Value::I8(-128))
+    );
+    assert_eq!(
+      negative(Value::U8(129), Overflow::Checked),
+      Err(VmErrorKind::IntegerOverflow(Type::U8))
+    );
+    assert_eq!(
+      negative(Value::U8(200), Overflow::Saturating),
+      Ok(Value::I8(i8::MIN))
+    );
+    assert_eq!(
+      negative(Value::I32(i32::MIN), Overflow::Wrapping),
+      Ok(Value::I32(i32::MIN))
+    );
+  }
+
+  #[test]
+  fn compares_isize_and_bool_values() {
+    let compare = |op, a, b| binary(op, Overflow::Checked, a, b);
+
+    assert_eq!(
+      compare(BinaryOp::LessThan, Value::Isize(-1), Value::Isize(0)),
+      Ok(Value::Bool(true))
+    );
+    assert_eq!(
+      compare(BinaryOp::Equals, Value::Isize(3), Value::Isize(3)),
+      Ok(Value::Bool(true))
+    );
+    assert_eq!(
+      compare(BinaryOp::GreaterThan, Value::Bool(true), Value::Bool(false)),
+      Ok(Value::Bool(true))
+    );
+    assert_eq!(
+      compare(
+        BinaryOp::LessThanOrEqual,
+        Value::Bool(true),
+        Value::Bool(false)
+      ),
+      Ok(Value::Bool(false))
+    );
+  }
+
+  #[test]
+  fn shifts_past_the_width_follow_the_overflow_mode() {
+    assert_eq!(
+      shift_left(Value::U8(1), Value::U8(7), Overflow::Checked),
+      Ok(Value::U8(128))
+    );
+    assert_eq!(
+      shift_left(Value::U8(1), Value::U8(9), Overflow::Checked),
+      Err(VmErrorKind::IntegerOverflow(Type::U8))
+    );
+    assert_eq!(
+      shift_left(Value::U8(1), Value::U8(9), Overflow::Wrapping),
+      Ok(Value::U8(2))
+    );
+    assert_eq!(
+      shift_left(Value::U16(1), Value::U16(16), Overflow::Saturating),
+      Ok(Value::U16(0))
+    );
+    assert_eq!(
+      shift_right(Value::I32(-8), Value::I32(1), Overflow::Checked),
+      Ok(Value::I32(-4))
+    );
+    assert_eq!(
+      shift_right(Value::I32(-8), Value::I32(-1), Overflow::Checked),
+      Err(VmErrorKind::IntegerOverflow(Type::I32))
+    );
+    assert_eq!(
+      shift_right(Value::I64(-8), Value::I64(64), Overflow::Saturating),
+      Ok(Value::I64(-1))
+    );
+    assert_eq!(
+      shift_right(Value::U64(u64::MAX), Value::U64(100), Overflow::Saturating),
+      Ok(Value::U64(0))
+    );
+  }
+}
@@ -0,0 +1,111 @@
+use std::sync::{
+  atomic::{AtomicBool, Ordering},
+  Arc,
+};
+
+/// Shared flag that stops a running VM from another thread.
+///
+/// The VM checks the flag before every instruction and stops with [`StopReason::Cancelled`],
+/// leaving PC and stack untouched. The flag stays set until [`CancelHandle::reset`] is called.
+///
+/// [`StopReason::Cancelled`]: crate::StopReason::Cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+
+  pub fn reset(&self) {
+    self.0.store(false, Ordering::Relaxed);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    thread,
+    time::{Duration, Instant},
+  };
+
+  use svm_lang::{opcodes::OpCode, Program, Value};
+
+  use crate::{StopReason, VM};
+
+  fn load(opcodes: Vec<OpCode>) -> VM {
+    let mut vm = VM::new();
+
+    vm.load(Program {
+      opcodes,
+      ..Program::empty()
+    })
+    .unwrap();
+
+    return vm;
+  }
+
+  #[test]
+  fn cancel_stops_before_the_next_instruction_until_reset() {
+    let mut vm = load(vec![OpCode::Push(Value::U8(1)), OpCode::Halt]);
+    let handle = vm.cancel_handle();
+
+    handle.cancel();
+    assert!(matches!(vm.run(), Ok(StopReason::Cancelled)));
+    assert!(matches!(vm.run(), Ok(StopReason::Cancelled)));
+    assert_eq!(vm.pc, 0);
+
+    handle.reset();
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+  }
+
+  #[test]
+  fn cancel_from_another_thread_stops_a_loop() {
+    let mut vm = load(vec![OpCode::Jump(0)]);
+    let handle = vm.cancel_handle();
+
+    let canceller = thread::spawn(move || {
+      thread::sleep(Duration::from_millis(10));
+      handle.cancel();
+    });
+
+    assert!(matches!(vm.run(), Ok(StopReason::Cancelled)));
+    canceller.join().unwrap();
+  }
+
+  #[test]
+  fn timeout_stops_at_the_first_step_past_the_deadline() {
+    let mut vm = load(vec![OpCode::Jump(0)]);
+
+    vm.set_deadline(Some(Instant::now()));
+    assert!(matches!(vm.run(), Ok(StopReason::TimedOut)));
+    assert_eq!(vm.pc, 0);
+
+    vm.set_timeout(None);
+    assert!(vm.step().is_none());
+  }
+
+  #[test]
+  fn deadline_is_sampled_every_1024_steps() {
+    let mut vm = load(vec![OpCode::Jump(0)]);
+
+    // The first step samples the clock before the deadline passes.
+    vm.set_timeout(Some(Duration::from_millis(20)));
+    assert!(vm.step().is_none());
+    thread::sleep(Duration::from_millis(30));
+
+    for _ in 0..1024 {
+      assert!(vm.step().is_none());
+    }
+
+    assert!(matches!(vm.step(), Some(StopReason::TimedOut)));
+  }
+}
@@ -0,0 +1,328 @@
+use svm_lang::{opcodes::Overflow, Type, Value};
+
+use super::VmErrorKind;
+
+/// A numeric value widened so that every conversion can start from one of two cases.
+#[derive(Debug, Clone, Copy)]
+enum Number {
+  /// Integers and bools (0 or 1). Every `u64` and `i64` fits.
+  Integer(i128),
+  /// Both float types. Every `f32` is exact as an `f64`.
+  Float(f64),
+}
+
+/// Converts an integer to the target integer type.
+macro_rules! to_integer {
+  ($number:expr, $overflow:expr, $target:ty) => {
+    match ($number, $overflow) {
+      (Number::Integer(n), Overflow::Wrapping) => Some(n as $target),
+      (Number::Integer(n), Overflow::Saturating) => {
+        Some(n.clamp(<$target>::MIN as i128, <$target>::MAX as i128) as $target)
+      }
+      (Number::Integer(n), Overflow::Checked) => <$target>::try_from(n).ok(),
+      // Rust's `as` truncates toward zero, saturates at the bounds and maps NaN to 0.
+      (Number::Float(f), Overflow::Wrapping | Overflow::Saturating) => Some(f as $target),
+      (Number::Float(f), Overflow::Checked) => {
+        exact_integer(f).and_then(|n| <$target>::try_from(n).ok())
+      }
+    }
+  };
+}
+
+/// Converts `value` to `to`.
+///
+/// Integers wrap with [`Overflow::Wrapping`], clamp with [`Overflow::Saturating`] and fail with
+/// [`Overflow::Checked`] when out of range. Floats become integers by truncating toward zero,
+/// saturating at the bounds, with NaN as 0; the checked mode fails unless the float is a whole
+/// number in range. Integers become floats rounded to the nearest value, and `f64` becomes
+/// `f32` the same way; the checked mode fails when rounding changes the value. Any nonzero
+/// value is `true`, which the checked mode only accepts for 0 and 1.
+///
+/// Values become `str` as printed by `std.io.print`. A `str` is read as a number, then
+/// converted with the rules above, so `"300"` to `u8` wraps to 44.
+pub(crate) fn cast(value: Value, to: Type, overflow: Overflow) -> Result<Value, VmErrorKind> {
+  if to == Type::String {
+    return format(value);
+  }
+
+  let number = match &value {
+    Value::Bool(v) => Number::Integer(*v as i128),
+    Value::U8(v) => Number::Integer(*v as i128),
+    Value::U16(v) => Number::Integer(*v as i128),
+    Value::U32(v) => Number::Integer(*v as i128),
+    Value::U64(v) => Number::Integer(*v as i128),
+    Value::I8(v) => Number::Integer(*v as i128),
+    Value::I16(v) => Number::Integer(*v as i128),
+    Value::I32(v) => Number::Integer(*v as i128),
+    Value::I64(v) => Number::Integer(*v as i128),
+    Value::Usize(v) => Number::Integer(*v as i128),
+    Value::Isize(v) => Number::Integer(*v as i128),
+    Value::F32(v) => Number::Float(*v as f64),
+    Value::F64(v) => Number::Float(*v),
+    // Going through f64 would round twice, so "0.1" would not give the nearest f32.
+    Value::String(text) if to == Type::F32 && text.parse::<f32>().is_ok() => {
+      return Ok(Value::F32(text.parse().unwrap()));
+    }
+    Value::String(text) => {
+      parse(text).ok_or_else(|| VmErrorKind::InvalidNumber(text.clone(), to))?
+    }
+    Value::Bytes(_) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot cast {:?} to {}",
+        value, to
+      )));
+    }
+  };
+
+  let result = match to {
+    Type::Bool => to_bool(number, overflow).map(Value::Bool),
+    Type::U8 => to_integer!(number, overflow, u8).map(Value::U8),
+    Type::U16 => to_integer!(number, overflow, u16).map(Value::U16),
+    Type::U32 => to_integer!(number, overflow, u32).map(Value::U32),
+    Type::U64 => to_integer!(number, overflow, u64).map(Value::U64),
+    Type::I8 => to_integer!(number, overflow, i8).map(Value::I8),
+    Type::I16 => to_integer!(number, overflow, i16).map(Value::I16),
+    Type::I32 => to_integer!(number, overflow, i32).map(Value::I32),
+    Type::I64 => to_integer!(number, overflow, i64).map(Value::I64),
+    Type::Usize => to_integer!(number, overflow, usize).map(Value::Usize),
+    Type::Isize => to_integer!(number, overflow, isize).map(Value::Isize),
+    Type::F32 => to_f32(number, overflow).map(Value::F32),
+    Type::F64 => to_f64(number, overflow).map(Value::F64),
+    Type::String | Type::Bytes => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot cast {:?} to {}",
+        value, to
+      )));
+    }
+  };
+
+  result.ok_or(VmErrorKind::LossyCast(value, to))
+}
+
+/// Text of `value` as printed by `std.io.print`.
+fn format(value: Value) -> Result<Value, VmErrorKind> {
+  let text = match value {
+    Value::Bool(v) => v.to_string(),
+    Value::U8(v) => v.to_string(),
+    Value::U16(v) => v.to_string(),
+    Value::U32(v) => v.to_string(),
+    Value::U64(v) => v.to_string(),
+    Value::I8(v) => v.to_string(),
+    Value::I16(v) => v.to_string(),
+    Value::I32(v) => v.to_string(),
+    Value::I64(v) => v.to_string(),
+    Value::Usize(v) => v.to_string(),
+    Value::Isize(v) => v.to_string(),
+    Value::F32(v) => v.to_string(),
+    Value::F64(v) => v.to_string(),
+    Value::String(v) => v,
+    Value::Bytes(_) => {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot cast {:?} to str",
+        value
+      )));
+    }
+  };
+
+  return Ok(Value::String(text));
+}
+
+/// Reads an integer, `true` or `false`, or else a float such as `2.5`, `1e3` or `NaN`.
+fn parse(text: &str) -> Option<Number> {
+  if let Ok(n) = text.parse::<i128>() {
+    return Some(Number::Integer(n));
+  }
+
+  if let Ok(b) = text.parse::<bool>() {
+    return Some(Number::Integer(b as i128));
+  }
+
+  return text.parse::<f64>().ok().map(Number::Float);
+}
+
+fn to_bool(number: Number, overflow: Overflow) -> Option<bool> {
+  match (number, overflow) {
+    (Number::Integer(n), Overflow::Checked) => match n {
+      0 => Some(false),
+      1 => Some(true),
+      _ => None,
+    },
+    (Number::Float(f), Overflow::Checked) => exact_integer(f).and_then(|n| match n {
+      0 => Some(false),
+      1 => Some(true),
+      _ => None,
+    }),
+    (Number::Integer(n), _) => Some(n != 0),
+    (Number::Float(f), _) => Some(f != 0.0),
+  }
+}
+
+fn to_f32(number: Number, overflow: Overflow) -> Option<f32> {
+  match (number, overflow) {
+    (Number::Integer(n), Overflow::Checked) => Some(n as f32).filter(|f| *f as i128 == n),
+    (Number::Integer(n), _) => Some(n as f32),
+    (Number::Float(f), Overflow::Checked) => {
+      Some(f as f32).filter(|narrow| *narrow as f64 == f || f.is_nan())
+    }
+    (Number::Float(f), Overflow::Saturating) if f.is_finite() => {
+      Some(f.clamp(f32::MIN as f64, f32::MAX as f64) as f32)
+    }
+    (Number::Float(f), _) => Some(f as f32),
+  }
+}
+
+fn to_f64(number: Number, overflow: Overflow) -> Option<f64> {
+  match (number, overflow) {
+    (Number::Integer(n), Overflow::Checked) => Some(n as f64).filter(|f| *f as i128 == n),
+    (Number::Integer(n), _) => Some(n as f64),
+    (Number::Float(f), _) => Some(f),
+  }
+}
+
+/// The integer equal to `f`, or `None` if `f` has a fractional part or is not finite.
+fn exact_integer(f: f64) -> Option<i128> {
+  let n = f as i128;
+
+  return (n as f64 == f).then_some(n);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn integers_wrap_clamp_or_fail_out_of_range() {
+    assert_eq!(
+      cast(Value::U16(300), Type::U8, Overflow::Wrapping),
+      Ok(Value::U8(44))
+    );
+    assert_eq!(
+      cast(Value::U16(300), Type::U8, Overflow::Saturating),
+      Ok(Value::U8(255))
+    );
+    assert_eq!(
+      cast(Value::U16(300), Type::U8, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::U16(300), Type::U8))
+    );
+    assert_eq!(
+      cast(Value::I8(-1), Type::U64, Overflow::Wrapping),
+      Ok(Value::U64(u64::MAX))
+    );
+    assert_eq!(
+      cast(Value::I8(-1), Type::U64, Overflow::Saturating),
+      Ok(Value::U64(0))
+    );
+    assert_eq!(
+      cast(Value::U64(u64::MAX), Type::I64, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::U64(u64::MAX), Type::I64))
+    );
+  }
+
+  #[test]
+  fn floats_truncate_toward_zero_and_saturate() {
+    assert_eq!(
+      cast(Value::F64(-2.9), Type::I32, Overflow::Wrapping),
+      Ok(Value::I32(-2))
+    );
+    assert_eq!(
+      cast(Value::F64(1e20), Type::U32, Overflow::Wrapping),
+      Ok(Value::U32(u32::MAX))
+    );
+    assert_eq!(
+      cast(Value::F64(-1.0), Type::U8, Overflow::Saturating),
+      Ok(Value::U8(0))
+    );
+    assert_eq!(
+      cast(Value::F32(f32::NAN), Type::I64, Overflow::Wrapping),
+      Ok(Value::I64(0))
+    );
+  }
+
+  #[test]
+  fn checked_float_casts_need_whole_numbers_in_range() {
+    assert_eq!(
+      cast(Value::F64(3.0), Type::U8, Overflow::Checked),
+      Ok(Value::U8(3))
+    );
+    assert_eq!(
+      cast(Value::F64(2.5), Type::I32, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::F64(2.5), Type::I32))
+    );
+    assert_eq!(
+      cast(Value::F64(256.0), Type::U8, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::F64(256.0), Type::U8))
+    );
+    assert_eq!(
+      cast(Value::F64(f64::INFINITY), Type::I64, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::F64(f64::INFINITY), Type::I64))
+    );
+  }
+
+  #[test]
+  fn integers_round_to_the_nearest_float() {
+    assert_eq!(
+      cast(Value::U32(16_777_217), Type::F32, Overflow::Wrapping),
+      Ok(Value::F32(16_777_216.0))
+    );
+    assert_eq!(
+      cast(Value::U32(16_777_217), Type::F32, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::U32(16_777_217), Type::F32))
+    );
+    assert_eq!(
+      cast(Value::U64(u64::MAX), Type::F64, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::U64(u64::MAX), Type::F64))
+    );
+  }
+
+  #[test]
+  fn narrowing_f64_to_f32() {
+    assert_eq!(
+      cast(Value::F64(0.5), Type::F32, Overflow::Checked),
+      Ok(Value::F32(0.5))
+    );
+    assert_eq!(
+      cast(Value::F64(0.1), Type::F32, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::F64(0.1), Type::F32))
+    );
+    assert_eq!(
+      cast(Value::F64(1e300), Type::F32, Overflow::Wrapping),
+      Ok(Value::F32(f32::INFINITY))
+    );
+    assert_eq!(
+      cast(Value::F64(1e300), Type::F32, Overflow::Saturating),
+      Ok(Value::F32(f32::MAX))
+    );
+  }
+
+  #[test]
+  fn any_nonzero_value_is_true() {
+    assert_eq!(
+      cast(Value::I16(-7), Type::Bool, Overflow::Wrapping),
+      Ok(Value::Bool(true))
+    );
+    assert_eq!(
+      cast(Value::F32(0.0), Type::Bool, Overflow::Wrapping),
+      Ok(Value::Bool(false))
+    );
+    assert_eq!(
+      cast(Value::U8(1), Type::Bool, Overflow::Checked),
+      Ok(Value::Bool(true))
+    );
+    assert_eq!(
+      cast(Value::U8(2), Type::Bool, Overflow::Checked),
+      Err(VmErrorKind::LossyCast(Value::U8(2), Type::Bool))
+    );
+    assert_eq!(
+      cast(Value::Bool(true), Type::I8, Overflow::Checked),
+      Ok(Value::I8(1))
+    );
+  }
+
+  #[test]
+  fn bytes_cannot_be_cast() {
+    assert!(matches!(
+      cast(Value::Bytes(vec![1]), Type::U8, Overflow::Wrapping),
+      Err(VmErrorKind::InvalidType(_))
+    ));
+  }
+}
@@ -0,0 +1,287 @@
+use std::mem;
+
+use svm_lang::{opcodes::OpCode, Value};
+
+use super::{Frame, Handler, Stack, StopReason, VmError, VmErrorKind, VM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineState {
+  /// Spawned or yielded, waiting for `RESUME`.
+  Suspended,
+  /// Currently executing, or waiting for a coroutine it resumed.
+  Running,
+  /// Returned from its entry point. Resuming it again has no effect.
+  Finished,
+}
+
+/// Execution state that is swapped in and out when switching between coroutines.
+#[derive(Debug)]
+pub struct Context {
+  pub stack: Stack,
+  pub pc: usize,
+  pub frames: Vec<Frame>,
+  pub handlers: Vec<Handler>,
+}
+
+impl Context {
+  fn empty() -> Self {
+    Self {
+      stack: Stack::new(0),
+      pc: 0,
+      frames: Vec::new(),
+      handlers: Vec::new(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Coroutine {
+  pub state: CoroutineState,
+  /// Saved state while suspended. Empty while running or finished.
+  pub context: Context,
+}
+
+/// The context that resumed a coroutine, restored when the coroutine yields or finishes.
+#[derive(Debug)]
+pub struct Resumer {
+  pub coroutine: usize,
+  pub context: Context,
+  /// Resumed through [`VM::resume_coroutine`] rather than the `RESUME` opcode.
+  pub host: bool,
+}
+
+/// Outcome of [`VM::resume_coroutine`].
+#[derive(Debug, Clone)]
+pub enum Resumed {
+  Yielded(Value),
+  Finished,
+  /// Execution stopped inside the coroutine (e.g. `HALT` or a timeout) before it yielded.
+  Stopped(StopReason),
+}
+
+impl VM {
+  /// Creates a suspended coroutine that starts at `addr` with its own stack and registers.
+  pub fn spawn_coroutine(&mut self, addr: usize) -> usize {
+    let mut stack = Stack::allocate(self.stack.size, self.stack.register_count());
+    stack.set_checked(self.stack.is_checked());
+
+    self.coroutines.push(Coroutine {
+      state: CoroutineState::Suspended,
+      context: Context {
+        stack,
+        pc: addr,
+        frames: Vec::new(),
+        handlers: Vec::new(),
+      },
+    });
+
+    return self.coroutines.len() - 1;
+  }
+
+  pub fn coroutine(&self, id: usize) -> Option<&Coroutine> {
+    self.coroutines.get(id)
+  }
+
+  /// Id of the coroutine being executed, or `None` in the main program.
+  pub fn current_coroutine(&self) -> Option<usize> {
+    self.resumers.last().map(|resumer| resumer.coroutine)
+  }
+
+  /// Runs coroutine `id` until it yields or finishes.
+  pub fn resume_coroutine(&mut self, id: usize) -> Result<Resumed, VmError> {
+    match self.enter_coroutine(id, true) {
+      Ok(true) => {}
+      Ok(false) => return Ok(Resumed::Finished),
+      Err(kind) => {
+        return Err(VmError {
+          kind,
+          pc: self.pc,
+          opcode: OpCode::Resume,
+        })
+      }
+    }
+
+    loop {
+      if let Some(resumed) = self.host_resumed.take() {
+        return Ok(resumed);
+      }
+
+      match self.step() {
+        None => {}
+        Some(StopReason::Error(error)) => return Err(error),
+        Some(reason) => return Ok(Resumed::Stopped(reason)),
+      }
+    }
+  }
+
+  /// Switches to coroutine `id`. Returns `false` if it has already finished.
+  pub(crate) fn enter_coroutine(&mut self, id: usize, host: bool) -> Result<bool, VmErrorKind> {
+    let coroutine = self
+      .coroutines
+      .get_mut(id)
+      .ok_or(VmErrorKind::InvalidCoroutine(id))?;
+
+    match coroutine.state {
+      CoroutineState::Suspended => {}
+      CoroutineState::Finished => return Ok(false),
+      CoroutineState::Running => return Err(VmErrorKind::InvalidCoroutine(id)),
+    }
+
+    coroutine.state = CoroutineState::Running;
+
+    let context = mem::replace(&mut coroutine.context, Context::empty());
+    let context = self.swap_context(context);
+
+    self.resumers.push(Resumer {
+      coroutine: id,
+      context,
+      host,
+    });
+
+    Ok(true)
+  }
+
+  /// Returns to the resumer of the running coroutine, suspending it with `value` or marking
+  /// it as finished when there is no value.
+  pub(crate) fn leave_coroutine(&mut self, value: Option<Value>) -> Result<(), VmErrorKind> {
+    let resumer = self
+      .resumers
+      .pop()
+      .ok_or(VmErrorKind::YieldOutsideCoroutine)?;
+    let context = self.swap_context(resumer.context);
+    let coroutine = &mut self.coroutines[resumer.coroutine];
+
+    match value {
+      Some(_) => {
+        coroutine.state = CoroutineState::Suspended;
+        coroutine.context = context;
+      }
+      None => coroutine.state = CoroutineState::Finished,
+    }
+
+    if resumer.host {
+      self.host_resumed = Some(match value {
+        Some(value) => Resumed::Yielded(value),
+        None => Resumed::Finished,
+      });
+
+      return Ok(());
+    }
+
+    match value {
+      Some(value) => {
+        self.stack.push_value(value)?;
+        self.stack.push_value(Value::Bool(true))
+      }
+      None => self.stack.push_value(Value::Bool(false)),
+    }
+  }
+
+  fn swap_context(&mut self, context: Context) -> Context {
+    Context {
+      stack: mem::replace(&mut self.stack, context.stack),
+      pc: mem::replace(&mut self.pc, context.pc),
+      frames: mem::replace(&mut self.frames, context.frames),
+      handlers: mem::replace(&mut self.handlers, context.handlers),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::{opcodes::OpCode, Program, Type, Value};
+
+  use super::{CoroutineState, Resumed};
+  use crate::{StopReason, VmErrorKind, VM};
+
+  fn load(opcodes: Vec<OpCode>) -> VM {
+    let mut vm = VM::new();
+
+    vm.load(Program {
+      opcodes,
+      ..Program::empty()
+    })
+    .unwrap();
+
+    return vm;
+  }
+
+  #[test]
+  fn resume_pushes_the_yielded_value_and_true() {
+    let mut vm = load(vec![
+      OpCode::Spawn(3),
+      OpCode::Resume,
+      OpCode::Halt,
+      OpCode::Push(Value::U8(10)),
+      OpCode::Yield(Type::U8),
+      OpCode::Return,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::Bool), Ok(Value::Bool(true)));
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(10)));
+    assert_eq!(vm.current_coroutine(), None);
+
+    let coroutine = vm.coroutine(0).unwrap();
+    assert_eq!(coroutine.state, CoroutineState::Suspended);
+    assert_eq!(coroutine.context.pc, 5);
+  }
+
+  #[test]
+  fn resume_pushes_false_once_the_coroutine_returned() {
+    let mut vm = load(vec![
+      OpCode::Spawn(7),
+      OpCode::Copy(Type::Usize),
+      OpCode::Copy(Type::Usize),
+      OpCode::Resume,
+      OpCode::Pop(Type::Bool, None),
+      OpCode::Resume,
+      OpCode::Halt,
+      OpCode::Return,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::Bool), Ok(Value::Bool(false)));
+    assert_eq!(vm.stack.pop_value(&Type::Usize), Ok(Value::Usize(0)));
+    assert_eq!(vm.coroutine(0).unwrap().state, CoroutineState::Finished);
+  }
+
+  #[test]
+  fn host_resumes_until_the_coroutine_finishes() {
+    let mut vm = load(vec![
+      OpCode::Halt,
+      OpCode::Push(Value::U8(1)),
+      OpCode::Yield(Type::U8),
+      OpCode::Push(Value::U8(2)),
+      OpCode::Yield(Type::U8),
+      OpCode::Return,
+    ]);
+    let id = vm.spawn_coroutine(1);
+
+    assert!(matches!(
+      vm.resume_coroutine(id),
+      Ok(Resumed::Yielded(Value::U8(1)))
+    ));
+    assert!(matches!(
+      vm.resume_coroutine(id),
+      Ok(Resumed::Yielded(Value::U8(2)))
+    ));
+    assert!(matches!(vm.resume_coroutine(id), Ok(Resumed::Finished)));
+    assert!(matches!(vm.resume_coroutine(id), Ok(Resumed::Finished)));
+    assert_eq!(vm.pc, 0);
+  }
+
+  #[test]
+  fn yield_outside_a_coroutine_fails() {
+    let mut vm = load(vec![OpCode::Push(Value::U8(1)), OpCode::Yield(Type::U8)]);
+
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::YieldOutsideCoroutine
+    );
+    assert_eq!(
+      vm.resume_coroutine(3).unwrap_err().kind,
+      VmErrorKind::InvalidCoroutine(3)
+    );
+  }
+}
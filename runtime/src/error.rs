@@ -0,0 +1,183 @@
+use std::fmt::Display;
+
+use svm_lang::{opcodes::OpCode, Type, Value};
+
+use crate::MAX_REGISTER_COUNT;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmErrorKind {
+  StackUnderflow,
+  StackOverflow,
+  InvalidType(String),
+  RegisterOutOfBounds(u8),
+  RegisterOverflow,
+  /// More registers were requested than [`MAX_REGISTER_COUNT`](crate::MAX_REGISTER_COUNT).
+  TooManyRegisters(usize),
+  InvalidExtern(usize),
+  DuplicateExtern(String),
+  UnresolvedImports(Vec<String>),
+  MemoryError,
+  NoSavedStack,
+  CallStackOverflow,
+  NoCallFrame,
+  DivisionByZero,
+  /// A checked integer operation on the given type did not fit in it.
+  IntegerOverflow(Type),
+  /// A checked `CAST` would have changed the value.
+  LossyCast(Value, Type),
+  /// The text of a `CAST` from `str` is not a number.
+  InvalidNumber(String, Type),
+  /// Char indices `start..end` of a string with `len` chars.
+  StringOutOfBounds {
+    start: usize,
+    end: usize,
+    len: usize,
+  },
+  InvalidChar(u32),
+  /// The bytes popped as a `str` are not valid UTF-8.
+  InvalidUtf8,
+  /// A local at this offset from the frame pointer is not below the stack pointer.
+  LocalOutOfBounds(isize),
+  UnknownConstant(usize),
+  UnknownGlobal(usize),
+  OutOfGas,
+  OutOfMemory(usize),
+  HeapOutOfBounds(usize),
+  UseAfterFree(usize),
+  InvalidFree(usize),
+  ExternError(String),
+  InvalidCoroutine(usize),
+  YieldOutsideCoroutine,
+  InvalidMessage(String),
+  Uncaught(Value),
+  NoHandler,
+  TypeMismatch {
+    expected: Type,
+    actual: Type,
+  },
+}
+
+impl VmErrorKind {
+  pub fn code(&self) -> &'static str {
+    match self {
+      VmErrorKind::StackUnderflow => "StackUnderflow",
+      VmErrorKind::StackOverflow => "StackOverflow",
+      VmErrorKind::InvalidType(_) => "InvalidType",
+      VmErrorKind::RegisterOutOfBounds(_) => "RegisterOutOfBounds",
+      VmErrorKind::RegisterOverflow => "RegisterOverflow",
+      VmErrorKind::TooManyRegisters(_) => "TooManyRegisters",
+      VmErrorKind::InvalidExtern(_) => "InvalidExtern",
+      VmErrorKind::DuplicateExtern(_) => "DuplicateExtern",
+      VmErrorKind::UnresolvedImports(_) => "UnresolvedImports",
+      VmErrorKind::MemoryError => "MemoryError",
+      VmErrorKind::NoSavedStack => "NoSavedStack",
+      VmErrorKind::CallStackOverflow => "CallStackOverflow",
+      VmErrorKind::NoCallFrame => "NoCallFrame",
+      VmErrorKind::DivisionByZero => "DivisionByZero",
+      VmErrorKind::IntegerOverflow(_) => "IntegerOverflow",
+      VmErrorKind::LossyCast(..) => "LossyCast",
+      VmErrorKind::InvalidNumber(..) => "InvalidNumber",
+      VmErrorKind::StringOutOfBounds { .. } => "StringOutOfBounds",
+      VmErrorKind::InvalidChar(_) => "InvalidChar",
+      VmErrorKind::InvalidUtf8 => "InvalidUtf8",
+      VmErrorKind::LocalOutOfBounds(_) => "LocalOutOfBounds",
+      VmErrorKind::UnknownConstant(_) => "UnknownConstant",
+      VmErrorKind::UnknownGlobal(_) => "UnknownGlobal",
+      VmErrorKind::OutOfGas => "OutOfGas",
+      VmErrorKind::OutOfMemory(_) => "OutOfMemory",
+      VmErrorKind::HeapOutOfBounds(_) => "HeapOutOfBounds",
+      VmErrorKind::UseAfterFree(_) => "UseAfterFree",
+      VmErrorKind::InvalidFree(_) => "InvalidFree",
+      VmErrorKind::ExternError(_) => "ExternError",
+      VmErrorKind::InvalidCoroutine(_) => "InvalidCoroutine",
+      VmErrorKind::YieldOutsideCoroutine => "YieldOutsideCoroutine",
+      VmErrorKind::InvalidMessage(_) => "InvalidMessage",
+      VmErrorKind::Uncaught(_) => "Uncaught",
+      VmErrorKind::NoHandler => "NoHandler",
+      VmErrorKind::TypeMismatch { .. } => "TypeMismatch",
+    }
+  }
+}
+
+impl Display for VmErrorKind {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VmErrorKind::StackUnderflow => write!(f, "Cannot read from empty stack!"),
+      VmErrorKind::StackOverflow => write!(f, "Maximum stack size exceeded!"),
+      VmErrorKind::InvalidType(message) => write!(f, "{message}"),
+      VmErrorKind::RegisterOutOfBounds(register) => {
+        write!(f, "Register index {register} out of bounds!")
+      }
+      VmErrorKind::RegisterOverflow => {
+        write!(f, "Cannot set register with value larger than usize!")
+      }
+      VmErrorKind::TooManyRegisters(count) => write!(
+        f,
+        "Cannot create {count} registers: at most {MAX_REGISTER_COUNT} are supported"
+      ),
+      VmErrorKind::InvalidExtern(import) => write!(f, "Import {import} is not linked"),
+      VmErrorKind::DuplicateExtern(name) => write!(f, "Extern {name:?} is already registered"),
+      VmErrorKind::UnresolvedImports(names) => {
+        write!(f, "Unresolved imports: {}", names.join(", "))
+      }
+      VmErrorKind::MemoryError => write!(f, "Stack pointer out of bounds"),
+      VmErrorKind::NoSavedStack => write!(f, "No saved stack to restore!"),
+      VmErrorKind::CallStackOverflow => write!(f, "Maximum call depth exceeded!"),
+      VmErrorKind::NoCallFrame => write!(f, "Cannot return outside of a function!"),
+      VmErrorKind::DivisionByZero => write!(f, "Division by zero"),
+      VmErrorKind::IntegerOverflow(item_type) => write!(f, "Integer overflow in {item_type}"),
+      VmErrorKind::LossyCast(value, to) => {
+        write!(f, "Cannot convert {value:?} to {to} without loss")
+      }
+      VmErrorKind::InvalidNumber(text, to) => write!(f, "Cannot parse {text:?} as {to}"),
+      VmErrorKind::StringOutOfBounds { start, end, len } => write!(
+        f,
+        "Chars {start}..{end} out of bounds for a string of {len} chars"
+      ),
+      VmErrorKind::InvalidChar(code) => write!(f, "{code} is not a valid char code"),
+      VmErrorKind::InvalidUtf8 => write!(f, "String on the stack is not valid UTF-8"),
+      VmErrorKind::LocalOutOfBounds(offset) => {
+        write!(f, "Local at offset {offset} out of bounds of the stack")
+      }
+      VmErrorKind::UnknownConstant(index) => write!(f, "Constant {index} does not exist"),
+      VmErrorKind::UnknownGlobal(index) => write!(f, "Global {index} does not exist"),
+      VmErrorKind::OutOfGas => write!(f, "Out of gas"),
+      VmErrorKind::OutOfMemory(size) => write!(f, "Cannot allocate {size} bytes: out of memory"),
+      VmErrorKind::HeapOutOfBounds(addr) => write!(f, "Heap access out of bounds at {addr}"),
+      VmErrorKind::UseAfterFree(addr) => write!(f, "Heap access to freed memory at {addr}"),
+      VmErrorKind::InvalidFree(addr) => write!(f, "Cannot free {addr}: not an allocated block"),
+      VmErrorKind::ExternError(message) => write!(f, "{message}"),
+      VmErrorKind::InvalidCoroutine(id) => write!(f, "Coroutine {id} cannot be resumed"),
+      VmErrorKind::YieldOutsideCoroutine => write!(f, "Cannot yield outside of a coroutine!"),
+      VmErrorKind::InvalidMessage(message) => write!(f, "{message}"),
+      VmErrorKind::Uncaught(value) => write!(f, "Uncaught exception: {value:?}"),
+      VmErrorKind::NoHandler => write!(f, "END_TRY without a matching TRY!"),
+      VmErrorKind::TypeMismatch { expected, actual } => {
+        write!(f, "Expected {expected} on the stack, found {actual}")
+      }
+    }
+  }
+}
+
+/// A runtime failure together with the instruction that caused it.
+#[derive(Debug, Clone)]
+pub struct VmError {
+  pub kind: VmErrorKind,
+  pub pc: usize,
+  pub opcode: OpCode,
+}
+
+impl Display for VmError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "VmError [{}]: {} (PC={}, {:?})",
+      self.kind.code(),
+      self.kind,
+      self.pc,
+      self.opcode
+    )
+  }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,52 @@
+use svm_lang::Value;
+
+use super::{VmErrorKind, VM};
+
+/// An exception handler installed by `TRY` and removed by `END_TRY`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handler {
+  /// Address execution continues at when a value is thrown.
+  pub catch_pc: usize,
+  /// Stack pointer when the handler was installed, restored before jumping to `catch_pc`.
+  pub sp: usize,
+  /// Number of call frames when the handler was installed.
+  pub frames: usize,
+}
+
+impl VM {
+  pub(crate) fn install_handler(&mut self, catch_pc: usize) {
+    self.handlers.push(Handler {
+      catch_pc,
+      sp: self.stack.sp,
+      frames: self.frames.len(),
+    });
+  }
+
+  /// Unwinds to the innermost handler and pushes the thrown value, then its type code as a
+  /// `u8` on top of it.
+  pub(crate) fn throw_value(&mut self, value: Value) -> Result<(), VmErrorKind> {
+    let Some(handler) = self.handlers.pop() else {
+      return Err(VmErrorKind::Uncaught(value));
+    };
+
+    self.frames.truncate(handler.frames);
+    self.stack.sp = handler.sp;
+    self.pc = handler.catch_pc;
+
+    let type_code = value.data_type().to_bytes()[0];
+
+    self.stack.push_value(value)?;
+    self.stack.push_value(Value::U8(type_code))
+  }
+
+  /// Drops the handlers installed by a function that returned without `END_TRY`.
+  pub(crate) fn discard_handlers(&mut self) {
+    while let Some(handler) = self.handlers.last() {
+      if handler.frames <= self.frames.len() {
+        break;
+      }
+
+      self.handlers.pop();
+    }
+  }
+}
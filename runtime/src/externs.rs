@@ -0,0 +1,42 @@
+use svm_lang::Value;
+
+use super::{VmErrorKind, DEFAULT_EXTERN_COST, VM};
+
+/// A host function callable from programs through `EXT`.
+///
+/// Externs are owned by the VM, so they can keep state between calls. Any
+/// `FnMut(&mut VM) -> Result<Option<Value>, VmErrorKind>` closure is an extern.
+pub trait Extern {
+  /// Runs the extern. A returned value is pushed onto the stack.
+  fn call(&mut self, vm: &mut VM) -> Result<Option<Value>, VmErrorKind>;
+
+  /// Gas charged on top of the `EXT` instruction itself.
+  fn cost(&self) -> u64 {
+    DEFAULT_EXTERN_COST
+  }
+}
+
+impl<F> Extern for F
+where
+  F: FnMut(&mut VM) -> Result<Option<Value>, VmErrorKind>,
+{
+  fn call(&mut self, vm: &mut VM) -> Result<Option<Value>, VmErrorKind> {
+    self(vm)
+  }
+}
+
+/// Overrides the cost of another extern.
+pub(crate) struct WithCost<E: Extern> {
+  pub ext: E,
+  pub cost: u64,
+}
+
+impl<E: Extern> Extern for WithCost<E> {
+  fn call(&mut self, vm: &mut VM) -> Result<Option<Value>, VmErrorKind> {
+    self.ext.call(vm)
+  }
+
+  fn cost(&self) -> u64 {
+    self.cost
+  }
+}
@@ -0,0 +1,8 @@
+/// A call frame pushed by `CALL` and popped by `RET`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+  /// Address of the instruction following the `CALL`.
+  pub return_pc: usize,
+  /// Stack pointer at the moment of the call, restored on `RET`.
+  pub sp: usize,
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, mem::Discriminant};
+
+use svm_lang::opcodes::OpCode;
+
+pub const DEFAULT_OPCODE_COST: u64 = 1;
+pub const DEFAULT_EXTERN_COST: u64 = 1;
+
+/// Gas charged for each instruction, keyed by opcode variant.
+///
+/// Operands are ignored, so `PUSH u8 1` and `PUSH u64 1` cost the same, but `PushBytes`,
+/// `PushAll` and `RegisterOp` are priced apart from `Push` and the stack arithmetic. Variants
+/// without an entry cost `default_cost`.
+#[derive(Debug, Clone)]
+pub struct GasTable {
+  pub default_cost: u64,
+  costs: HashMap<Discriminant<OpCode>, u64>,
+}
+
+impl GasTable {
+  pub fn new(default_cost: u64) -> Self {
+    Self {
+      default_cost,
+      costs: HashMap::new(),
+    }
+  }
+
+  /// Sets the cost of every opcode of the same variant as `op`.
+  pub fn set(&mut self, op: &OpCode, cost: u64) -> &mut Self {
+    self.costs.insert(std::mem::discriminant(op), cost);
+    self
+  }
+
+  pub fn cost(&self, op: &OpCode) -> u64 {
+    return *self
+      .costs
+      .get(&std::mem::discriminant(op))
+      .unwrap_or(&self.default_cost);
+  }
+}
+
+impl Default for GasTable {
+  fn default() -> Self {
+    Self::new(DEFAULT_OPCODE_COST)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::{
+    opcodes::{BinaryOp, OpCode, Overflow},
+    Program, Type, Value,
+  };
+
+  use super::GasTable;
+  use crate::{StopReason, VmErrorKind, VM};
+
+  #[test]
+  fn prices_each_variant_apart() {
+    let mut table = GasTable::new(1);
+    table
+      .set(&OpCode::Push(Value::U8(0)), 2)
+      .set(&OpCode::PushBytes(vec![]), 5)
+      .set(&OpCode::Add(Type::U8, Overflow::Checked), 3);
+
+    assert_eq!(table.cost(&OpCode::Push(Value::U64(9))), 2);
+    assert_eq!(table.cost(&OpCode::PushBytes(vec![1, 2])), 5);
+    assert_eq!(table.cost(&OpCode::PushAll(vec![Value::U8(1)])), 1);
+    assert_eq!(table.cost(&OpCode::Add(Type::U64, Overflow::Wrapping)), 3);
+    assert_eq!(
+      table.cost(&OpCode::RegisterOp(
+        BinaryOp::Add,
+        Overflow::Checked,
+        Type::U8,
+        0,
+        1,
+        2
+      )),
+      1
+    );
+  }
+
+  #[test]
+  fn out_of_gas_leaves_the_pc_for_refuelling() {
+    let mut vm = VM::new();
+    vm.load(Program {
+      opcodes: vec![
+        OpCode::Push(Value::U8(1)),
+        OpCode::Push(Value::U8(2)),
+        OpCode::Add(Type::U8, Overflow::Checked),
+        OpCode::Halt,
+      ],
+      ..Program::empty()
+    })
+    .unwrap();
+    let mut table = GasTable::default();
+    table.set(&OpCode::Add(Type::U8, Overflow::Checked), 5);
+    vm.set_gas_table(table);
+    vm.set_gas(Some(6));
+
+    let error = vm.run().unwrap_err();
+    assert_eq!(error.kind, VmErrorKind::OutOfGas);
+    assert_eq!(error.pc, 2);
+    assert_eq!(vm.pc, 2);
+    assert_eq!(vm.remaining_gas(), Some(4));
+
+    vm.set_gas(Some(6));
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.remaining_gas(), Some(0));
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(3)));
+  }
+}
@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use svm_lang::Type;
+
+use super::VmErrorKind;
+
+pub const DEFAULT_HEAP_SIZE: usize = 1024 * 1024;
+
+/// Allocations are aligned to this many bytes.
+const HEAP_ALIGN: usize = 8;
+
+/// Byte-addressable memory with a first-fit allocator.
+///
+/// The first `HEAP_ALIGN` bytes are never handed out, so address 0 can be used as a null
+/// pointer. Memory grows on demand up to `max_size` bytes.
+#[derive(Debug, Clone)]
+pub struct Heap {
+  pub memory: Vec<u8>,
+  pub max_size: usize,
+  /// Live blocks, by start address.
+  pub allocations: BTreeMap<usize, usize>,
+  /// Freed blocks available for reuse, by start address. Adjacent blocks are merged.
+  pub free_blocks: BTreeMap<usize, usize>,
+}
+
+impl Heap {
+  pub fn new(max_size: usize) -> Self {
+    Self {
+      memory: vec![0; HEAP_ALIGN],
+      max_size,
+      allocations: BTreeMap::new(),
+      free_blocks: BTreeMap::new(),
+    }
+  }
+
+  /// Allocates `size` bytes and returns the address of the block.
+  pub fn alloc(&mut self, size: usize) -> Result<usize, VmErrorKind> {
+    let block_size = size
+      .max(1)
+      .checked_next_multiple_of(HEAP_ALIGN)
+      .ok_or(VmErrorKind::OutOfMemory(size))?;
+
+    let reusable = self
+      .free_blocks
+      .iter()
+      .find(|(_, free_size)| **free_size >= block_size)
+      .map(|(addr, free_size)| (*addr, *free_size));
+
+    let addr = match reusable {
+      Some((addr, free_size)) => {
+        self.free_blocks.remove(&addr);
+
+        if free_size > block_size {
+          self
+            .free_blocks
+            .insert(addr + block_size, free_size - block_size);
+        }
+
+        self.memory[addr..addr + block_size].fill(0);
+
+        addr
+      }
+      None => {
+        let addr = self.memory.len();
+
+        if self.max_size.saturating_sub(addr) < block_size {
+          return Err(VmErrorKind::OutOfMemory(size));
+        }
+
+        self.memory.resize(addr + block_size, 0);
+
+        addr
+      }
+    };
+
+    self.allocations.insert(addr, block_size);
+
+    return Ok(addr);
+  }
+
+  pub fn free(&mut self, addr: usize) -> Result<(), VmErrorKind> {
+    let mut size = self
+      .allocations
+      .remove(&addr)
+      .ok_or(VmErrorKind::InvalidFree(addr))?;
+    let mut start = addr;
+
+    if let Some(next_size) = self.free_blocks.remove(&(addr + size)) {
+      size += next_size;
+    }
+
+    if let Some((&prev, &prev_size)) = self.free_blocks.range(..addr).next_back() {
+      if prev + prev_size == addr {
+        self.free_blocks.remove(&prev);
+        start = prev;
+        size += prev_size;
+      }
+    }
+
+    self.free_blocks.insert(start, size);
+
+    Ok(())
+  }
+
+  pub fn read(&self, addr: usize, len: usize) -> Result<&[u8], VmErrorKind> {
+    self.check_access(addr, len)?;
+
+    return Ok(&self.memory[addr..addr + len]);
+  }
+
+  pub fn write(&mut self, addr: usize, bytes: &[u8]) -> Result<(), VmErrorKind> {
+    self.check_access(addr, bytes.len())?;
+
+    self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+
+    Ok(())
+  }
+
+  /// Whether memory fits in `max_size` and every block lies inside it, past the reserved null
+  /// bytes, without overlapping another. Used to validate heaps read from a snapshot.
+  pub(crate) fn is_consistent(&self) -> bool {
+    if self.memory.len() < HEAP_ALIGN || self.memory.len() > self.max_size.max(HEAP_ALIGN) {
+      return false;
+    }
+
+    let mut blocks = self
+      .allocations
+      .iter()
+      .chain(&self.free_blocks)
+      .collect::<Vec<_>>();
+    blocks.sort();
+
+    let mut end = HEAP_ALIGN;
+
+    for (&addr, &size) in blocks {
+      match addr.checked_add(size) {
+        Some(block_end) if addr >= end && block_end <= self.memory.len() => end = block_end,
+        _ => return false,
+      }
+    }
+
+    return true;
+  }
+
+  /// Size of a value of `item_type` in memory. Strings and byte arrays have no fixed size.
+  pub fn value_size(item_type: &Type) -> Result<usize, VmErrorKind> {
+    match item_type {
+      Type::String | Type::Bytes => Err(VmErrorKind::InvalidType(format!(
+        "Cannot access {} in the heap",
+        item_type.to_str()
+      ))),
+      t => Ok(t.size()),
+    }
+  }
+
+  /// The whole range must lie inside a single live allocation.
+  fn check_access(&self, addr: usize, len: usize) -> Result<(), VmErrorKind> {
+    let end = addr
+      .checked_add(len)
+      .ok_or(VmErrorKind::HeapOutOfBounds(addr))?;
+
+    if let Some((&start, &size)) = self.allocations.range(..=addr).next_back() {
+      if addr < start + size {
+        if end > start + size {
+          return Err(VmErrorKind::HeapOutOfBounds(addr));
+        }
+
+        return Ok(());
+      }
+    }
+
+    if let Some((&start, &size)) = self.free_blocks.range(..=addr).next_back() {
+      if addr < start + size {
+        return Err(VmErrorKind::UseAfterFree(addr));
+      }
+    }
+
+    return Err(VmErrorKind::HeapOutOfBounds(addr));
+  }
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    Self::new(DEFAULT_HEAP_SIZE)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn allocations_are_aligned_and_never_null() {
+    let mut heap = Heap::new(64);
+
+    assert_eq!(heap.alloc(1), Ok(8));
+    assert_eq!(heap.alloc(9), Ok(16));
+    assert_eq!(heap.alloc(0), Ok(32));
+    assert!(heap.is_consistent());
+  }
+
+  #[test]
+  fn freed_blocks_are_merged_and_reused_zeroed() {
+    let mut heap = Heap::new(64);
+    let a = heap.alloc(8).unwrap();
+    let b = heap.alloc(8).unwrap();
+    heap.alloc(8).unwrap();
+
+    heap.write(a, &[1; 8]).unwrap();
+    heap.free(a).unwrap();
+    heap.free(b).unwrap();
+
+    assert_eq!(heap.free_blocks, BTreeMap::from([(a, 16)]));
+    assert_eq!(heap.alloc(16), Ok(a));
+    assert_eq!(heap.read(a, 16), Ok(&[0; 16][..]));
+    assert!(heap.is_consistent());
+  }
+
+  #[test]
+  fn rejects_accesses_outside_live_blocks() {
+    let mut heap = Heap::new(64);
+    let a = heap.alloc(8).unwrap();
+    let b = heap.alloc(8).unwrap();
+
+    assert_eq!(
+      heap.read(a + 4, 8),
+      Err(VmErrorKind::HeapOutOfBounds(a + 4))
+    );
+    assert_eq!(heap.read(0, 1), Err(VmErrorKind::HeapOutOfBounds(0)));
+
+    heap.free(b).unwrap();
+
+    assert_eq!(heap.read(b, 1), Err(VmErrorKind::UseAfterFree(b)));
+    assert_eq!(heap.free(b), Err(VmErrorKind::InvalidFree(b)));
+  }
+
+  #[test]
+  fn stops_growing_at_the_maximum_size() {
+    let mut heap = Heap::new(32);
+
+    assert_eq!(heap.alloc(24), Ok(8));
+    assert_eq!(heap.alloc(1), Err(VmErrorKind::OutOfMemory(1)));
+    assert_eq!(
+      heap.alloc(usize::MAX),
+      Err(VmErrorKind::OutOfMemory(usize::MAX))
+    );
+  }
+
+  #[test]
+  fn detects_overlapping_blocks() {
+    let mut heap = Heap::new(64);
+    heap.alloc(16).unwrap();
+    heap.free_blocks.insert(16, 8);
+
+    assert!(!heap.is_consistent());
+  }
+}
@@ -0,0 +1,231 @@
+use svm_lang::{
+  opcodes::{BinaryOp, OpCode, Overflow},
+  Type, Value,
+};
+
+use super::VmErrorKind;
+
+/// Operand of `PUSH`, `PUSH_BYTES` and `MOV`, or an entry of the constant pool of the program,
+/// stored already encoded for the stack.
+#[derive(Debug, Clone)]
+pub(crate) struct Constant {
+  pub bytes: Box<[u8]>,
+  /// Type recorded by the checked stack, or `None` for raw bytes.
+  pub item_type: Option<Type>,
+}
+
+/// An [`OpCode`] decoded for execution.
+///
+/// Operands that own memory live in [`Code::constants`] and are referenced by index, so an
+/// instruction is small and `Copy` and can be dispatched without cloning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Instruction {
+  NoOperation,
+  Halt,
+  Move(u8, u32),
+  Register(u8, Type),
+  ProgramCounter,
+  StackPointer,
+  MoveStackPointer(isize),
+  Push(u32),
+  PushBytes(u32),
+  /// Pushes `count` constants starting at `first`.
+  PushAll {
+    first: u32,
+    count: u32,
+  },
+  Pop(Type, Option<u8>),
+  Copy(Type),
+  Increment(Type, Overflow),
+  Decrement(Type, Overflow),
+  Add(Type, Overflow),
+  Subtraction(Type, Overflow),
+  Multiply(Type, Overflow),
+  Divide(Type, Overflow),
+  Modulo(Type, Overflow),
+  Negative(Type, Overflow),
+  Power(Type, Overflow),
+  And(Type),
+  Or(Type),
+  XOr(Type),
+  Not(Type),
+  ShiftLeft(Type, Overflow),
+  ShiftRight(Type, Overflow),
+  Equals(Type),
+  NotEquals(Type),
+  GreaterThan(Type),
+  GreaterThanOrEqual(Type),
+  LessThan(Type),
+  LessThanOrEqual(Type),
+  RegisterOp(BinaryOp, Overflow, Type, u8, u8, u8),
+  Cast(Type, Type, Overflow),
+  Concat,
+  Length,
+  CharCount,
+  Slice,
+  CharCode,
+  FromCharCode,
+  FramePointer,
+  LocalGet(Type, isize),
+  LocalSet(Type, isize),
+  /// Pushes the constant at this index of [`Code::constants`], which starts with the constant
+  /// pool of the program.
+  PushConstant(u32),
+  GlobalGet(Type, usize),
+  GlobalSet(Type, usize),
+  Jump(usize),
+  JumpIfZero(usize),
+  JumpIfNotZero(usize),
+  Goto,
+  GotoIfZero,
+  GotoIfNotZero,
+  External(usize),
+  Call(usize),
+  Return,
+  Alloc,
+  Free,
+  Load(Type),
+  Store(Type),
+  Spawn(usize),
+  Yield(Type),
+  Resume,
+  Send(Type),
+  Receive(Type),
+  ProcessId,
+  Try(usize),
+  EndTry,
+  Throw(Type),
+}
+
+/// The instructions of a program together with their constant pool.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Code {
+  pub instructions: Vec<Instruction>,
+  pub constants: Vec<Constant>,
+  /// Mnemonic of each instruction, for the profiler.
+  pub mnemonics: Vec<&'static str>,
+  /// Gas cost of each instruction, filled by the VM from its gas table.
+  pub costs: Vec<u64>,
+}
+
+impl Code {
+  /// Decodes `opcodes`, encoding the constant pool `constants` once so that `CONST` does not
+  /// have to. Fails if `CONST` refers to a constant that does not exist.
+  pub fn decode(opcodes: &[OpCode], constants: &[Value]) -> Result<Self, VmErrorKind> {
+    let mut code = Code::default();
+
+    for value in constants {
+      code.constant(value.to_bytes(), Some(value.data_type()));
+    }
+
+    if let Some(index) = opcodes.iter().find_map(|op| match op {
+      OpCode::PushConstant(index) if *index >= constants.len() => Some(*index),
+      _ => None,
+    }) {
+      return Err(VmErrorKind::UnknownConstant(index));
+    }
+
+    code.instructions = opcodes.iter().map(|op| code.decode_opcode(op)).collect();
+    code.mnemonics = opcodes.iter().map(|op| op.mnemonic()).collect();
+
+    return Ok(code);
+  }
+
+  fn constant(&mut self, bytes: Vec<u8>, item_type: Option<Type>) -> u32 {
+    self.constants.push(Constant {
+      bytes: bytes.into_boxed_slice(),
+      item_type,
+    });
+
+    return (self.constants.len() - 1) as u32;
+  }
+
+  fn decode_opcode(&mut self, op: &OpCode) -> Instruction {
+    match op {
+      OpCode::NoOperation => Instruction::NoOperation,
+      OpCode::Halt => Instruction::Halt,
+      OpCode::Move(reg, value) => Instruction::Move(*reg, self.constant(value.to_bytes(), None)),
+      OpCode::Register(reg, t) => Instruction::Register(*reg, *t),
+      OpCode::ProgramCounter => Instruction::ProgramCounter,
+      OpCode::StackPointer => Instruction::StackPointer,
+      OpCode::MoveStackPointer(offset) => Instruction::MoveStackPointer(*offset),
+      OpCode::Push(value) => {
+        Instruction::Push(self.constant(value.to_bytes(), Some(value.data_type())))
+      }
+      OpCode::PushBytes(bytes) => Instruction::PushBytes(self.constant(bytes.clone(), None)),
+      OpCode::PushAll(values) => {
+        let first = self.constants.len() as u32;
+
+        for value in values {
+          self.constant(value.to_bytes(), Some(value.data_type()));
+        }
+
+        Instruction::PushAll {
+          first,
+          count: values.len() as u32,
+        }
+      }
+      OpCode::Pop(t, reg) => Instruction::Pop(*t, *reg),
+      OpCode::Copy(t) => Instruction::Copy(*t),
+      OpCode::Increment(t, overflow) => Instruction::Increment(*t, *overflow),
+      OpCode::Decrement(t, overflow) => Instruction::Decrement(*t, *overflow),
+      OpCode::Add(t, overflow) => Instruction::Add(*t, *overflow),
+      OpCode::Subtraction(t, overflow) => Instruction::Subtraction(*t, *overflow),
+      OpCode::Multiply(t, overflow) => Instruction::Multiply(*t, *overflow),
+      OpCode::Divide(t, overflow) => Instruction::Divide(*t, *overflow),
+      OpCode::Modulo(t, overflow) => Instruction::Modulo(*t, *overflow),
+      OpCode::Negative(t, overflow) => Instruction::Negative(*t, *overflow),
+      OpCode::Power(t, overflow) => Instruction::Power(*t, *overflow),
+      OpCode::And(t) => Instruction::And(*t),
+      OpCode::Or(t) => Instruction::Or(*t),
+      OpCode::XOr(t) => Instruction::XOr(*t),
+      OpCode::Not(t) => Instruction::Not(*t),
+      OpCode::ShiftLeft(t, overflow) => Instruction::ShiftLeft(*t, *overflow),
+      OpCode::ShiftRight(t, overflow) => Instruction::ShiftRight(*t, *overflow),
+      OpCode::Equals(t) => Instruction::Equals(*t),
+      OpCode::NotEquals(t) => Instruction::NotEquals(*t),
+      OpCode::GreaterThan(t) => Instruction::GreaterThan(*t),
+      OpCode::GreaterThanOrEqual(t) => Instruction::GreaterThanOrEqual(*t),
+      OpCode::LessThan(t) => Instruction::LessThan(*t),
+      OpCode::LessThanOrEqual(t) => Instruction::LessThanOrEqual(*t),
+      OpCode::RegisterOp(op, overflow, t, lhs, rhs, dst) => {
+        Instruction::RegisterOp(*op, *overflow, *t, *lhs, *rhs, *dst)
+      }
+      OpCode::Cast(from, to, overflow) => Instruction::Cast(*from, *to, *overflow),
+      OpCode::Concat => Instruction::Concat,
+      OpCode::Length => Instruction::Length,
+      OpCode::CharCount => Instruction::CharCount,
+      OpCode::Slice => Instruction::Slice,
+      OpCode::CharCode => Instruction::CharCode,
+      OpCode::FromCharCode => Instruction::FromCharCode,
+      OpCode::FramePointer => Instruction::FramePointer,
+      OpCode::LocalGet(t, offset) => Instruction::LocalGet(*t, *offset),
+      OpCode::LocalSet(t, offset) => Instruction::LocalSet(*t, *offset),
+      OpCode::PushConstant(index) => Instruction::PushConstant(*index as u32),
+      OpCode::GlobalGet(t, index) => Instruction::GlobalGet(*t, *index),
+      OpCode::GlobalSet(t, index) => Instruction::GlobalSet(*t, *index),
+      OpCode::Jump(addr) => Instruction::Jump(*addr),
+      OpCode::JumpIfZero(addr) => Instruction::JumpIfZero(*addr),
+      OpCode::JumpIfNotZero(addr) => Instruction::JumpIfNotZero(*addr),
+      OpCode::Goto => Instruction::Goto,
+      OpCode::GotoIfZero => Instruction::GotoIfZero,
+      OpCode::GotoIfNotZero => Instruction::GotoIfNotZero,
+      OpCode::External(import) => Instruction::External(*import),
+      OpCode::Call(addr) => Instruction::Call(*addr),
+      OpCode::Return => Instruction::Return,
+      OpCode::Alloc => Instruction::Alloc,
+      OpCode::Free => Instruction::Free,
+      OpCode::Load(t) => Instruction::Load(*t),
+      OpCode::Store(t) => Instruction::Store(*t),
+      OpCode::Spawn(addr) => Instruction::Spawn(*addr),
+      OpCode::Yield(t) => Instruction::Yield(*t),
+      OpCode::Resume => Instruction::Resume,
+      OpCode::Send(t) => Instruction::Send(*t),
+      OpCode::Receive(t) => Instruction::Receive(*t),
+      OpCode::ProcessId => Instruction::ProcessId,
+      OpCode::Try(addr) => Instruction::Try(*addr),
+      OpCode::EndTry => Instruction::EndTry,
+      OpCode::Throw(t) => Instruction::Throw(*t),
+    }
+  }
+}
@@ -1,5 +1,32 @@
+mod arithmetic;
+mod cancel;
+mod cast;
+mod coroutine;
+mod error;
+mod exception;
+mod externs;
+mod frame;
+mod gas;
+mod heap;
+mod instruction;
+mod profiler;
+mod scheduler;
+mod snapshot;
 mod stack;
-pub mod util;
+mod strings;
+mod trace;
 mod vm;
+pub use cancel::*;
+pub use coroutine::*;
+pub use error::*;
+pub use exception::*;
+pub use externs::*;
+pub use frame::*;
+pub use gas::*;
+pub use heap::*;
+pub use profiler::*;
+pub use scheduler::*;
+pub use snapshot::*;
 pub use stack::*;
+pub use trace::*;
 pub use vm::*;
@@ -0,0 +1,189 @@
+use std::{collections::HashMap, fmt::Write, time::Duration};
+
+use svm_lang::Program;
+
+use super::Frame;
+
+/// Name of the region before the first label.
+pub const ENTRY_REGION: &str = "_start";
+
+const HOT_PC_COUNT: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExternProfile {
+  pub calls: u64,
+  pub total: Duration,
+}
+
+/// Execution counters collected while a VM runs.
+///
+/// Each executed instruction counts as one sample.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+  pub samples: u64,
+  pub pc_counts: HashMap<usize, u64>,
+  pub opcode_counts: HashMap<&'static str, u64>,
+  /// Extern timings, by index in the program imports.
+  pub extern_profiles: HashMap<usize, ExternProfile>,
+  /// Samples keyed by the call sites of the active frames followed by the PC.
+  stacks: HashMap<Vec<usize>, u64>,
+}
+
+impl Profiler {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn record(&mut self, pc: usize, mnemonic: &'static str, frames: &[Frame]) {
+    self.samples += 1;
+    *self.pc_counts.entry(pc).or_default() += 1;
+    *self.opcode_counts.entry(mnemonic).or_default() += 1;
+
+    let mut stack = Vec::with_capacity(frames.len() + 1);
+
+    // `return_pc` points right after the `CALL`.
+    stack.extend(frames.iter().map(|frame| frame.return_pc - 1));
+    stack.push(pc);
+
+    *self.stacks.entry(stack).or_default() += 1;
+  }
+
+  pub fn record_extern(&mut self, import: usize, elapsed: Duration) {
+    let profile = self.extern_profiles.entry(import).or_default();
+
+    profile.calls += 1;
+    profile.total += elapsed;
+  }
+
+  /// Samples per label-delimited region, highest first.
+  pub fn region_counts(&self, program: &Program) -> Vec<(String, u64)> {
+    let mut regions: HashMap<String, u64> = HashMap::new();
+
+    for (pc, count) in &self.pc_counts {
+      *regions.entry(region_name(program, *pc)).or_default() += count;
+    }
+
+    return sorted_by_count(regions);
+  }
+
+  /// Folded stacks (`outer;inner count` per line), as accepted by flamegraph tools.
+  pub fn folded_stacks(&self, program: &Program) -> String {
+    let mut folded: HashMap<String, u64> = HashMap::new();
+
+    for (stack, count) in &self.stacks {
+      let names = stack
+        .iter()
+        .map(|pc| region_name(program, *pc))
+        .collect::<Vec<_>>()
+        .join(";");
+
+      *folded.entry(names).or_default() += count;
+    }
+
+    let mut lines = folded.into_iter().collect::<Vec<_>>();
+
+    lines.sort();
+
+    return lines
+      .iter()
+      .map(|(stack, count)| format!("{} {}\n", stack, count))
+      .collect();
+  }
+
+  pub fn report(&self, program: &Program) -> String {
+    let mut report = String::new();
+
+    let _ = writeln!(report, "Instructions executed: {}", self.samples);
+
+    let _ = writeln!(report, "\nBy region:");
+    for (region, count) in self.region_counts(program) {
+      let _ = writeln!(
+        report,
+        "  {:>12} {:>6.2}%  {}",
+        count,
+        self.percent(count),
+        region
+      );
+    }
+
+    let _ = writeln!(report, "\nBy opcode:");
+    let opcodes = self
+      .opcode_counts
+      .iter()
+      .map(|(name, count)| (name.to_string(), *count));
+    for (name, count) in sorted_by_count(opcodes) {
+      let _ = writeln!(
+        report,
+        "  {:>12} {:>6.2}%  {}",
+        count,
+        self.percent(count),
+        name
+      );
+    }
+
+    let _ = writeln!(report, "\nHot instructions:");
+    let mut pcs = self.pc_counts.iter().collect::<Vec<_>>();
+    pcs.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (pc, count) in pcs.into_iter().take(HOT_PC_COUNT) {
+      let op = program
+        .opcodes
+        .get(*pc)
+        .map(|op| op.to_string())
+        .unwrap_or_default();
+
+      let _ = writeln!(
+        report,
+        "  {:>12} {:>6.2}%  {:>6} [{}] {}",
+        count,
+        self.percent(*count),
+        pc,
+        region_name(program, *pc),
+        op
+      );
+    }
+
+    if !self.extern_profiles.is_empty() {
+      let _ = writeln!(report, "\nExterns:");
+      let mut externs = self.extern_profiles.iter().collect::<Vec<_>>();
+      externs.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+      for (import, profile) in externs {
+        let name = program
+          .imports
+          .get(*import)
+          .cloned()
+          .unwrap_or_else(|| import.to_string());
+
+        let _ = writeln!(
+          report,
+          "  EXT {:<20} calls={:<10} total={:?} avg={:?}",
+          name,
+          profile.calls,
+          profile.total,
+          profile.total / profile.calls.max(1) as u32
+        );
+      }
+    }
+
+    return report;
+  }
+
+  fn percent(&self, count: u64) -> f64 {
+    if self.samples == 0 {
+      return 0.0;
+    }
+
+    return count as f64 * 100.0 / self.samples as f64;
+  }
+}
+
+fn region_name(program: &Program, pc: usize) -> String {
+  return program.region_of(pc).unwrap_or(ENTRY_REGION).to_string();
+}
+
+fn sorted_by_count(counts: impl IntoIterator<Item = (String, u64)>) -> Vec<(String, u64)> {
+  let mut counts = counts.into_iter().collect::<Vec<_>>();
+
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+  return counts;
+}
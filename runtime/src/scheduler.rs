@@ -0,0 +1,290 @@
+use std::fmt::Display;
+
+use svm_lang::Value;
+
+use super::{StopReason, VmError, VM};
+
+pub const DEFAULT_QUANTUM: usize = 1000;
+
+/// A value sent with `SEND`, waiting in the outbox of the sender until it is delivered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+  pub to: usize,
+  pub value: Value,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProcessState {
+  Ready,
+  /// Waiting in `RECV` for a message.
+  Blocked,
+  /// Stopped for good, e.g. after `HALT` or at the end of the program.
+  Done(StopReason),
+}
+
+pub struct Process {
+  pub vm: VM,
+  pub state: ProcessState,
+}
+
+#[derive(Debug)]
+pub enum SchedulerError {
+  /// Every unfinished process is waiting for a message.
+  Deadlock(Vec<usize>),
+  ProcessFailed {
+    pid: usize,
+    error: VmError,
+  },
+  UnknownProcess {
+    from: usize,
+    to: usize,
+  },
+}
+
+impl Display for SchedulerError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SchedulerError::Deadlock(pids) => write!(
+        f,
+        "Deadlock: processes {} are all waiting for messages",
+        pids
+          .iter()
+          .map(|pid| pid.to_string())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      SchedulerError::ProcessFailed { pid, error } => write!(f, "Process {pid} failed: {error}"),
+      SchedulerError::UnknownProcess { from, to } => {
+        write!(f, "Process {from} sent a message to unknown process {to}")
+      }
+    }
+  }
+}
+
+impl std::error::Error for SchedulerError {}
+
+/// Runs many VMs on the current thread, switching between them every `quantum` instructions.
+///
+/// Each VM is a process identified by its index. Messages sent with `SEND` are delivered to the
+/// mailbox of the receiver at the end of the sender's time slice.
+pub struct Scheduler {
+  pub processes: Vec<Process>,
+  pub quantum: usize,
+}
+
+impl Scheduler {
+  pub fn new(quantum: usize) -> Self {
+    Self {
+      processes: Vec::new(),
+      quantum: quantum.max(1),
+    }
+  }
+
+  /// Adds `vm` as a new process and returns its pid.
+  pub fn spawn(&mut self, mut vm: VM) -> usize {
+    let pid = self.processes.len();
+
+    vm.pid = pid;
+
+    self.processes.push(Process {
+      vm,
+      state: ProcessState::Ready,
+    });
+
+    return pid;
+  }
+
+  /// Runs until every process is done. Stops at the first failing process.
+  pub fn run(&mut self) -> Result<(), SchedulerError> {
+    loop {
+      let mut ran = false;
+
+      for pid in 0..self.processes.len() {
+        if !matches!(self.processes[pid].state, ProcessState::Ready) {
+          continue;
+        }
+
+        ran = true;
+        self.run_slice(pid)?;
+        self.deliver(pid)?;
+      }
+
+      if ran {
+        continue;
+      }
+
+      let blocked = self
+        .processes
+        .iter()
+        .enumerate()
+        .filter(|(_, process)| matches!(process.state, ProcessState::Blocked))
+        .map(|(pid, _)| pid)
+        .collect::<Vec<_>>();
+
+      if blocked.is_empty() {
+        return Ok(());
+      }
+
+      return Err(SchedulerError::Deadlock(blocked));
+    }
+  }
+
+  fn run_slice(&mut self, pid: usize) -> Result<(), SchedulerError> {
+    let process = &mut self.processes[pid];
+
+    for _ in 0..self.quantum {
+      match process.vm.step() {
+        None => {}
+        Some(StopReason::Blocked) => {
+          process.state = ProcessState::Blocked;
+          break;
+        }
+        Some(StopReason::Error(error)) => {
+          process.state = ProcessState::Done(StopReason::Error(error.clone()));
+          return Err(SchedulerError::ProcessFailed { pid, error });
+        }
+        Some(reason) => {
+          process.state = ProcessState::Done(reason);
+          break;
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn deliver(&mut self, from: usize) -> Result<(), SchedulerError> {
+    let messages = std::mem::take(&mut self.processes[from].vm.outbox);
+
+    for message in messages {
+      let receiver = self
+        .processes
+        .get_mut(message.to)
+        .ok_or(SchedulerError::UnknownProcess {
+          from,
+          to: message.to,
+        })?;
+
+      receiver.vm.mailbox.push_back(message.value);
+
+      if matches!(receiver.state, ProcessState::Blocked) {
+        receiver.state = ProcessState::Ready;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::{
+    opcodes::{OpCode, Overflow},
+    Program, Type,
+  };
+
+  use super::*;
+
+  fn process(opcodes: Vec<OpCode>, globals: Vec<Value>) -> VM {
+    let mut vm = VM::new();
+
+    vm.load(Program {
+      opcodes,
+      globals,
+      ..Program::empty()
+    })
+    .unwrap();
+
+    return vm;
+  }
+
+  /// Sends `value` to process 2 three times, one send per 10 instructions.
+  fn sender(value: u64) -> VM {
+    let opcodes = vec![
+      OpCode::Push(Value::Usize(2)),
+      OpCode::Push(Value::U64(value)),
+      OpCode::Send(Type::U64),
+      OpCode::GlobalGet(Type::U8, 0),
+      OpCode::Decrement(Type::U8, Overflow::Checked),
+      OpCode::Copy(Type::U8),
+      OpCode::GlobalSet(Type::U8, 0),
+      OpCode::Push(Value::U8(0)),
+      OpCode::Equals(Type::U8),
+      OpCode::JumpIfZero(0),
+      OpCode::Halt,
+    ];
+
+    return process(opcodes, vec![Value::U8(3)]);
+  }
+
+  /// Receives six values and appends each one as a decimal digit of global 0.
+  fn receiver() -> VM {
+    let opcodes = vec![
+      OpCode::GlobalGet(Type::U64, 0),
+      OpCode::Push(Value::U64(10)),
+      OpCode::Multiply(Type::U64, Overflow::Checked),
+      OpCode::Receive(Type::U64),
+      OpCode::Add(Type::U64, Overflow::Checked),
+      OpCode::GlobalSet(Type::U64, 0),
+      OpCode::GlobalGet(Type::U8, 1),
+      OpCode::Decrement(Type::U8, Overflow::Checked),
+      OpCode::Copy(Type::U8),
+      OpCode::GlobalSet(Type::U8, 1),
+      OpCode::Push(Value::U8(0)),
+      OpCode::Equals(Type::U8),
+      OpCode::JumpIfZero(0),
+      OpCode::Halt,
+    ];
+
+    return process(opcodes, vec![Value::U64(0), Value::U8(6)]);
+  }
+
+  #[test]
+  fn processes_take_turns_every_quantum() {
+    let mut scheduler = Scheduler::new(10);
+
+    scheduler.spawn(sender(1));
+    scheduler.spawn(sender(2));
+    scheduler.spawn(receiver());
+    scheduler.run().unwrap();
+
+    // Running each sender to completion would give 111222.
+    assert_eq!(scheduler.processes[2].vm.globals[0], Value::U64(121212));
+    assert!(scheduler
+      .processes
+      .iter()
+      .all(|process| matches!(process.state, ProcessState::Done(StopReason::Halted))));
+  }
+
+  #[test]
+  fn reports_a_deadlock_when_every_process_waits() {
+    let mut scheduler = Scheduler::new(10);
+
+    scheduler.spawn(process(vec![OpCode::Receive(Type::U8)], vec![]));
+    scheduler.spawn(process(vec![OpCode::Halt], vec![]));
+
+    assert!(matches!(
+      scheduler.run(),
+      Err(SchedulerError::Deadlock(pids)) if pids == vec![0]
+    ));
+  }
+
+  #[test]
+  fn reports_messages_to_unknown_processes() {
+    let mut scheduler = Scheduler::new(10);
+
+    scheduler.spawn(process(
+      vec![
+        OpCode::Push(Value::Usize(5)),
+        OpCode::Push(Value::U8(1)),
+        OpCode::Send(Type::U8),
+      ],
+      vec![],
+    ));
+
+    assert!(matches!(
+      scheduler.run(),
+      Err(SchedulerError::UnknownProcess { from: 0, to: 5 })
+    ));
+  }
+}
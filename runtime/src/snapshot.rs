@@ -0,0 +1,707 @@
+use std::{collections::BTreeMap, fmt::Display, fs, io, path::Path};
+
+use svm_lang::{DecodeError, Program, Type, Value};
+
+use super::{
+  Context, Coroutine, CoroutineState, Frame, Handler, Heap, Message, Resumer, Stack, VmErrorKind,
+  MAX_REGISTER_COUNT, VM,
+};
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"SVMS";
+pub const SNAPSHOT_VERSION: u32 = 8;
+
+/// Magic, version, payload length and checksum.
+const HEADER_LEN: usize = 4 + 4 + 8 + 8;
+const USIZE_LEN: usize = std::mem::size_of::<usize>();
+
+#[derive(Debug)]
+pub enum SnapshotError {
+  Io(io::Error),
+  InvalidMagic,
+  UnsupportedVersion(u32),
+  ChecksumMismatch,
+  Truncated,
+  InvalidData(String),
+  /// The saved program is not valid bytecode.
+  Program(DecodeError),
+  /// The program imports externs that are not registered in the restoring VM.
+  Link(VmErrorKind),
+}
+
+impl Display for SnapshotError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SnapshotError::Io(error) => write!(f, "{error}"),
+      SnapshotError::InvalidMagic => write!(f, "Not a snapshot file"),
+      SnapshotError::UnsupportedVersion(version) => {
+        write!(f, "Unsupported snapshot version {version}")
+      }
+      SnapshotError::ChecksumMismatch => write!(f, "Snapshot checksum mismatch"),
+      SnapshotError::Truncated => write!(f, "Snapshot is truncated"),
+      SnapshotError::InvalidData(message) => write!(f, "Invalid snapshot: {message}"),
+      SnapshotError::Program(error) => write!(f, "Invalid snapshot: program has {error}"),
+      SnapshotError::Link(error) => write!(f, "{error}"),
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+  fn from(error: io::Error) -> Self {
+    SnapshotError::Io(error)
+  }
+}
+
+/// Saving and restoring the execution state of a paused VM.
+///
+/// A snapshot holds the program (with its labels), PC, stack, call frames, exception handlers,
+/// heap, globals, coroutines, mailboxes and remaining gas. Externs, tracer, profiler,
+/// breakpoints and deadlines belong to the host and are not saved; externs must be registered
+/// again before restoring so the program can be re-linked.
+impl VM {
+  pub fn snapshot(&self) -> Vec<u8> {
+    let mut payload = Writer::default();
+
+    payload.bytes(&self.program().to_bytes());
+
+    let mut labels = self.program().labels.iter().collect::<Vec<_>>();
+    labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+    payload.usize(labels.len());
+    for (name, address) in labels {
+      payload.usize(*address);
+      payload.bytes(name.as_bytes());
+    }
+
+    payload.context(self.pc, &self.stack, &self.frames, &self.handlers);
+
+    payload.usize(self.coroutines.len());
+    for coroutine in &self.coroutines {
+      payload.u8(match coroutine.state {
+        CoroutineState::Suspended => 0,
+        CoroutineState::Running => 1,
+        CoroutineState::Finished => 2,
+      });
+
+      let context = &coroutine.context;
+      payload.context(
+        context.pc,
+        &context.stack,
+        &context.frames,
+        &context.handlers,
+      );
+    }
+
+    payload.usize(self.resumers.len());
+    for resumer in &self.resumers {
+      payload.usize(resumer.coroutine);
+      payload.u8(resumer.host as u8);
+
+      let context = &resumer.context;
+      payload.context(
+        context.pc,
+        &context.stack,
+        &context.frames,
+        &context.handlers,
+      );
+    }
+
+    payload.usize(self.heap.max_size);
+    payload.bytes(&self.heap.memory);
+    payload.blocks(&self.heap.allocations);
+    payload.blocks(&self.heap.free_blocks);
+
+    payload.usize(self.pid);
+    payload.usize(self.mailbox.len());
+    self.mailbox.iter().for_each(|value| payload.value(value));
+    payload.usize(self.outbox.len());
+    for message in &self.outbox {
+      payload.usize(message.to);
+      payload.value(&message.value);
+    }
+
+    payload.usize(self.globals.len());
+    self.globals.iter().for_each(|value| payload.value(value));
+
+    match self.remaining_gas() {
+      Some(gas) => {
+        payload.u8(1);
+        payload.u64(gas);
+      }
+      None => payload.u8(0),
+    }
+
+    let payload = payload.0;
+    let mut snapshot = Vec::with_capacity(HEADER_LEN + payload.len());
+
+    snapshot.extend(SNAPSHOT_MAGIC);
+    snapshot.extend(SNAPSHOT_VERSION.to_be_bytes());
+    snapshot.extend((payload.len() as u64).to_be_bytes());
+    snapshot.extend(checksum(&payload).to_be_bytes());
+    snapshot.extend(payload);
+
+    return snapshot;
+  }
+
+  /// Replaces the execution state with the one in `snapshot`. The VM is left untouched if the
+  /// snapshot is invalid or the program cannot be linked.
+  pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), SnapshotError> {
+    if snapshot.len() < HEADER_LEN {
+      return Err(SnapshotError::Truncated);
+    }
+
+    let mut header = Reader::new(&snapshot[..HEADER_LEN]);
+
+    if header.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+      return Err(SnapshotError::InvalidMagic);
+    }
+
+    let version = u32::from_be_bytes(header.take(4)?.try_into().unwrap());
+
+    if version != SNAPSHOT_VERSION {
+      return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let payload_len = header.u64()? as usize;
+    let expected_checksum = header.u64()?;
+    let payload = &snapshot[HEADER_LEN..];
+
+    if payload.len() < payload_len {
+      return Err(SnapshotError::Truncated);
+    }
+
+    if payload.len() > payload_len {
+      return Err(SnapshotError::InvalidData(String::from(
+        "unexpected trailing data",
+      )));
+    }
+
+    if checksum(payload) != expected_checksum {
+      return Err(SnapshotError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader::new(payload);
+
+    let mut program =
+      Program::try_from_bytes(&mut reader.bytes()?.to_vec()).map_err(SnapshotError::Program)?;
+
+    for _ in 0..reader.usize()? {
+      let address = reader.usize()?;
+      let name = String::from_utf8(reader.bytes()?.to_vec())
+        .map_err(|_| SnapshotError::InvalidData(String::from("label is not valid UTF-8")))?;
+
+      program.labels.insert(name, address);
+    }
+
+    let mut main = reader.context()?;
+
+    let mut coroutines = Vec::new();
+    for _ in 0..reader.usize()? {
+      let state = match reader.u8()? {
+        0 => CoroutineState::Suspended,
+        1 => CoroutineState::Running,
+        2 => CoroutineState::Finished,
+        state => {
+          return Err(SnapshotError::InvalidData(format!(
+            "unknown coroutine state {state}"
+          )))
+        }
+      };
+
+      coroutines.push(Coroutine {
+        state,
+        context: reader.context()?,
+      });
+    }
+
+    let mut resumers = Vec::new();
+    for _ in 0..reader.usize()? {
+      let coroutine = reader.usize()?;
+
+      if coroutine >= coroutines.len() {
+        return Err(SnapshotError::InvalidData(format!(
+          "unknown coroutine {coroutine}"
+        )));
+      }
+
+      resumers.push(Resumer {
+        coroutine,
+        host: reader.u8()? != 0,
+        context: reader.context()?,
+      });
+    }
+
+    let mut heap = Heap::new(reader.usize()?);
+    heap.memory = reader.bytes()?.to_vec();
+    heap.allocations = reader.blocks()?;
+    heap.free_blocks = reader.blocks()?;
+
+    if !heap.is_consistent() {
+      return Err(SnapshotError::InvalidData(String::from(
+        "heap blocks out of bounds",
+      )));
+    }
+
+    let pid = reader.usize()?;
+    let mailbox = (0..reader.usize()?)
+      .map(|_| reader.value())
+      .collect::<Result<_, _>>()?;
+    let outbox = (0..reader.usize()?)
+      .map(|_| {
+        Ok(Message {
+          to: reader.usize()?,
+          value: reader.value()?,
+        })
+      })
+      .collect::<Result<_, SnapshotError>>()?;
+
+    let globals = (0..reader.usize()?)
+      .map(|_| reader.value())
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let types_match = globals.len() == program.globals.len()
+      && globals
+        .iter()
+        .zip(&program.globals)
+        .all(|(value, initial)| value.data_type() == initial.data_type());
+
+    if !types_match {
+      return Err(SnapshotError::InvalidData(String::from(
+        "globals do not match the program",
+      )));
+    }
+
+    let gas = match reader.u8()? {
+      0 => None,
+      _ => Some(reader.u64()?),
+    };
+
+    if !reader.is_empty() {
+      return Err(SnapshotError::InvalidData(String::from(
+        "unexpected trailing data",
+      )));
+    }
+
+    self.load(program).map_err(SnapshotError::Link)?;
+
+    // Value types are not saved, so only values pushed after the restore are checked.
+    let checked = self.stack.is_checked();
+    main.stack.set_checked(checked);
+    coroutines
+      .iter_mut()
+      .map(|coroutine| &mut coroutine.context)
+      .chain(resumers.iter_mut().map(|resumer| &mut resumer.context))
+      .for_each(|context| context.stack.set_checked(checked));
+
+    self.pc = main.pc;
+    self.stack = main.stack;
+    self.frames = main.frames;
+    self.handlers = main.handlers;
+    self.coroutines = coroutines;
+    self.resumers = resumers;
+    self.host_resumed = None;
+    self.heap = heap;
+    self.pid = pid;
+    self.mailbox = mailbox;
+    self.outbox = outbox;
+    self.globals = globals;
+    self.set_gas(gas);
+    self.running = false;
+
+    Ok(())
+  }
+
+  pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+    fs::write(path, self.snapshot())?;
+
+    Ok(())
+  }
+
+  pub fn load_snapshot<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SnapshotError> {
+    self.restore(&fs::read(path)?)
+  }
+}
+
+/// 64-bit FNV-1a.
+fn checksum(bytes: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf29ce484222325;
+
+  for byte in bytes {
+    hash ^= *byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+
+  return hash;
+}
+
+/// Integers are stored as big-endian `u64` regardless of the platform `usize`.
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn u8(&mut self, value: u8) {
+    self.0.push(value);
+  }
+
+  fn u64(&mut self, value: u64) {
+    self.0.extend(value.to_be_bytes());
+  }
+
+  fn usize(&mut self, value: usize) {
+    self.u64(value as u64);
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) {
+    self.usize(bytes.len());
+    self.0.extend(bytes);
+  }
+
+  fn usizes(&mut self, values: &[usize]) {
+    self.usize(values.len());
+    values.iter().for_each(|value| self.usize(*value));
+  }
+
+  fn context(&mut self, pc: usize, stack: &Stack, frames: &[Frame], handlers: &[Handler]) {
+    self.usize(pc);
+
+    self.usize(stack.size);
+    self.usize(stack.sp);
+    self.bytes(&stack.data);
+    self.usizes(&stack.saves);
+    self.bytes(&stack.registers);
+
+    self.usize(frames.len());
+    for frame in frames {
+      self.usize(frame.return_pc);
+      self.usize(frame.sp);
+    }
+
+    self.usize(handlers.len());
+    for handler in handlers {
+      self.usize(handler.catch_pc);
+      self.usize(handler.sp);
+      self.usize(handler.frames);
+    }
+  }
+
+  fn value(&mut self, value: &Value) {
+    self.u8(value.data_type().to_bytes()[0]);
+    self.bytes(&value.to_bytes());
+  }
+
+  fn blocks(&mut self, blocks: &BTreeMap<usize, usize>) {
+    self.usize(blocks.len());
+    for (addr, size) in blocks {
+      self.usize(*addr);
+      self.usize(*size);
+    }
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(bytes: &'a [u8]) -> Self {
+    Self { bytes, position: 0 }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.position == self.bytes.len()
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+    let end = self
+      .position
+      .checked_add(len)
+      .filter(|end| *end <= self.bytes.len())
+      .ok_or(SnapshotError::Truncated)?;
+
+    let bytes = &self.bytes[self.position..end];
+
+    self.position = end;
+
+    return Ok(bytes);
+  }
+
+  fn u8(&mut self) -> Result<u8, SnapshotError> {
+    return Ok(self.take(1)?[0]);
+  }
+
+  fn u64(&mut self) -> Result<u64, SnapshotError> {
+    return Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()));
+  }
+
+  fn usize(&mut self) -> Result<usize, SnapshotError> {
+    return usize::try_from(self.u64()?)
+      .map_err(|_| SnapshotError::InvalidData(String::from("value does not fit in usize")));
+  }
+
+  fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+    let len = self.usize()?;
+
+    return self.take(len);
+  }
+
+  fn usizes(&mut self) -> Result<Vec<usize>, SnapshotError> {
+    return (0..self.usize()?).map(|_| self.usize()).collect();
+  }
+
+  fn context(&mut self) -> Result<Context, SnapshotError> {
+    let pc = self.usize()?;
+
+    let size = self.usize()?;
+    let sp = self.usize()?;
+    let data = self.bytes()?;
+
+    // The whole buffer is saved, so the size is bounded by the payload that was just read.
+    if data.len() != size {
+      return Err(SnapshotError::InvalidData(String::from(
+        "stack data does not match the stack size",
+      )));
+    }
+
+    if sp > size {
+      return Err(SnapshotError::InvalidData(String::from(
+        "stack pointer out of bounds",
+      )));
+    }
+
+    let mut stack = Stack::new(size);
+    stack.sp = sp;
+    stack.data.copy_from_slice(data);
+    stack.saves = self.usizes()?;
+    stack.registers = self.bytes()?.to_vec();
+
+    // Same bound as `Stack::with_registers`, which builds the register file of a new VM.
+    if !stack.registers.len().is_multiple_of(USIZE_LEN)
+      || stack.register_count() > MAX_REGISTER_COUNT
+    {
+      return Err(SnapshotError::InvalidData(String::from(
+        "invalid register file",
+      )));
+    }
+
+    if stack.saves.iter().any(|sp| *sp > stack.size) {
+      return Err(SnapshotError::InvalidData(String::from(
+        "stack pointer out of bounds",
+      )));
+    }
+
+    let mut frames = Vec::new();
+    for _ in 0..self.usize()? {
+      frames.push(Frame {
+        return_pc: self.usize()?,
+        sp: self.usize()?,
+      });
+    }
+
+    if frames.iter().any(|frame| frame.sp > stack.size) {
+      return Err(SnapshotError::InvalidData(String::from(
+        "frame stack pointer out of bounds",
+      )));
+    }
+
+    let mut handlers = Vec::new();
+    for _ in 0..self.usize()? {
+      handlers.push(Handler {
+        catch_pc: self.usize()?,
+        sp: self.usize()?,
+        frames: self.usize()?,
+      });
+    }
+
+    if handlers.iter().any(|handler| handler.sp > stack.size) {
+      return Err(SnapshotError::InvalidData(String::from(
+        "handler stack pointer out of bounds",
+      )));
+    }
+
+    return Ok(Context {
+      pc,
+      stack,
+      frames,
+      handlers,
+    });
+  }
+
+  fn value(&mut self) -> Result<Value, SnapshotError> {
+    let code = self.u8()?;
+    let value_type = Type::try_from_u8(code)
+      .ok_or_else(|| SnapshotError::InvalidData(format!("unknown type code {code}")))?;
+    let bytes = self.bytes()?;
+
+    return Value::try_from_stack_bytes(bytes.to_vec(), &value_type)
+      .ok_or_else(|| SnapshotError::InvalidData(format!("invalid {} value", value_type)));
+  }
+
+  fn blocks(&mut self) -> Result<BTreeMap<usize, usize>, SnapshotError> {
+    return (0..self.usize()?)
+      .map(|_| Ok((self.usize()?, self.usize()?)))
+      .collect();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::opcodes::{OpCode, Overflow};
+
+  use super::*;
+  use crate::StopReason;
+
+  fn program() -> Program {
+    return Program {
+      opcodes: vec![
+        OpCode::Push(Value::U64(1)),
+        OpCode::Call(4),
+        OpCode::Push(Value::U8(9)),
+        OpCode::Halt,
+        OpCode::Increment(Type::U64, Overflow::Checked),
+        OpCode::GlobalGet(Type::U64, 0),
+        OpCode::Increment(Type::U64, Overflow::Checked),
+        OpCode::GlobalSet(Type::U64, 0),
+        OpCode::Increment(Type::U64, Overflow::Checked),
+        OpCode::Return,
+      ],
+      globals: vec![Value::U64(40)],
+      ..Program::empty()
+    };
+  }
+
+  /// VM paused inside the function, after the first `INC`.
+  fn paused() -> VM {
+    let mut vm = VM::new();
+    vm.load(program()).unwrap();
+
+    for _ in 0..3 {
+      assert!(vm.step().is_none());
+    }
+
+    vm.heap.alloc(16).unwrap();
+    vm.mailbox.push_back(Value::String(String::from("hi")));
+
+    return vm;
+  }
+
+  fn seal(payload: Vec<u8>) -> Vec<u8> {
+    return SNAPSHOT_MAGIC
+      .iter()
+      .copied()
+      .chain(SNAPSHOT_VERSION.to_be_bytes())
+      .chain((payload.len() as u64).to_be_bytes())
+      .chain(checksum(&payload).to_be_bytes())
+      .chain(payload)
+      .collect();
+  }
+
+  #[test]
+  fn restored_vm_continues_where_it_stopped() {
+    let snapshot = paused().snapshot();
+
+    let mut vm = VM::new();
+    vm.restore(&snapshot).unwrap();
+
+    assert_eq!(vm.pc, 5);
+    assert_eq!(vm.frames.len(), 1);
+    assert_eq!(vm.heap.allocations.len(), 1);
+    assert_eq!(vm.mailbox.len(), 1);
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.globals, vec![Value::U64(41)]);
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(9)));
+    assert_eq!(vm.stack.pop_value(&Type::U64), Ok(Value::U64(3)));
+  }
+
+  #[test]
+  fn snapshot_of_a_restored_vm_is_identical() {
+    let snapshot = paused().snapshot();
+
+    let mut vm = VM::new();
+    vm.restore(&snapshot).unwrap();
+
+    assert_eq!(vm.snapshot(), snapshot);
+  }
+
+  #[test]
+  fn rejects_corrupted_snapshots() {
+    let snapshot = paused().snapshot();
+    let mut vm = VM::new();
+
+    let mut flipped = snapshot.clone();
+    *flipped.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+      vm.restore(&flipped),
+      Err(SnapshotError::ChecksumMismatch)
+    ));
+
+    assert!(matches!(
+      vm.restore(&snapshot[..snapshot.len() - 1]),
+      Err(SnapshotError::Truncated)
+    ));
+
+    let mut version = snapshot.clone();
+    version[7] += 1;
+    assert!(matches!(
+      vm.restore(&version),
+      Err(SnapshotError::UnsupportedVersion(_))
+    ));
+
+    assert!(vm.program().opcodes.is_empty());
+  }
+
+  #[test]
+  fn rejects_an_invalid_program() {
+    let mut payload = Writer::default();
+    payload.bytes(b"not a program");
+
+    assert!(matches!(
+      VM::new().restore(&seal(payload.0)),
+      Err(SnapshotError::Program(DecodeError::MissingHeader))
+    ));
+  }
+
+  #[test]
+  fn rejects_a_stack_larger_than_its_data() {
+    let mut payload = Writer::default();
+    payload.bytes(&Program::empty().to_bytes());
+    payload.usize(0);
+    payload.usize(0);
+    payload.usize(usize::MAX / 2);
+    payload.usize(0);
+    payload.bytes(&[]);
+
+    assert!(matches!(
+      VM::new().restore(&seal(payload.0)),
+      Err(SnapshotError::InvalidData(_))
+    ));
+  }
+
+  #[test]
+  fn rejects_a_stack_pointer_past_the_stack() {
+    let mut payload = Writer::default();
+    payload.bytes(&Program::empty().to_bytes());
+    payload.usize(0);
+    payload.usize(0);
+    payload.usize(4);
+    payload.usize(5);
+    payload.bytes(&[0; 4]);
+
+    assert!(matches!(
+      VM::new().restore(&seal(payload.0)),
+      Err(SnapshotError::InvalidData(_))
+    ));
+  }
+
+  #[test]
+  fn rejects_heap_blocks_out_of_bounds() {
+    let mut vm = paused();
+    let addr = *vm.heap.allocations.keys().next().unwrap();
+    vm.heap.allocations.insert(addr, 1 << 20);
+
+    assert!(matches!(
+      VM::new().restore(&vm.snapshot()),
+      Err(SnapshotError::InvalidData(_))
+    ));
+  }
+}
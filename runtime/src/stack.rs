@@ -1,29 +1,110 @@
 const USIZE_LEN: usize = std::mem::size_of::<usize>();
 const ISIZE_LEN: usize = std::mem::size_of::<isize>();
 
+use std::fmt::Debug;
+
 use svm_lang::{Type, Value};
 
-use super::util::vm_panic;
+use super::VmErrorKind;
+
+/// Registers of a stack built with [`Stack::new`]: `%addr`, `%a`, `%b` and `%c`.
+pub const DEFAULT_REGISTER_COUNT: usize = 4;
+/// Upper bound of the register count of a stack, not the count itself: registers are addressed
+/// by a `u8` starting at 1, so [`Stack::with_registers`] accepts at most this many.
+pub const MAX_REGISTER_COUNT: usize = u8::MAX as usize;
 
-pub const MAX_REGISTER_COUNT: usize = 4;
+/// Type of a value pushed in checked mode, stored in `data[start..end]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackEntry {
+  pub start: usize,
+  pub end: usize,
+  pub item_type: Type,
+}
 
-#[derive(Debug)]
 pub struct Stack {
+  /// Preallocated to `size` bytes. Only `data[..sp]` holds live values; popping just moves
+  /// `sp`, so bytes above it keep their old contents until overwritten.
   pub data: Vec<u8>,
   pub saves: Vec<usize>,
   pub registers: Vec<u8>,
   pub size: usize,
   pub sp: usize,
+  /// Types of the pushed values, innermost last, or `None` when type checks are disabled.
+  /// Entries above `sp` are stale and dropped on the next push or pop.
+  types: Option<Vec<StackEntry>>,
 }
 
 impl Stack {
   pub fn new(size: usize) -> Self {
+    return Self::allocate(size, DEFAULT_REGISTER_COUNT);
+  }
+
+  /// Creates a stack with `count` registers. Fails with [`VmErrorKind::TooManyRegisters`] if
+  /// `count` is above [`MAX_REGISTER_COUNT`].
+  pub fn with_registers(size: usize, count: usize) -> Result<Self, VmErrorKind> {
+    if count > MAX_REGISTER_COUNT {
+      return Err(VmErrorKind::TooManyRegisters(count));
+    }
+
+    return Ok(Self::allocate(size, count));
+  }
+
+  /// Creates a stack with `count` registers, which must not be above [`MAX_REGISTER_COUNT`].
+  pub(crate) fn allocate(size: usize, count: usize) -> Self {
     Self {
-      data: vec![],
+      data: vec![0; size],
       saves: vec![],
-      registers: vec![0; USIZE_LEN * MAX_REGISTER_COUNT],
+      registers: vec![0; USIZE_LEN * count],
       size,
       sp: 0,
+      types: None,
+    }
+  }
+
+  /// Records the type of every value pushed so that pops and peeks with another type fail
+  /// with [`VmErrorKind::TypeMismatch`]. Bytes pushed without a type are not checked.
+  pub fn set_checked(&mut self, checked: bool) {
+    self.types = checked.then(Vec::new);
+  }
+
+  pub fn is_checked(&self) -> bool {
+    return self.types.is_some();
+  }
+
+  /// Typed values below `sp`, bottom first. Empty when type checks are disabled.
+  pub fn entries(&self) -> Vec<StackEntry> {
+    return self
+      .types
+      .iter()
+      .flatten()
+      .filter(|entry| entry.end <= self.sp)
+      .copied()
+      .collect();
+  }
+
+  fn check(&self, expected: &Type) -> Result<(), VmErrorKind> {
+    let Some(types) = &self.types else {
+      return Ok(());
+    };
+
+    let top = types.iter().rev().find(|entry| entry.start < self.sp);
+
+    match top {
+      Some(entry) if entry.end == self.sp && entry.item_type != *expected => {
+        Err(VmErrorKind::TypeMismatch {
+          expected: *expected,
+          actual: entry.item_type,
+        })
+      }
+      _ => Ok(()),
+    }
+  }
+
+  fn discard_types_above(&mut self, sp: usize) {
+    if let Some(types) = &mut self.types {
+      while types.last().is_some_and(|entry| entry.start >= sp) {
+        types.pop();
+      }
     }
   }
 
@@ -31,29 +112,29 @@ impl Stack {
     self.saves.push(self.sp);
   }
 
-  pub fn restore(&mut self) {
-    if self.saves.len() == 0 {
-      vm_panic("NoSavedStack", "No saved stack to restore!");
-    }
+  pub fn restore(&mut self) -> Result<(), VmErrorKind> {
+    self.sp = self.saves.pop().ok_or(VmErrorKind::NoSavedStack)?;
 
-    self.sp = self.saves.pop().unwrap();
+    Ok(())
   }
 
-  pub fn read_bytes(&mut self, size: usize) -> &[u8] {
+  pub fn read_bytes(&mut self, size: usize) -> Result<&[u8], VmErrorKind> {
     if self.sp < size {
-      vm_panic("StackUnderflow", "Cannot read from empty stack!");
+      return Err(VmErrorKind::StackUnderflow);
     }
 
     let range = self.sp - size..self.sp;
 
-    return &self.data[range];
+    return Ok(&self.data[range]);
   }
 
-  pub fn peek(&mut self, item_type: &Type) -> &[u8] {
+  pub fn peek(&mut self, item_type: &Type) -> Result<&[u8], VmErrorKind> {
     if self.sp == 0 {
-      vm_panic("StackUnderflow", "Cannot peek from empty stack!");
+      return Err(VmErrorKind::StackUnderflow);
     }
 
+    self.check(item_type)?;
+
     match item_type {
       Type::U8 => self.read_bytes(1),
       Type::U16 => self.read_bytes(2),
@@ -79,79 +160,148 @@ impl Stack {
           len += 1;
         }
 
-        let bytes = self.read_bytes(len);
-
-        return bytes;
+        return self.read_bytes(len);
       }
       Type::Bytes => {
-        let size = usize::from_le_bytes(self.read_bytes(USIZE_LEN).try_into().unwrap());
+        let size = usize::from_le_bytes(self.read_bytes(USIZE_LEN)?.try_into().unwrap());
         return self.read_bytes(size);
       }
     }
   }
 
-  pub fn peek_value(&mut self, item_type: &Type) -> Value {
-    return Value::from_stack_bytes(self.peek(item_type).to_vec(), item_type);
+  pub fn peek_value(&mut self, item_type: &Type) -> Result<Value, VmErrorKind> {
+    return decode(self.peek(item_type)?.to_vec(), item_type);
   }
 
-  pub fn push_value(&mut self, value: Value) {
-    self.push(value.to_bytes());
+  pub fn push_value(&mut self, value: Value) -> Result<(), VmErrorKind> {
+    self.push_typed(&value.to_bytes(), &value.data_type())
   }
 
-  pub fn push(&mut self, value: Vec<u8>) {
-    if self.sp >= self.size {
-      vm_panic("StackOverflow", "Maximum stack size exceeded!");
-    }
+  /// Pushes `value`, recording `item_type` for it in checked mode.
+  pub fn push_typed(&mut self, value: &[u8], item_type: &Type) -> Result<(), VmErrorKind> {
+    let start = self.sp;
 
-    let current_size = self.data.len();
-    let buffer_size = value.len();
-    let available_space = current_size - self.sp;
+    self.push(value)?;
 
-    if available_space < buffer_size {
-      self
-        .data
-        .resize(current_size + buffer_size - available_space, 0);
+    if self.types.is_some() {
+      self.discard_types_above(start);
+      self.types.as_mut().unwrap().push(StackEntry {
+        start,
+        end: self.sp,
+        item_type: *item_type,
+      });
     }
 
-    for i in 0..buffer_size {
-      self.data[self.sp + i] = value[i];
+    Ok(())
+  }
+
+  pub fn push(&mut self, value: &[u8]) -> Result<(), VmErrorKind> {
+    let end = self.sp + value.len();
+
+    if end > self.size {
+      return Err(VmErrorKind::StackOverflow);
     }
 
-    self.sp += buffer_size;
+    self.data[self.sp..end].copy_from_slice(value);
+    self.sp = end;
+
+    Ok(())
   }
 
-  pub fn pop(&mut self, item_type: &Type) -> Vec<u8> {
+  pub fn pop(&mut self, item_type: &Type) -> Result<Vec<u8>, VmErrorKind> {
     if self.sp == 0 {
-      vm_panic("StackUnderflow", "Cannot pop from empty stack!");
+      return Err(VmErrorKind::StackUnderflow);
     }
 
-    let result = self.peek(item_type).to_vec();
+    let len = self.peek(item_type)?.len();
 
-    self.data.splice(self.sp - result.len()..self.sp, vec![]);
+    self.sp -= len;
+    self.discard_types_above(self.sp);
+
+    return Ok(self.data[self.sp..self.sp + len].to_vec());
+  }
 
-    self.sp -= result.len();
+  pub fn pop_value(&mut self, item_type: &Type) -> Result<Value, VmErrorKind> {
+    let data = self.pop(item_type)?;
 
-    return result;
+    return decode(data, item_type);
   }
 
-  pub fn pop_value(&mut self, item_type: &Type) -> Value {
-    return Value::from_stack_bytes(self.pop(item_type), item_type);
+  pub fn pop_type(&mut self) -> Result<Type, VmErrorKind> {
+    self.peek(&Type::U8)?;
+    self.sp -= 1;
+    self.discard_types_above(self.sp);
+
+    let code = self.data[self.sp];
+
+    return Type::try_from_u8(code)
+      .ok_or_else(|| VmErrorKind::InvalidType(format!("Unknown type_code: [0x{:x}]", code)));
   }
 
-  pub fn pop_type(&mut self) -> Type {
-    let item_type = self.data.pop().expect("Cannot pop from empty stack!");
+  pub fn pop_usize(&mut self) -> Result<usize, VmErrorKind> {
+    let value = usize::from_be_bytes(self.peek(&Type::Usize)?.try_into().unwrap());
 
-    self.sp -= 1;
+    self.sp -= USIZE_LEN;
+    self.discard_types_above(self.sp);
+
+    return Ok(value);
+  }
+
+  /// Bytes of the `item_type` value at `start`, which the caller keeps below `sp`.
+  pub fn peek_at(&self, start: usize, item_type: &Type) -> Result<&[u8], VmErrorKind> {
+    if let Some(types) = &self.types {
+      let entry = types
+        .iter()
+        .find(|entry| entry.start == start && entry.end <= self.sp);
+
+      if let Some(entry) = entry.filter(|entry| entry.item_type != *item_type) {
+        return Err(VmErrorKind::TypeMismatch {
+          expected: *item_type,
+          actual: entry.item_type,
+        });
+      }
+    }
+
+    return Ok(&self.data[start..start + item_type.size()]);
+  }
+
+  /// Overwrites the bytes at `start`, which the caller keeps below `sp`, with a value of
+  /// `item_type`.
+  pub fn set_at(&mut self, start: usize, value: &[u8], item_type: &Type) {
+    let end = start + value.len();
+
+    self.data[start..end].copy_from_slice(value);
+
+    if let Some(types) = &mut self.types {
+      types.retain(|entry| entry.end <= start || entry.start >= end);
+
+      let index = types.partition_point(|entry| entry.start < start);
+      types.insert(
+        index,
+        StackEntry {
+          start,
+          end,
+          item_type: *item_type,
+        },
+      );
+    }
+  }
 
-    return Type::from_u8(item_type);
+  pub fn pop_string(&mut self) -> Result<String, VmErrorKind> {
+    match self.pop_value(&Type::String)? {
+      Value::String(text) => return Ok(text),
+      _ => unreachable!(),
+    }
   }
 
-  pub fn set_sp(&mut self, offset: usize) {
+  pub fn set_sp(&mut self, offset: usize) -> Result<(), VmErrorKind> {
     if offset > self.size {
-      vm_panic("StackOverflow", "Stack pointer out of bounds!");
+      return Err(VmErrorKind::StackOverflow);
     }
 
     self.sp = offset;
+
+    Ok(())
   }
 
   pub fn get_sp(&self) -> usize {
@@ -166,41 +316,113 @@ impl Stack {
     return &self.data;
   }
 
-  pub fn peek_register(&mut self, register: u8, item_type: &Type) -> Vec<u8> {
-    if (register as usize - 1) >= (self.registers.len() / USIZE_LEN) {
-      vm_panic("RegisterOutOfBounds", "Register index out of bounds!");
+  pub fn register_count(&self) -> usize {
+    return self.registers.len() / USIZE_LEN;
+  }
+
+  pub fn peek_register(&mut self, register: u8, item_type: &Type) -> Result<Vec<u8>, VmErrorKind> {
+    if register == 0 || register as usize > self.register_count() {
+      return Err(VmErrorKind::RegisterOutOfBounds(register));
     }
 
-    let size = item_type.size();
+    let size = register_size(item_type)?;
     let start = ((register as usize) * USIZE_LEN) - size;
     let end = start + size;
 
-    return self.registers[start..end].to_vec();
+    return Ok(self.registers[start..end].to_vec());
   }
 
-  pub fn set_register(&mut self, register: u8, value: Vec<u8>) {
-    if register == 0 {
-      vm_panic("RegisterOutOfBounds", "Cannot set register 0!");
-    }
-
-    if register > MAX_REGISTER_COUNT as u8 {
-      vm_panic("RegisterOutOfBounds", "Register index out of bounds!");
+  pub fn set_register(&mut self, register: u8, value: &[u8]) -> Result<(), VmErrorKind> {
+    if register == 0 || register as usize > self.register_count() {
+      return Err(VmErrorKind::RegisterOutOfBounds(register));
     }
 
     if value.len() > USIZE_LEN {
-      vm_panic(
-        "RegisterOverflow",
-        "Cannot set register with value larger than usize!",
-      );
+      return Err(VmErrorKind::RegisterOverflow);
     }
 
     let start = (register - 1) as usize * USIZE_LEN;
     let end = start + USIZE_LEN;
 
-    let mut bytes = vec![0; USIZE_LEN];
+    // Values are stored right-aligned, big-endian, like `peek_register` reads them.
+    self.registers[start..end - value.len()].fill(0);
+    self.registers[end - value.len()..end].copy_from_slice(value);
 
-    bytes.splice(USIZE_LEN - value.len()..USIZE_LEN, value);
+    Ok(())
+  }
+}
+
+impl Debug for Stack {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let mut debug = f.debug_struct("Stack");
+
+    debug
+      .field("data", &&self.data[..self.sp])
+      .field("saves", &self.saves)
+      .field("registers", &self.registers)
+      .field("size", &self.size)
+      .field("sp", &self.sp);
+
+    if let Some(types) = &self.types {
+      debug.field("types", types);
+    }
+
+    debug.finish()
+  }
+}
+
+/// Reads a value popped or peeked from the stack. Bytes written without a type, e.g. by
+/// `PUSH u8`, can form a `str` that is not valid UTF-8.
+fn decode(data: Vec<u8>, item_type: &Type) -> Result<Value, VmErrorKind> {
+  if *item_type == Type::String && std::str::from_utf8(data.get(1..).unwrap_or_default()).is_err() {
+    return Err(VmErrorKind::InvalidUtf8);
+  }
+
+  return Ok(Value::from_stack_bytes(data, item_type));
+}
+
+fn register_size(item_type: &Type) -> Result<usize, VmErrorKind> {
+  match item_type {
+    Type::String | Type::Bytes => Err(VmErrorKind::InvalidType(format!(
+      "Cannot read {} from a register",
+      item_type.to_str()
+    ))),
+    t => Ok(t.size()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn register_count_is_bounded() {
+    let stack = Stack::with_registers(16, MAX_REGISTER_COUNT).unwrap();
+    assert_eq!(stack.register_count(), MAX_REGISTER_COUNT);
+
+    assert_eq!(
+      Stack::with_registers(16, MAX_REGISTER_COUNT + 1).err(),
+      Some(VmErrorKind::TooManyRegisters(MAX_REGISTER_COUNT + 1))
+    );
+  }
 
-    self.registers.splice(start..end, bytes);
+  #[test]
+  fn registers_are_addressed_from_one_up_to_the_count() {
+    let mut stack = Stack::with_registers(16, 8).unwrap();
+
+    stack.set_register(8, &7u64.to_be_bytes()).unwrap();
+    assert_eq!(
+      stack.peek_register(8, &Type::U64).unwrap(),
+      7u64.to_be_bytes().to_vec()
+    );
+
+    assert_eq!(
+      stack.set_register(0, &[1]),
+      Err(VmErrorKind::RegisterOutOfBounds(0))
+    );
+    assert_eq!(
+      stack.peek_register(9, &Type::U8),
+      Err(VmErrorKind::RegisterOutOfBounds(9))
+    );
   }
 }
@@ -0,0 +1,77 @@
+use super::VmErrorKind;
+
+/// Byte offset of the char at `index` in `text`. The index one past the last char is the
+/// end of the string.
+fn char_offset(text: &str, index: usize) -> Option<usize> {
+  return text
+    .char_indices()
+    .map(|(offset, _)| offset)
+    .chain([text.len()])
+    .nth(index);
+}
+
+fn out_of_bounds(text: &str, start: usize, end: usize) -> VmErrorKind {
+  VmErrorKind::StringOutOfBounds {
+    start,
+    end,
+    len: text.chars().count(),
+  }
+}
+
+/// Chars of `text` from `start` up to, but not including, `end`.
+pub(crate) fn slice(text: &str, start: usize, end: usize) -> Result<String, VmErrorKind> {
+  let range = char_offset(text, start)
+    .zip(char_offset(text, end))
+    .filter(|(from, to)| from <= to)
+    .ok_or_else(|| out_of_bounds(text, start, end))?;
+
+  return Ok(text[range.0..range.1].to_string());
+}
+
+/// Code of the char at `index` in `text`.
+pub(crate) fn char_code(text: &str, index: usize) -> Result<u32, VmErrorKind> {
+  return text
+    .chars()
+    .nth(index)
+    .map(|c| c as u32)
+    .ok_or_else(|| out_of_bounds(text, index, index + 1));
+}
+
+/// String with the char of `code`. Code 0 is rejected because it ends strings on the stack.
+pub(crate) fn from_char_code(code: u32) -> Result<String, VmErrorKind> {
+  return char::from_u32(code)
+    .filter(|c| *c != '\0')
+    .map(String::from)
+    .ok_or(VmErrorKind::InvalidChar(code));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn slices_by_char_not_by_byte() {
+    assert_eq!(slice("héllo", 1, 3), Ok(String::from("él")));
+    assert_eq!(slice("日本語", 2, 3), Ok(String::from("語")));
+    assert_eq!(slice("héllo", 5, 5), Ok(String::new()));
+  }
+
+  #[test]
+  fn rejects_slices_out_of_bounds_or_reversed() {
+    let error = |start, end| VmErrorKind::StringOutOfBounds { start, end, len: 5 };
+
+    assert_eq!(slice("héllo", 2, 6), Err(error(2, 6)));
+    assert_eq!(slice("héllo", 3, 1), Err(error(3, 1)));
+  }
+
+  #[test]
+  fn char_codes_round_trip() {
+    assert_eq!(char_code("hé", 1), Ok(0xE9));
+    assert_eq!(from_char_code(0xE9), Ok(String::from("é")));
+    assert_eq!(from_char_code(0), Err(VmErrorKind::InvalidChar(0)));
+    assert_eq!(
+      from_char_code(0xD800),
+      Err(VmErrorKind::InvalidChar(0xD800))
+    );
+  }
+}
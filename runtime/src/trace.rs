@@ -0,0 +1,90 @@
+use std::io::Write;
+
+use svm_lang::opcodes::OpCode;
+
+const USIZE_LEN: usize = std::mem::size_of::<usize>();
+
+/// Machine state around a single instruction.
+pub struct TraceEvent<'a> {
+  /// Address of the instruction being executed.
+  pub pc: usize,
+  pub opcode: &'a OpCode,
+  pub sp: usize,
+  pub registers: &'a [u8],
+}
+
+impl TraceEvent<'_> {
+  /// Register file decoded as one `usize` per register.
+  pub fn register_values(&self) -> Vec<usize> {
+    return self
+      .registers
+      .chunks_exact(USIZE_LEN)
+      .map(|bytes| usize::from_be_bytes(bytes.try_into().unwrap()))
+      .collect();
+  }
+}
+
+/// Hook invoked by the VM around every instruction it executes.
+pub trait Tracer {
+  fn before(&mut self, event: &TraceEvent);
+  fn after(&mut self, event: &TraceEvent);
+}
+
+/// Writes one JSON object per line for every traced event.
+pub struct JsonTracer<W: Write> {
+  writer: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+  pub fn new(writer: W) -> Self {
+    Self { writer }
+  }
+
+  fn write(&mut self, kind: &str, event: &TraceEvent) {
+    let registers = event
+      .register_values()
+      .iter()
+      .map(|r| r.to_string())
+      .collect::<Vec<_>>()
+      .join(",");
+
+    // Tracing must never stop the program, so write errors are ignored.
+    let _ = writeln!(
+      self.writer,
+      "{{\"event\":\"{}\",\"pc\":{},\"op\":\"{}\",\"sp\":{},\"registers\":[{}]}}",
+      kind,
+      event.pc,
+      escape_json(&event.opcode.to_string()),
+      event.sp,
+      registers
+    );
+  }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+  fn before(&mut self, event: &TraceEvent) {
+    self.write("before", event);
+  }
+
+  fn after(&mut self, event: &TraceEvent) {
+    self.write("after", event);
+  }
+}
+
+fn escape_json(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+
+  for ch in text.chars() {
+    match ch {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c => escaped.push(c),
+    }
+  }
+
+  return escaped;
+}
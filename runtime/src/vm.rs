@@ -1,385 +1,624 @@
-use std::{collections::HashMap, time::Duration};
-
-use svm_lang::{opcodes::OpCode, Program, Type, Value};
-
-use super::{util::vm_panic, Stack};
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fs::File,
+  time::{Duration, Instant},
+};
+
+use svm_lang::{
+  opcodes::{BinaryOp, OpCode, Overflow},
+  Program, Type, Value,
+};
+
+use super::{
+  arithmetic, cast,
+  instruction::{Code, Instruction},
+  strings, CancelHandle, Coroutine, Extern, Frame, GasTable, Handler, Heap, Message, Profiler,
+  Resumed, Resumer, Stack, TraceEvent, Tracer, VmError, VmErrorKind, WithCost,
+};
+
+pub const MAX_CALL_DEPTH: usize = 1024;
+
+/// Register holding the jump target of `GOTO`, `GZ` and `GNZ` (`%addr`).
+const ADDRESS_REGISTER: u8 = 1;
+
+/// Number of instructions executed between two deadline checks.
+const DEADLINE_CHECK_INTERVAL: u32 = 1024;
+
+/// Why execution stopped.
+#[derive(Debug, Clone)]
+pub enum StopReason {
+  /// A `HALT` instruction was executed.
+  Halted,
+  /// The PC reached a breakpoint; the instruction at that address has not run yet.
+  Breakpoint(usize),
+  /// The PC moved past the last instruction.
+  EndOfProgram,
+  /// The [`CancelHandle`] was triggered; the instruction at the PC has not run yet.
+  Cancelled,
+  /// The deadline set with [`VM::set_timeout`] passed; the instruction at the PC has not run yet.
+  TimedOut,
+  /// `RECV` found an empty mailbox; the instruction at the PC has not run yet.
+  Blocked,
+  Error(VmError),
+}
 
-pub struct VM<'a> {
+pub struct VM {
   pub stack: Stack,
-  pub program: Program,
+  pub heap: Heap,
+  /// The loaded program, replaced by [`VM::load`] together with `code`.
+  program: Program,
+  /// `program` decoded for dispatch, with the mnemonic and gas cost of each instruction.
+  code: Code,
   pub pc: usize,
   pub running: bool,
   pub sleep: Duration,
-  pub externs: HashMap<usize, &'a dyn Fn(&mut VM) -> Option<Value>>,
+  /// Registered externs, by slot. A slot is empty while its extern is running.
+  externs: Vec<Option<Box<dyn Extern>>>,
+  extern_slots: HashMap<String, usize>,
+  /// Extern slot of each entry of `program.imports`, filled by [`VM::load`].
+  linked_imports: Vec<usize>,
+  pub frames: Vec<Frame>,
+  /// Exception handlers installed by `TRY`, innermost last.
+  pub handlers: Vec<Handler>,
+  pub max_call_depth: usize,
+  pub breakpoints: HashSet<usize>,
+  pub tracer: Option<Box<dyn Tracer>>,
+  pub profiler: Option<Profiler>,
+  gas_table: GasTable,
+  /// Remaining gas, or `None` when execution is unmetered.
+  gas: Option<u64>,
+  cancel: CancelHandle,
+  deadline: Option<Instant>,
+  steps_until_deadline_check: u32,
+  pub(crate) coroutines: Vec<Coroutine>,
+  /// Contexts waiting for the coroutine they resumed, innermost last.
+  pub(crate) resumers: Vec<Resumer>,
+  /// Result for [`VM::resume_coroutine`] once the coroutine yields or finishes.
+  pub(crate) host_resumed: Option<Resumed>,
+  /// Process id within a [`Scheduler`](crate::Scheduler), pushed by `PID`.
+  pub pid: usize,
+  /// Messages received and not yet taken by `RECV`, oldest first.
+  pub mailbox: VecDeque<Value>,
+  /// Messages sent with `SEND` and not yet delivered.
+  pub outbox: Vec<Message>,
+  /// Current values of the globals of the program, shared by all coroutines.
+  pub globals: Vec<Value>,
 }
 
-impl<'a> VM<'a> {
+impl VM {
   pub fn new() -> Self {
+    return Self::with_stack(Stack::new(1024));
+  }
+
+  /// Creates a VM with `count` general registers, at most
+  /// [`MAX_REGISTER_COUNT`](crate::MAX_REGISTER_COUNT). Coroutines get the same number.
+  pub fn with_registers(count: usize) -> Result<Self, VmErrorKind> {
+    return Ok(Self::with_stack(Stack::with_registers(1024, count)?));
+  }
+
+  fn with_stack(stack: Stack) -> Self {
     Self {
-      stack: Stack::new(1024),
+      stack,
+      heap: Heap::default(),
       sleep: Duration::ZERO,
       program: Program::empty(),
+      code: Code::default(),
       pc: 0,
       running: false,
-      externs: HashMap::new(),
+      externs: Vec::new(),
+      extern_slots: HashMap::new(),
+      linked_imports: Vec::new(),
+      frames: Vec::new(),
+      handlers: Vec::new(),
+      max_call_depth: MAX_CALL_DEPTH,
+      breakpoints: HashSet::new(),
+      tracer: None,
+      profiler: None,
+      gas_table: GasTable::default(),
+      gas: None,
+      cancel: CancelHandle::new(),
+      deadline: None,
+      steps_until_deadline_check: 0,
+      coroutines: Vec::new(),
+      resumers: Vec::new(),
+      host_resumed: None,
+      pid: 0,
+      mailbox: VecDeque::new(),
+      outbox: Vec::new(),
+      globals: Vec::new(),
     }
   }
 
-  pub fn load_plugin<F: FnMut(&mut VM) -> ()>(&mut self, mut plugin: F) {
-    plugin(self);
+  pub fn load_plugin<F: FnMut(&mut VM) -> Result<(), VmErrorKind>>(
+    &mut self,
+    mut plugin: F,
+  ) -> Result<(), VmErrorKind> {
+    plugin(self)
   }
 
-  pub fn register_extern(&mut self, addr: usize, ext: &'a dyn Fn(&mut VM) -> Option<Value>) {
-    if self.externs.contains_key(&addr) {
-      vm_panic("InvalidExtern", "Extern already registered");
-      return;
+  /// Registers `ext` under its qualified name (e.g. `std.io.print`).
+  pub fn register_extern<E: Extern + 'static>(
+    &mut self,
+    name: &str,
+    ext: E,
+  ) -> Result<(), VmErrorKind> {
+    if self.extern_slots.contains_key(name) {
+      return Err(VmErrorKind::DuplicateExtern(name.to_string()));
     }
 
-    self.externs.insert(addr, ext);
+    self
+      .extern_slots
+      .insert(name.to_string(), self.externs.len());
+    self.externs.push(Some(Box::new(ext)));
+
+    Ok(())
   }
 
-  pub fn run(&mut self) {
-    self.running = true;
+  /// Registers `ext`, replacing its own [`Extern::cost`] with `cost`.
+  pub fn register_extern_with_cost<E: Extern + 'static>(
+    &mut self,
+    name: &str,
+    ext: E,
+    cost: u64,
+  ) -> Result<(), VmErrorKind> {
+    self.register_extern(name, WithCost { ext, cost })
+  }
 
-    while self.running {
-      if self.pc >= self.program.opcodes.len() {
-        self.running = false;
-        break;
-      }
+  /// Names of all registered externs.
+  pub fn extern_names(&self) -> impl Iterator<Item = &str> {
+    self.extern_slots.keys().map(|name| name.as_str())
+  }
 
-      self.pc += 1;
-
-      let op = self.program.opcodes[self.pc - 1].clone();
-
-      match op.clone() {
-        OpCode::NoOperation => (),
-        OpCode::Halt => self.halt(),
-        OpCode::Push(value) => self.push(value),
-        OpCode::PushAll(values) => self.push_all(values),
-        OpCode::Increment(t) => self.increment(t),
-        OpCode::Decrement(t) => self.decrement(t),
-        OpCode::Add(t) => self.add(t),
-        OpCode::Subtraction(t) => self.subtract(t),
-        OpCode::Multiply(t) => self.multiply(t),
-        OpCode::Divide(t) => self.divide(t),
-        OpCode::Modulo(t) => self.modulo(t),
-        OpCode::Power(t) => self.power(t),
-        OpCode::Copy(t) => self.copy(t),
-        OpCode::Equals(t) => self.equals(t),
-        OpCode::LessThan(t) => self.less_than(t),
-        OpCode::GreaterThan(t) => self.greater_than(t),
-        OpCode::LessThanOrEqual(t) => self.less_than_or_equal(t),
-        OpCode::GreaterThanOrEqual(t) => self.greater_than_or_equal(t),
-        OpCode::Pop(t, r) => self.pop(t, r),
-        OpCode::ProgramCounter => self.program_counter(),
-        OpCode::StackPointer => self.stack_pointer(),
-        OpCode::And(t) => self.and(t),
-        OpCode::Or(t) => self.or(t),
-        OpCode::XOr(t) => self.xor(t),
-        OpCode::Not(t) => self.not(t),
-        OpCode::ShiftLeft(t) => self.shift_left(t),
-        OpCode::ShiftRight(t) => self.shift_right(t),
-        OpCode::Move(reg, value) => self.mov(reg, value),
-        OpCode::Register(reg, item_type) => self.register(reg, item_type),
-        OpCode::Call(addr) => self.call(addr),
-        OpCode::Return => self.ret(),
-        OpCode::External(addr) => self.external(addr),
-        OpCode::Goto => self.goto(),
-        OpCode::GotoIfNotZero => self.goto_if_not_zero(),
-        OpCode::GotoIfZero => self.goto_if_zero(),
-        OpCode::Jump(addr) => self.jump(addr),
-        OpCode::JumpIfNotZero(addr) => self.jump_if_not_zero(addr),
-        OpCode::JumpIfZero(addr) => self.jump_if_zero(addr),
-        OpCode::MoveStackPointer(offset) => self.move_stack_pointer(offset),
-        OpCode::Negative(t) => self.negative(t),
-        OpCode::NotEquals(t) => self.not_equals(t),
-        OpCode::PushBytes(bytes) => self.push_bytes(bytes),
-      };
-
-      // println!(
-      //   "\x1bcPC={}\nSP={}\nSTACK: {:?}\nREGISTERS: {:?}\nINSTRUCTION: {:?}\n",
-      //   self.pc,
-      //   self.stack.sp,
-      //   self.stack.data,
-      //   self
-      //     .stack
-      //     .registers
-      //     .chunks_exact(std::mem::size_of::<usize>())
-      //     .collect::<Vec<_>>(),
-      //   op,
-      // );
+  /// Links the imports of `program` against the registered externs and makes it the
+  /// current program, starting from PC 0 with the initial values of its globals.
+  ///
+  /// Fails without touching the VM if any import has no registered extern or `CONST` refers
+  /// to a constant that does not exist.
+  pub fn load(&mut self, program: Program) -> Result<(), VmErrorKind> {
+    let unresolved = program
+      .imports
+      .iter()
+      .filter(|name| !self.extern_slots.contains_key(*name))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    if !unresolved.is_empty() {
+      return Err(VmErrorKind::UnresolvedImports(unresolved));
+    }
 
-      if self.sleep > Duration::ZERO {
-        std::thread::sleep(self.sleep);
+    let code = Code::decode(&program.opcodes, &program.constants)?;
+
+    self.linked_imports = program
+      .imports
+      .iter()
+      .map(|name| self.extern_slots[name])
+      .collect();
+    self.code = code;
+    self.globals = program.globals.clone();
+    self.program = program;
+    self.price_code();
+    self.pc = 0;
+    self.frames.clear();
+    self.handlers.clear();
+
+    Ok(())
+  }
+
+  pub fn program(&self) -> &Program {
+    &self.program
+  }
+
+  /// Reads the labels of a `.sym` file into the loaded program.
+  pub fn load_labels(&mut self, file: File) {
+    self.program.load_labels(file);
+  }
+
+  /// Limits execution to `gas` units. `None` disables metering.
+  pub fn set_gas(&mut self, gas: Option<u64>) {
+    self.gas = gas;
+  }
+
+  pub fn remaining_gas(&self) -> Option<u64> {
+    self.gas
+  }
+
+  /// Handle that stops this VM from any thread.
+  pub fn cancel_handle(&self) -> CancelHandle {
+    self.cancel.clone()
+  }
+
+  /// Stops execution once `timeout` has elapsed from now. `None` removes the deadline.
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+    self.set_deadline(timeout.map(|timeout| Instant::now() + timeout));
+  }
+
+  pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+    self.deadline = deadline;
+    self.steps_until_deadline_check = 0;
+  }
+
+  pub fn deadline(&self) -> Option<Instant> {
+    self.deadline
+  }
+
+  pub fn gas_table(&self) -> &GasTable {
+    &self.gas_table
+  }
+
+  /// Replaces the gas table, repricing the loaded program.
+  pub fn set_gas_table(&mut self, gas_table: GasTable) {
+    self.gas_table = gas_table;
+    self.price_code();
+  }
+
+  /// Gas charged for executing `op`, including the cost of the called extern.
+  pub fn gas_cost(&self, op: &OpCode) -> u64 {
+    let mut cost = self.gas_table.cost(op);
+
+    if let OpCode::External(import) = op {
+      let ext = self
+        .linked_imports
+        .get(*import)
+        .and_then(|slot| self.externs[*slot].as_ref());
+
+      if let Some(ext) = ext {
+        cost = cost.saturating_add(ext.cost());
       }
     }
+
+    return cost;
   }
 
-  fn halt(&mut self) {
-    self.running = false;
+  /// Computes the cost of every instruction once, so `step` does not look it up.
+  fn price_code(&mut self) {
+    self.code.costs = self
+      .program
+      .opcodes
+      .iter()
+      .map(|op| self.gas_cost(op))
+      .collect();
   }
 
-  fn push(&mut self, value: Value) {
-    self.stack.push(value.to_bytes());
+  /// Runs until the program halts, reaches its end or fails. Breakpoints are ignored.
+  pub fn run(&mut self) -> Result<StopReason, VmError> {
+    match self.run_loop(false) {
+      StopReason::Error(error) => Err(error),
+      reason => Ok(reason),
+    }
   }
 
-  fn push_all(&mut self, values: Vec<Value>) {
-    self.stack.push(
-      values
-        .iter()
-        .map(|x| x.to_bytes())
-        .flatten()
-        .collect::<Vec<u8>>(),
-    );
+  /// Runs until the program stops for any reason, including hitting a breakpoint.
+  ///
+  /// The instruction at the current PC is always executed, so calling this again after a
+  /// breakpoint continues past it.
+  pub fn run_until_break(&mut self) -> StopReason {
+    self.run_loop(true)
   }
 
-  fn push_bytes(&mut self, bytes: Vec<u8>) {
-    self.stack.push(bytes);
-  }
-
-  fn increment(&mut self, t: Type) {
-    let value = self.stack.pop_value(&t);
-
-    let new_value = match value {
-      Value::U8(value) => Value::U8(value + 1),
-      Value::U16(value) => Value::U16(value + 1),
-      Value::U32(value) => Value::U32(value + 1),
-      Value::U64(value) => Value::U64(value + 1),
-      Value::I8(value) => Value::I8(value + 1),
-      Value::I16(value) => Value::I16(value + 1),
-      Value::I32(value) => Value::I32(value + 1),
-      Value::I64(value) => Value::I64(value + 1),
-      Value::F32(value) => Value::F32(value + 1.0),
-      Value::F64(value) => Value::F64(value + 1.0),
-      Value::Usize(value) => Value::Usize(value + 1),
-      Value::Isize(value) => Value::Isize(value + 1),
-      _ => {
-        vm_panic("InvalidType", "Cannot increment non-number value");
-        return;
-      }
-    };
+  fn run_loop(&mut self, use_breakpoints: bool) -> StopReason {
+    let mut first = true;
 
-    self.stack.push_value(new_value)
-  }
-
-  fn decrement(&mut self, t: Type) {
-    let value = self.stack.pop_value(&t);
-
-    let new_value = match value {
-      Value::U8(value) => Value::U8(value - 1),
-      Value::U16(value) => Value::U16(value - 1),
-      Value::U32(value) => Value::U32(value - 1),
-      Value::U64(value) => Value::U64(value - 1),
-      Value::I8(value) => Value::I8(value - 1),
-      Value::I16(value) => Value::I16(value - 1),
-      Value::I32(value) => Value::I32(value - 1),
-      Value::I64(value) => Value::I64(value - 1),
-      Value::F32(value) => Value::F32(value - 1.0),
-      Value::F64(value) => Value::F64(value - 1.0),
-      Value::Usize(value) => Value::Usize(value - 1),
-      Value::Isize(value) => Value::Isize(value - 1),
-      _ => {
-        vm_panic("InvalidType", "Cannot increment non-number value");
-        return;
+    loop {
+      if use_breakpoints && !first && self.breakpoints.contains(&self.pc) {
+        return StopReason::Breakpoint(self.pc);
       }
-    };
 
-    self.stack.push_value(new_value)
-  }
-
-  fn add(&mut self, item_type: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&item_type), &item_type);
-    let a = Value::from_stack_bytes(self.stack.pop(&item_type), &item_type);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a + b),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a + b),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a + b),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a + b),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a + b),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a + b),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a + b),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a + b),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a + b),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a + b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a + b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot add {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
+      first = false;
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn subtract(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a - b),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a - b),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a - b),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a - b),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a - b),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a - b),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a - b),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a - b),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a - b),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a - b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a - b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot subtract {:?} and {:?}", a, b).as_str(),
-        );
-        return;
+      if let Some(reason) = self.step() {
+        return reason;
       }
-    };
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn multiply(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a * b),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a * b),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a * b),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a * b),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a * b),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a * b),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a * b),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a * b),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a * b),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a * b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a * b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot multiply {:?} and {:?}", a, b).as_str(),
-        );
-        return;
+      if self.sleep > Duration::ZERO {
+        std::thread::sleep(self.sleep);
       }
-    };
+    }
+  }
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn divide(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a / b),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a / b),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a / b),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a / b),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a / b),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a / b),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a / b),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a / b),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a / b),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a / b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a / b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot divide {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
+  /// Executes exactly one instruction. Returns `None` if execution can continue.
+  pub fn step(&mut self) -> Option<StopReason> {
+    let pc = self.pc;
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn modulo(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a % b),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a % b),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a % b),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a % b),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a % b),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a % b),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a % b),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a % b),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a % b),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a % b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a % b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot modulo {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
+    let Some(&instruction) = self.code.instructions.get(pc) else {
+      self.running = false;
+      return Some(StopReason::EndOfProgram);
     };
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn power(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::U8(a.pow(b.into())),
-      (Value::U16(a), Value::U16(b)) => Value::U16(a.pow(b.into())),
-      (Value::U32(a), Value::U32(b)) => Value::U32(a.pow(b)),
-      (Value::U64(a), Value::U64(b)) => Value::U64(a.pow(b.try_into().unwrap())),
-      (Value::I8(a), Value::I8(b)) => Value::I8(a.pow(b.try_into().unwrap())),
-      (Value::I16(a), Value::I16(b)) => Value::I16(a.pow(b.try_into().unwrap())),
-      (Value::I32(a), Value::I32(b)) => Value::I32(a.pow(b.try_into().unwrap())),
-      (Value::I64(a), Value::I64(b)) => Value::I64(a.pow(b.try_into().unwrap())),
-      (Value::F32(a), Value::F32(b)) => Value::F32(a.powf(b)),
-      (Value::F64(a), Value::F64(b)) => Value::F64(a.powf(b)),
-      (Value::Usize(a), Value::Usize(b)) => Value::Usize(a.pow(b as u32)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot pow {:?} and {:?}", a, b).as_str(),
-        );
-        return;
+    if self.cancel.is_cancelled() {
+      self.running = false;
+      return Some(StopReason::Cancelled);
+    }
+
+    if self.deadline_passed() {
+      self.running = false;
+      return Some(StopReason::TimedOut);
+    }
+
+    if matches!(instruction, Instruction::Receive(_)) && self.mailbox.is_empty() {
+      self.running = false;
+      return Some(StopReason::Blocked);
+    }
+
+    if let Some(gas) = self.gas {
+      let cost = self.code.costs[pc];
+
+      // The PC is left on the instruction so the VM can resume after refuelling.
+      if cost > gas {
+        self.running = false;
+
+        return Some(StopReason::Error(VmError {
+          kind: VmErrorKind::OutOfGas,
+          pc,
+          opcode: self.program.opcodes[pc].clone(),
+        }));
       }
+
+      self.gas = Some(gas - cost);
+    }
+
+    self.pc += 1;
+    self.running = true;
+
+    if let Some(profiler) = self.profiler.as_mut() {
+      profiler.record(pc, self.code.mnemonics[pc], &self.frames);
+    }
+
+    if let Some(tracer) = self.tracer.as_mut() {
+      tracer.before(&TraceEvent {
+        pc,
+        opcode: &self.program.opcodes[pc],
+        sp: self.stack.sp,
+        registers: &self.stack.registers,
+      });
+    }
+
+    if let Err(kind) = self.execute(instruction) {
+      self.running = false;
+
+      return Some(StopReason::Error(VmError {
+        kind,
+        pc,
+        opcode: self.program.opcodes[pc].clone(),
+      }));
+    }
+
+    if let Some(tracer) = self.tracer.as_mut() {
+      tracer.after(&TraceEvent {
+        pc,
+        opcode: &self.program.opcodes[pc],
+        sp: self.stack.sp,
+        registers: &self.stack.registers,
+      });
+    }
+
+    if !self.running {
+      return Some(StopReason::Halted);
+    }
+
+    None
+  }
+
+  /// Reading the clock on every instruction is too slow, so it is sampled periodically.
+  fn deadline_passed(&mut self) -> bool {
+    let Some(deadline) = self.deadline else {
+      return false;
     };
 
-    self.stack.push(result.to_bytes());
-  }
-
-  fn negative(&mut self, t: Type) {
-    let value = self.stack.pop_value(&t);
-
-    let new_value = match value {
-      Value::U8(value) => Value::I8(-(value as i8)),
-      Value::U16(value) => Value::I16(-(value as i16)),
-      Value::U32(value) => Value::I32(-(value as i32)),
-      Value::U64(value) => Value::I64(-(value as i64)),
-      Value::I8(value) => Value::I8(-value),
-      Value::I16(value) => Value::I16(-value),
-      Value::I32(value) => Value::I32(-value),
-      Value::I64(value) => Value::I64(-value),
-      Value::F32(value) => Value::F32(-value),
-      Value::F64(value) => Value::F64(-value),
-      Value::Usize(value) => Value::Isize(-(value as isize)),
-      _ => {
-        vm_panic("InvalidType", "Cannot negate non-integer value");
-        return;
+    if self.steps_until_deadline_check > 0 {
+      self.steps_until_deadline_check -= 1;
+      return false;
+    }
+
+    self.steps_until_deadline_check = DEADLINE_CHECK_INTERVAL;
+
+    return Instant::now() >= deadline;
+  }
+
+  fn execute(&mut self, instruction: Instruction) -> Result<(), VmErrorKind> {
+    match instruction {
+      Instruction::NoOperation => Ok(()),
+      Instruction::Halt => self.halt(),
+      Instruction::Push(value) => self.push(value),
+      Instruction::PushAll { first, count } => self.push_all(first, count),
+      Instruction::Increment(t, overflow) => self.increment(t, overflow),
+      Instruction::Decrement(t, overflow) => self.decrement(t, overflow),
+      Instruction::Add(t, overflow) => self.binary(BinaryOp::Add, overflow, t),
+      Instruction::Subtraction(t, overflow) => self.binary(BinaryOp::Subtraction, overflow, t),
+      Instruction::Multiply(t, overflow) => self.binary(BinaryOp::Multiply, overflow, t),
+      Instruction::Divide(t, overflow) => self.binary(BinaryOp::Divide, overflow, t),
+      Instruction::Modulo(t, overflow) => self.binary(BinaryOp::Modulo, overflow, t),
+      Instruction::Power(t, overflow) => self.binary(BinaryOp::Power, overflow, t),
+      Instruction::Copy(t) => self.copy(t),
+      Instruction::Equals(t) => self.binary(BinaryOp::Equals, Overflow::Checked, t),
+      Instruction::LessThan(t) => self.binary(BinaryOp::LessThan, Overflow::Checked, t),
+      Instruction::GreaterThan(t) => self.binary(BinaryOp::GreaterThan, Overflow::Checked, t),
+      Instruction::LessThanOrEqual(t) => {
+        self.binary(BinaryOp::LessThanOrEqual, Overflow::Checked, t)
+      }
+      Instruction::GreaterThanOrEqual(t) => {
+        self.binary(BinaryOp::GreaterThanOrEqual, Overflow::Checked, t)
+      }
+      Instruction::Pop(t, r) => self.pop(t, r),
+      Instruction::RegisterOp(op, overflow, t, lhs, rhs, dst) => {
+        self.register_operation(op, overflow, t, lhs, rhs, dst)
       }
+      Instruction::Cast(from, to, overflow) => self.cast(from, to, overflow),
+      Instruction::Concat => self.concat(),
+      Instruction::Length => self.length(),
+      Instruction::CharCount => self.char_count(),
+      Instruction::Slice => self.slice(),
+      Instruction::CharCode => self.char_code(),
+      Instruction::FromCharCode => self.char_from_code(),
+      Instruction::FramePointer => self.stack.push_value(Value::Usize(self.frame_pointer())),
+      Instruction::LocalGet(t, offset) => self.local_get(t, offset),
+      Instruction::LocalSet(t, offset) => self.local_set(t, offset),
+      Instruction::PushConstant(index) => self.push(index),
+      Instruction::GlobalGet(t, index) => self.global_get(t, index),
+      Instruction::GlobalSet(t, index) => self.global_set(t, index),
+      Instruction::ProgramCounter => self.program_counter(),
+      Instruction::StackPointer => self.stack_pointer(),
+      Instruction::And(t) => self.and(t),
+      Instruction::Or(t) => self.or(t),
+      Instruction::XOr(t) => self.xor(t),
+      Instruction::Not(t) => self.not(t),
+      Instruction::ShiftLeft(t, overflow) => self.shift_left(t, overflow),
+      Instruction::ShiftRight(t, overflow) => self.shift_right(t, overflow),
+      Instruction::Move(reg, value) => self.mov(reg, value),
+      Instruction::Register(reg, item_type) => self.register(reg, item_type),
+      Instruction::Call(addr) => self.call(addr),
+      Instruction::Return => self.ret(),
+      Instruction::Alloc => self.alloc(),
+      Instruction::Free => self.free(),
+      Instruction::Load(t) => self.heap_load(t),
+      Instruction::Store(t) => self.heap_store(t),
+      Instruction::Spawn(addr) => self.spawn(addr),
+      Instruction::Yield(t) => self.yield_value(t),
+      Instruction::Resume => self.resume(),
+      Instruction::Send(t) => self.send(t),
+      Instruction::Receive(t) => self.receive(t),
+      Instruction::ProcessId => self.stack.push_value(Value::Usize(self.pid)),
+      Instruction::Try(addr) => self.try_(addr),
+      Instruction::EndTry => self.end_try(),
+      Instruction::Throw(t) => self.throw(t),
+      Instruction::External(import) => self.external(import),
+      Instruction::Goto => self.goto(),
+      Instruction::GotoIfNotZero => self.goto_if_not_zero(),
+      Instruction::GotoIfZero => self.goto_if_zero(),
+      Instruction::Jump(addr) => self.jump(addr),
+      Instruction::JumpIfNotZero(addr) => self.jump_if_not_zero(addr),
+      Instruction::JumpIfZero(addr) => self.jump_if_zero(addr),
+      Instruction::MoveStackPointer(offset) => self.move_stack_pointer(offset),
+      Instruction::Negative(t, overflow) => self.negative(t, overflow),
+      Instruction::NotEquals(t) => self.binary(BinaryOp::NotEquals, Overflow::Checked, t),
+      Instruction::PushBytes(constant) => self.push(constant),
+    }
+  }
+
+  fn halt(&mut self) -> Result<(), VmErrorKind> {
+    self.running = false;
+
+    Ok(())
+  }
+
+  fn push(&mut self, constant: u32) -> Result<(), VmErrorKind> {
+    let constant = &self.code.constants[constant as usize];
+
+    match &constant.item_type {
+      Some(item_type) => self.stack.push_typed(&constant.bytes, item_type),
+      None => self.stack.push(&constant.bytes),
+    }
+  }
+
+  fn push_all(&mut self, first: u32, count: u32) -> Result<(), VmErrorKind> {
+    (first..first + count).try_for_each(|constant| self.push(constant))
+  }
+
+  fn increment(&mut self, t: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&t)?;
+
+    self
+      .stack
+      .push_value(arithmetic::increment(value, overflow)?)
+  }
+
+  fn binary(&mut self, op: BinaryOp, overflow: Overflow, t: Type) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
+
+    self
+      .stack
+      .push_value(arithmetic::binary(op, overflow, a, b)?)
+  }
+
+  fn register_operation(
+    &mut self,
+    op: BinaryOp,
+    overflow: Overflow,
+    t: Type,
+    lhs: u8,
+    rhs: u8,
+    dst: u8,
+  ) -> Result<(), VmErrorKind> {
+    let a = Value::from_stack_bytes(self.stack.peek_register(lhs, &t)?, &t);
+    let b = Value::from_stack_bytes(self.stack.peek_register(rhs, &t)?, &t);
+
+    let result = arithmetic::binary(op, overflow, a, b)?;
+
+    self.stack.set_register(dst, &result.to_bytes())
+  }
+
+  fn decrement(&mut self, t: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&t)?;
+
+    self
+      .stack
+      .push_value(arithmetic::decrement(value, overflow)?)
+  }
+
+  fn negative(&mut self, t: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&t)?;
+
+    self
+      .stack
+      .push_value(arithmetic::negative(value, overflow)?)
+  }
+
+  fn cast(&mut self, from: Type, to: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&from)?;
+
+    self.stack.push_value(cast::cast(value, to, overflow)?)
+  }
+
+  fn concat(&mut self) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_string()?;
+    let a = self.stack.pop_string()?;
+
+    self.stack.push_value(Value::String(a + &b))
+  }
+
+  fn length(&mut self) -> Result<(), VmErrorKind> {
+    let text = self.stack.pop_string()?;
+
+    self.stack.push_value(Value::Usize(text.len()))
+  }
+
+  fn char_count(&mut self) -> Result<(), VmErrorKind> {
+    let text = self.stack.pop_string()?;
+
+    self.stack.push_value(Value::Usize(text.chars().count()))
+  }
+
+  fn slice(&mut self) -> Result<(), VmErrorKind> {
+    let end = self.stack.pop_usize()?;
+    let start = self.stack.pop_usize()?;
+    let text = self.stack.pop_string()?;
+
+    self
+      .stack
+      .push_value(Value::String(strings::slice(&text, start, end)?))
+  }
+
+  fn char_code(&mut self) -> Result<(), VmErrorKind> {
+    let index = self.stack.pop_usize()?;
+    let text = self.stack.pop_string()?;
+
+    self
+      .stack
+      .push_value(Value::U32(strings::char_code(&text, index)?))
+  }
+
+  fn char_from_code(&mut self) -> Result<(), VmErrorKind> {
+    let code = self.stack.pop_value(&Type::U32)?;
+    let Value::U32(code) = code else {
+      unreachable!()
     };
 
-    self.stack.push_value(new_value)
+    self
+      .stack
+      .push_value(Value::String(strings::from_char_code(code)?))
   }
 
-  pub fn move_stack_pointer(&mut self, offset: isize) {
+  pub fn move_stack_pointer(&mut self, offset: isize) -> Result<(), VmErrorKind> {
     let new_sp = if offset.is_negative() {
       self.stack.sp.wrapping_sub(offset.wrapping_abs() as usize)
     } else {
@@ -387,200 +626,62 @@ impl<'a> VM<'a> {
     };
 
     if new_sp > self.stack.size {
-      vm_panic("MemoryError", "Stack pointer out of bounds");
+      return Err(VmErrorKind::MemoryError);
     }
 
     self.stack.sp = new_sp;
+
+    Ok(())
   }
 
-  pub fn program_counter(&mut self) {
+  pub fn program_counter(&mut self) -> Result<(), VmErrorKind> {
     self.stack.push_value(Value::Usize(self.pc))
   }
 
-  pub fn stack_pointer(&mut self) {
+  pub fn stack_pointer(&mut self) -> Result<(), VmErrorKind> {
     self.stack.push_value(Value::Usize(self.stack.sp))
   }
 
-  pub fn jump(&mut self, pc: usize) {
+  pub fn jump(&mut self, pc: usize) -> Result<(), VmErrorKind> {
     self.pc = pc;
+
+    Ok(())
   }
 
-  pub fn jump_if_not_zero(&mut self, pc: usize) {
-    if self.stack.pop_value(&Type::Bool) != Value::Bool(false) {
+  pub fn jump_if_not_zero(&mut self, pc: usize) -> Result<(), VmErrorKind> {
+    if self.stack.pop_value(&Type::Bool)? != Value::Bool(false) {
       self.pc = pc;
     }
+
+    Ok(())
   }
 
-  pub fn jump_if_zero(&mut self, pc: usize) {
-    if self.stack.pop_value(&Type::Bool) == Value::Bool(false) {
+  pub fn jump_if_zero(&mut self, pc: usize) -> Result<(), VmErrorKind> {
+    if self.stack.pop_value(&Type::Bool)? == Value::Bool(false) {
       self.pc = pc;
     }
-  }
 
-  fn copy(&mut self, t: Type) {
-    let value = self.stack.peek(&t).to_vec();
-    self.stack.push(value);
+    Ok(())
   }
 
-  fn less_than(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::Bool(a < b),
-      (Value::U16(a), Value::U16(b)) => Value::Bool(a < b),
-      (Value::U32(a), Value::U32(b)) => Value::Bool(a < b),
-      (Value::U64(a), Value::U64(b)) => Value::Bool(a < b),
-      (Value::I8(a), Value::I8(b)) => Value::Bool(a < b),
-      (Value::I16(a), Value::I16(b)) => Value::Bool(a < b),
-      (Value::I32(a), Value::I32(b)) => Value::Bool(a < b),
-      (Value::I64(a), Value::I64(b)) => Value::Bool(a < b),
-      (Value::F32(a), Value::F32(b)) => Value::Bool(a < b),
-      (Value::F64(a), Value::F64(b)) => Value::Bool(a < b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Bool(a < b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot compare {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
-
-    self.stack.push(result.to_bytes());
-  }
-
-  fn less_than_or_equal(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::Bool(a <= b),
-      (Value::U16(a), Value::U16(b)) => Value::Bool(a <= b),
-      (Value::U32(a), Value::U32(b)) => Value::Bool(a <= b),
-      (Value::U64(a), Value::U64(b)) => Value::Bool(a <= b),
-      (Value::I8(a), Value::I8(b)) => Value::Bool(a <= b),
-      (Value::I16(a), Value::I16(b)) => Value::Bool(a <= b),
-      (Value::I32(a), Value::I32(b)) => Value::Bool(a <= b),
-      (Value::I64(a), Value::I64(b)) => Value::Bool(a <= b),
-      (Value::F32(a), Value::F32(b)) => Value::Bool(a <= b),
-      (Value::F64(a), Value::F64(b)) => Value::Bool(a <= b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Bool(a <= b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot compare {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
-
-    self.stack.push(result.to_bytes());
-  }
-
-  fn equals(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::Bool(a == b),
-      (Value::U16(a), Value::U16(b)) => Value::Bool(a == b),
-      (Value::U32(a), Value::U32(b)) => Value::Bool(a == b),
-      (Value::U64(a), Value::U64(b)) => Value::Bool(a == b),
-      (Value::I8(a), Value::I8(b)) => Value::Bool(a == b),
-      (Value::I16(a), Value::I16(b)) => Value::Bool(a == b),
-      (Value::I32(a), Value::I32(b)) => Value::Bool(a == b),
-      (Value::I64(a), Value::I64(b)) => Value::Bool(a == b),
-      (Value::F32(a), Value::F32(b)) => Value::Bool(a == b),
-      (Value::F64(a), Value::F64(b)) => Value::Bool(a == b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Bool(a == b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot compare {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
-
-    self.stack.push(result.to_bytes());
-  }
-
-  fn not_equals(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    self.stack.push(vec![if a != b { 1 } else { 0 }]);
-  }
-
-  fn greater_than(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::Bool(a > b),
-      (Value::U16(a), Value::U16(b)) => Value::Bool(a > b),
-      (Value::U32(a), Value::U32(b)) => Value::Bool(a > b),
-      (Value::U64(a), Value::U64(b)) => Value::Bool(a > b),
-      (Value::I8(a), Value::I8(b)) => Value::Bool(a > b),
-      (Value::I16(a), Value::I16(b)) => Value::Bool(a > b),
-      (Value::I32(a), Value::I32(b)) => Value::Bool(a > b),
-      (Value::I64(a), Value::I64(b)) => Value::Bool(a > b),
-      (Value::F32(a), Value::F32(b)) => Value::Bool(a > b),
-      (Value::F64(a), Value::F64(b)) => Value::Bool(a > b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Bool(a > b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot compare {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
-
-    self.stack.push(result.to_bytes());
-  }
-
-  fn greater_than_or_equal(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
-
-    let result = match (a, b) {
-      (Value::U8(a), Value::U8(b)) => Value::Bool(a >= b),
-      (Value::U16(a), Value::U16(b)) => Value::Bool(a >= b),
-      (Value::U32(a), Value::U32(b)) => Value::Bool(a >= b),
-      (Value::U64(a), Value::U64(b)) => Value::Bool(a >= b),
-      (Value::I8(a), Value::I8(b)) => Value::Bool(a >= b),
-      (Value::I16(a), Value::I16(b)) => Value::Bool(a >= b),
-      (Value::I32(a), Value::I32(b)) => Value::Bool(a >= b),
-      (Value::I64(a), Value::I64(b)) => Value::Bool(a >= b),
-      (Value::F32(a), Value::F32(b)) => Value::Bool(a >= b),
-      (Value::F64(a), Value::F64(b)) => Value::Bool(a >= b),
-      (Value::Usize(a), Value::Usize(b)) => Value::Bool(a >= b),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot compare {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
-
-    self.stack.push(result.to_bytes());
+  fn copy(&mut self, t: Type) -> Result<(), VmErrorKind> {
+    let value = self.stack.peek(&t)?.to_vec();
+    self.stack.push_typed(&value, &t)
   }
 
-  fn pop(&mut self, t: Type, register: Option<u8>) {
-    let value = self.stack.pop(&t);
+  fn pop(&mut self, t: Type, register: Option<u8>) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop(&t)?;
 
     if let Some(r) = register {
-      self.stack.set_register(r, value);
+      self.stack.set_register(r, &value)?;
     }
+
+    Ok(())
   }
 
-  fn and(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
+  fn and(&mut self, t: Type) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
 
     match (a, b) {
       (Value::Bool(a), Value::Bool(b)) => self.stack.push_value(Value::Bool(a && b)),
@@ -593,19 +694,16 @@ impl<'a> VM<'a> {
       (Value::I32(a), Value::I32(b)) => self.stack.push_value(Value::I32(a & b)),
       (Value::I64(a), Value::I64(b)) => self.stack.push_value(Value::I64(a & b)),
       (Value::Usize(a), Value::Usize(b)) => self.stack.push_value(Value::Usize(a & b)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot and {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
-    };
+      (a, b) => Err(VmErrorKind::InvalidType(format!(
+        "Cannot and {:?} and {:?}",
+        a, b
+      ))),
+    }
   }
 
-  fn or(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
+  fn or(&mut self, t: Type) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
 
     match (a, b) {
       (Value::Bool(a), Value::Bool(b)) => self.stack.push_value(Value::Bool(a || b)),
@@ -618,19 +716,16 @@ impl<'a> VM<'a> {
       (Value::I32(a), Value::I32(b)) => self.stack.push_value(Value::I32(a | b)),
       (Value::I64(a), Value::I64(b)) => self.stack.push_value(Value::I64(a | b)),
       (Value::Usize(a), Value::Usize(b)) => self.stack.push_value(Value::Usize(a | b)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot or {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
+      (a, b) => Err(VmErrorKind::InvalidType(format!(
+        "Cannot or {:?} and {:?}",
+        a, b
+      ))),
     }
   }
 
-  fn xor(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
+  fn xor(&mut self, t: Type) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
 
     match (a, b) {
       (Value::Bool(a), Value::Bool(b)) => self.stack.push_value(Value::Bool(a ^ b)),
@@ -643,18 +738,15 @@ impl<'a> VM<'a> {
       (Value::I32(a), Value::I32(b)) => self.stack.push_value(Value::I32(a ^ b)),
       (Value::I64(a), Value::I64(b)) => self.stack.push_value(Value::I64(a ^ b)),
       (Value::Usize(a), Value::Usize(b)) => self.stack.push_value(Value::Usize(a ^ b)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot xor {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
+      (a, b) => Err(VmErrorKind::InvalidType(format!(
+        "Cannot xor {:?} and {:?}",
+        a, b
+      ))),
     }
   }
 
-  fn not(&mut self, t: Type) {
-    let a = Value::from_stack_bytes(self.stack.pop(&t), &t);
+  fn not(&mut self, t: Type) -> Result<(), VmErrorKind> {
+    let a = self.stack.pop_value(&t)?;
 
     match a {
       Value::Bool(a) => self.stack.push_value(Value::Bool(!a)),
@@ -667,128 +759,692 @@ impl<'a> VM<'a> {
       Value::I32(a) => self.stack.push_value(Value::I32(!a)),
       Value::I64(a) => self.stack.push_value(Value::I64(!a)),
       Value::Usize(a) => self.stack.push_value(Value::Usize(!a)),
-      a => {
-        vm_panic("InvalidType", format!("Cannot not {:?}", a).as_str());
-        return;
-      }
+      a => Err(VmErrorKind::InvalidType(format!("Cannot not {:?}", a))),
     }
   }
 
-  fn shift_left(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&Type::U8), &Type::U8);
+  fn shift_left(&mut self, t: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
 
-    match (a, b) {
-      (Value::U8(a), Value::U8(b)) => self.stack.push_value(Value::U8(a << b)),
-      (Value::U16(a), Value::U16(b)) => self.stack.push_value(Value::U16(a << b)),
-      (Value::U32(a), Value::U32(b)) => self.stack.push_value(Value::U32(a << b)),
-      (Value::U64(a), Value::U64(b)) => self.stack.push_value(Value::U64(a << b)),
-      (Value::I8(a), Value::I8(b)) => self.stack.push_value(Value::I8(a << b)),
-      (Value::I16(a), Value::I16(b)) => self.stack.push_value(Value::I16(a << b)),
-      (Value::I32(a), Value::I32(b)) => self.stack.push_value(Value::I32(a << b)),
-      (Value::I64(a), Value::I64(b)) => self.stack.push_value(Value::I64(a << b)),
-      (Value::Usize(a), Value::Usize(b)) => self.stack.push_value(Value::Usize(a << b)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot shl {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
+    self
+      .stack
+      .push_value(arithmetic::shift_left(a, b, overflow)?)
+  }
+
+  fn shift_right(&mut self, t: Type, overflow: Overflow) -> Result<(), VmErrorKind> {
+    let b = self.stack.pop_value(&t)?;
+    let a = self.stack.pop_value(&t)?;
+
+    self
+      .stack
+      .push_value(arithmetic::shift_right(a, b, overflow)?)
+  }
+
+  fn mov(&mut self, reg: u8, constant: u32) -> Result<(), VmErrorKind> {
+    let value = &self.code.constants[constant as usize].bytes;
+
+    self.stack.set_register(reg, value)
+  }
+
+  fn register(&mut self, reg: u8, item_type: Type) -> Result<(), VmErrorKind> {
+    let value = self.stack.peek_register(reg, &item_type)?;
+    self.stack.push_typed(&value, &item_type)
+  }
+
+  fn call(&mut self, addr: usize) -> Result<(), VmErrorKind> {
+    if self.frames.len() >= self.max_call_depth {
+      return Err(VmErrorKind::CallStackOverflow);
     }
+
+    self.frames.push(Frame {
+      return_pc: self.pc,
+      sp: self.stack.sp,
+    });
+
+    self.pc = addr;
+
+    Ok(())
   }
 
-  fn shift_right(&mut self, t: Type) {
-    let b = Value::from_stack_bytes(self.stack.pop(&t), &t);
-    let a = Value::from_stack_bytes(self.stack.pop(&Type::U8), &Type::U8);
+  /// Stack pointer saved by the innermost `CALL`. Locals are addressed from it.
+  pub fn frame_pointer(&self) -> usize {
+    return self.frames.last().map_or(0, |frame| frame.sp);
+  }
 
-    match (a, b) {
-      (Value::U8(a), Value::U8(b)) => self.stack.push_value(Value::U8(a >> b)),
-      (Value::U16(a), Value::U16(b)) => self.stack.push_value(Value::U16(a >> b)),
-      (Value::U32(a), Value::U32(b)) => self.stack.push_value(Value::U32(a >> b)),
-      (Value::U64(a), Value::U64(b)) => self.stack.push_value(Value::U64(a >> b)),
-      (Value::I8(a), Value::I8(b)) => self.stack.push_value(Value::I8(a >> b)),
-      (Value::I16(a), Value::I16(b)) => self.stack.push_value(Value::I16(a >> b)),
-      (Value::I32(a), Value::I32(b)) => self.stack.push_value(Value::I32(a >> b)),
-      (Value::I64(a), Value::I64(b)) => self.stack.push_value(Value::I64(a >> b)),
-      (Value::Usize(a), Value::Usize(b)) => self.stack.push_value(Value::Usize(a >> b)),
-      (a, b) => {
-        vm_panic(
-          "InvalidType",
-          format!("Cannot shr {:?} and {:?}", a, b).as_str(),
-        );
-        return;
-      }
+  /// Start of the `t` local at `offset` bytes from the frame pointer.
+  fn local(&self, t: Type, offset: isize) -> Result<usize, VmErrorKind> {
+    if matches!(t, Type::String | Type::Bytes) {
+      return Err(VmErrorKind::InvalidType(format!(
+        "Cannot use {} as a local",
+        t.to_str()
+      )));
     }
+
+    return self
+      .frame_pointer()
+      .checked_add_signed(offset)
+      .filter(|start| {
+        start
+          .checked_add(t.size())
+          .is_some_and(|end| end <= self.stack.sp)
+      })
+      .ok_or(VmErrorKind::LocalOutOfBounds(offset));
+  }
+
+  fn local_get(&mut self, t: Type, offset: isize) -> Result<(), VmErrorKind> {
+    let start = self.local(t, offset)?;
+    let value = self.stack.peek_at(start, &t)?.to_vec();
+
+    self.stack.push_typed(&value, &t)
   }
 
-  fn mov(&mut self, reg: u8, value: Value) {
-    self.stack.set_register(reg, value.to_bytes());
+  fn local_set(&mut self, t: Type, offset: isize) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop(&t)?;
+    let start = self.local(t, offset)?;
+
+    self.stack.set_at(start, &value, &t);
+
+    Ok(())
+  }
+
+  /// The global at `index`, which must hold a value of type `t`.
+  fn global(&self, t: Type, index: usize) -> Result<&Value, VmErrorKind> {
+    let global = self
+      .globals
+      .get(index)
+      .ok_or(VmErrorKind::UnknownGlobal(index))?;
+
+    if global.data_type() != t {
+      return Err(VmErrorKind::TypeMismatch {
+        expected: t,
+        actual: global.data_type(),
+      });
+    }
+
+    return Ok(global);
   }
 
-  fn register(&mut self, reg: u8, item_type: Type) {
-    let value = self.stack.peek_register(reg, &item_type);
-    self.stack.push(value);
+  fn global_get(&mut self, t: Type, index: usize) -> Result<(), VmErrorKind> {
+    let value = self.global(t, index)?.clone();
+
+    self.stack.push_value(value)
   }
 
-  fn call(&mut self, _addr: usize) {
-    todo!()
+  fn global_set(&mut self, t: Type, index: usize) -> Result<(), VmErrorKind> {
+    self.global(t, index)?;
+    self.globals[index] = self.stack.pop_value(&t)?;
+
+    Ok(())
   }
 
-  fn external(&mut self, addr: usize) {
-    let external_function = *self
-      .externs
-      .get_mut(&addr)
-      .expect("External function not found");
+  fn spawn(&mut self, addr: usize) -> Result<(), VmErrorKind> {
+    let id = self.spawn_coroutine(addr);
+
+    self.stack.push_value(Value::Usize(id))
+  }
 
-    if let Some(returned_value) = external_function(self) {
-      self.stack.push_value(returned_value);
+  fn yield_value(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    if self.resumers.is_empty() {
+      return Err(VmErrorKind::YieldOutsideCoroutine);
     }
+
+    let value = self.stack.pop_value(&item_type)?;
+
+    self.leave_coroutine(Some(value))
   }
 
-  fn ret(&mut self) {
-    todo!()
+  fn resume(&mut self) -> Result<(), VmErrorKind> {
+    let id = self.stack.pop_usize()?;
+
+    if !self.enter_coroutine(id, false)? {
+      self.stack.push_value(Value::Bool(false))?;
+    }
+
+    Ok(())
   }
 
-  fn goto(&mut self) {
+  fn send(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&item_type)?;
+    let to = self.stack.pop_usize()?;
+
+    self.outbox.push(Message { to, value });
+
+    Ok(())
+  }
+
+  fn receive(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    let value = self
+      .mailbox
+      .front()
+      .ok_or(VmErrorKind::InvalidMessage(String::from(
+        "Mailbox is empty",
+      )))?;
+
+    if value.data_type() != item_type {
+      return Err(VmErrorKind::InvalidMessage(format!(
+        "Expected a message of type {}, got {}",
+        item_type,
+        value.data_type()
+      )));
+    }
+
+    let value = self.mailbox.pop_front().unwrap();
+
+    self.stack.push_value(value)
+  }
+
+  fn try_(&mut self, addr: usize) -> Result<(), VmErrorKind> {
+    self.install_handler(addr);
+
+    Ok(())
+  }
+
+  fn end_try(&mut self) -> Result<(), VmErrorKind> {
+    let handler = self.handlers.last().ok_or(VmErrorKind::NoHandler)?;
+
+    // A handler installed by a caller cannot be removed from inside a callee.
+    if handler.frames != self.frames.len() {
+      return Err(VmErrorKind::NoHandler);
+    }
+
+    self.handlers.pop();
+
+    Ok(())
+  }
+
+  fn throw(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    let value = self.stack.pop_value(&item_type)?;
+
+    self.throw_value(value)
+  }
+
+  fn alloc(&mut self) -> Result<(), VmErrorKind> {
+    let size = self.stack.pop_usize()?;
+    let addr = self.heap.alloc(size)?;
+
+    self.stack.push_value(Value::Usize(addr))
+  }
+
+  fn free(&mut self) -> Result<(), VmErrorKind> {
+    let addr = self.stack.pop_usize()?;
+
+    self.heap.free(addr)
+  }
+
+  fn heap_load(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    let size = Heap::value_size(&item_type)?;
+    let addr = self.stack.pop_usize()?;
+    let bytes = self.heap.read(addr, size)?.to_vec();
+
+    self
+      .stack
+      .push_value(Value::from_stack_bytes(bytes, &item_type))
+  }
+
+  fn heap_store(&mut self, item_type: Type) -> Result<(), VmErrorKind> {
+    Heap::value_size(&item_type)?;
+
+    let value = self.stack.pop_value(&item_type)?;
+    let addr = self.stack.pop_usize()?;
+
+    self.heap.write(addr, &value.to_bytes())
+  }
+
+  fn external(&mut self, import: usize) -> Result<(), VmErrorKind> {
+    let slot = *self
+      .linked_imports
+      .get(import)
+      .ok_or(VmErrorKind::InvalidExtern(import))?;
+
+    // The extern needs `&mut self`, so it is taken out of its slot while it runs.
+    let mut external_function = self.externs[slot]
+      .take()
+      .ok_or(VmErrorKind::InvalidExtern(import))?;
+
+    let started = self.profiler.is_some().then(Instant::now);
+    let returned_value = external_function.call(self);
+
+    self.externs[slot] = Some(external_function);
+
+    if let (Some(profiler), Some(started)) = (self.profiler.as_mut(), started) {
+      profiler.record_extern(import, started.elapsed());
+    }
+
+    if let Some(returned_value) = returned_value? {
+      self.stack.push_value(returned_value)?;
+    }
+
+    Ok(())
+  }
+
+  fn ret(&mut self) -> Result<(), VmErrorKind> {
+    // Returning from the entry point of a coroutine finishes it.
+    if self.frames.is_empty() && !self.resumers.is_empty() {
+      return self.leave_coroutine(None);
+    }
+
+    let frame = self.frames.pop().ok_or(VmErrorKind::NoCallFrame)?;
+
+    self.stack.set_sp(frame.sp)?;
+    self.pc = frame.return_pc;
+    self.discard_handlers();
+
+    Ok(())
+  }
+
+  fn goto(&mut self) -> Result<(), VmErrorKind> {
     let addr = usize::from_be_bytes(
       self
         .stack
-        .peek_register(0, &Type::Usize)
+        .peek_register(ADDRESS_REGISTER, &Type::Usize)?
         .try_into()
         .unwrap(),
     );
 
     self.pc = addr;
+
+    Ok(())
   }
 
-  fn goto_if_zero(&mut self) {
+  fn goto_if_zero(&mut self) -> Result<(), VmErrorKind> {
     let addr = usize::from_be_bytes(
       self
         .stack
-        .peek_register(0, &Type::Usize)
+        .peek_register(ADDRESS_REGISTER, &Type::Usize)?
         .try_into()
         .unwrap(),
     );
-    let value = self.stack.pop_value(&Type::Bool);
+    let value = self.stack.pop_value(&Type::Bool)?;
 
     if value == Value::Bool(false) {
       self.pc = addr
     }
+
+    Ok(())
   }
 
-  fn goto_if_not_zero(&mut self) {
+  fn goto_if_not_zero(&mut self) -> Result<(), VmErrorKind> {
     let addr = usize::from_be_bytes(
       self
         .stack
-        .peek_register(0, &Type::Usize)
+        .peek_register(ADDRESS_REGISTER, &Type::Usize)?
         .try_into()
         .unwrap(),
     );
-    let value = self.stack.pop_value(&Type::Bool);
+    let value = self.stack.pop_value(&Type::Bool)?;
 
     if value != Value::Bool(false) {
       self.pc = addr
     }
+
+    Ok(())
+  }
+}
+
+impl Default for VM {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use svm_lang::{
+    opcodes::{OpCode, Overflow},
+    Program, Type, Value,
+  };
+
+  use super::{StopReason, VM};
+  use crate::VmErrorKind;
+
+  fn load(opcodes: Vec<OpCode>) -> VM {
+    let mut vm = VM::new();
+
+    vm.load(Program {
+      opcodes,
+      ..Program::empty()
+    })
+    .unwrap();
+
+    return vm;
+  }
+
+  #[test]
+  fn ret_restores_the_stack_pointer_of_the_call() {
+    let mut vm = load(vec![
+      OpCode::Push(Value::U64(1)),
+      OpCode::Call(4),
+      OpCode::Push(Value::U8(2)),
+      OpCode::Halt,
+      OpCode::Push(Value::U64(7)),
+      OpCode::Push(Value::U64(8)),
+      OpCode::Return,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.sp, 9);
+    assert!(vm.frames.is_empty());
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(2)));
+    assert_eq!(vm.stack.pop_value(&Type::U64), Ok(Value::U64(1)));
+  }
+
+  #[test]
+  fn nested_calls_return_in_order() {
+    let mut vm = load(vec![
+      OpCode::Call(3),
+      OpCode::Push(Value::U8(3)),
+      OpCode::Halt,
+      OpCode::Call(6),
+      OpCode::Push(Value::U8(2)),
+      OpCode::Return,
+      OpCode::Push(Value::U8(1)),
+      OpCode::Return,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.sp, 1);
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(3)));
+  }
+
+  #[test]
+  fn checked_mode_types_string_literals() {
+    let mut vm = load(vec![
+      OpCode::Push(Value::String(String::from("hello world"))),
+      OpCode::Pop(Type::U64, None),
+    ]);
+    vm.stack.set_checked(true);
+
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::TypeMismatch {
+        expected: Type::U64,
+        actual: Type::String,
+      }
+    );
+  }
+
+  #[test]
+  fn checked_mode_does_not_type_raw_bytes() {
+    let mut vm = load(vec![
+      OpCode::PushBytes(vec![0, 0, 0, 0, 0, 0, 0, 1]),
+      OpCode::Pop(Type::U64, None),
+      OpCode::Halt,
+    ]);
+    vm.stack.set_checked(true);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+  }
+
+  #[test]
+  fn ret_outside_of_a_function_fails() {
+    let mut vm = load(vec![OpCode::Return]);
+
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::NoCallFrame);
+  }
+
+  #[test]
+  fn recursion_is_limited_by_the_call_depth() {
+    let mut vm = load(vec![OpCode::Call(0)]);
+    vm.max_call_depth = 16;
+
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::CallStackOverflow);
+    assert_eq!(vm.frames.len(), 16);
+  }
+
+  #[test]
+  fn strings_that_are_not_utf8_fail_instead_of_panicking() {
+    let uses = [
+      vec![OpCode::Length],
+      vec![OpCode::CharCount],
+      vec![OpCode::Concat],
+      vec![
+        OpCode::PushAll(vec![Value::Usize(0), Value::Usize(1)]),
+        OpCode::Slice,
+      ],
+    ];
+
+    for ops in uses {
+      // `0, 255, 254` reads as a `str` whose bytes are not UTF-8.
+      let mut vm = load(
+        [
+          OpCode::Push(Value::String(String::from("ok"))),
+          OpCode::Push(Value::U8(0)),
+          OpCode::Push(Value::U8(255)),
+          OpCode::Push(Value::U8(254)),
+        ]
+        .into_iter()
+        .chain(ops)
+        .collect(),
+      );
+
+      assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::InvalidUtf8);
+    }
+  }
+
+  #[test]
+  fn slices_strings_by_char() {
+    let mut vm = load(vec![
+      OpCode::Push(Value::String(String::from("héllo"))),
+      OpCode::PushAll(vec![Value::Usize(1), Value::Usize(3)]),
+      OpCode::Slice,
+      OpCode::Copy(Type::String),
+      OpCode::CharCount,
+      OpCode::Halt,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::Usize), Ok(Value::Usize(2)));
+    assert_eq!(vm.stack.pop_string(), Ok(String::from("él")));
+  }
+
+  #[test]
+  fn const_pushes_typed_constants_from_the_pool() {
+    let mut vm = VM::new();
+    vm.stack.set_checked(true);
+    vm.load(Program {
+      opcodes: vec![
+        OpCode::PushConstant(1),
+        OpCode::PushConstant(0),
+        OpCode::PushConstant(0),
+        OpCode::Halt,
+      ],
+      constants: vec![Value::String(String::from("hi")), Value::F64(1.5)],
+      ..Program::empty()
+    })
+    .unwrap();
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_string(), Ok(String::from("hi")));
+    assert_eq!(vm.stack.pop_string(), Ok(String::from("hi")));
+    assert_eq!(vm.stack.pop_value(&Type::F64), Ok(Value::F64(1.5)));
+  }
+
+  #[test]
+  fn unknown_constants_are_rejected_at_load() {
+    let mut vm = VM::new();
+    let result = vm.load(Program {
+      opcodes: vec![OpCode::Halt, OpCode::PushConstant(1)],
+      constants: vec![Value::U8(1)],
+      ..Program::empty()
+    });
+
+    assert_eq!(result, Err(VmErrorKind::UnknownConstant(1)));
+  }
+
+  #[test]
+  fn globals_keep_their_values_and_types() {
+    let mut vm = VM::new();
+    vm.load(Program {
+      opcodes: vec![
+        OpCode::GlobalGet(Type::U64, 0),
+        OpCode::Increment(Type::U64, Overflow::Checked),
+        OpCode::GlobalSet(Type::U64, 0),
+        OpCode::GlobalGet(Type::U64, 0),
+        OpCode::Halt,
+      ],
+      globals: vec![Value::U64(41)],
+      ..Program::empty()
+    })
+    .unwrap();
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::U64), Ok(Value::U64(42)));
+    assert_eq!(vm.globals, vec![Value::U64(42)]);
+  }
+
+  #[test]
+  fn globals_must_exist_and_match_the_type() {
+    let mut vm = load(vec![OpCode::GlobalGet(Type::U8, 0)]);
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::UnknownGlobal(0));
+
+    let mut vm = VM::new();
+    vm.load(Program {
+      opcodes: vec![OpCode::Push(Value::U8(1)), OpCode::GlobalSet(Type::U8, 0)],
+      globals: vec![Value::U64(0)],
+      ..Program::empty()
+    })
+    .unwrap();
+
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::TypeMismatch {
+        expected: Type::U8,
+        actual: Type::U64,
+      }
+    );
+  }
+
+  #[test]
+  fn locals_are_addressed_from_the_frame_pointer() {
+    let mut vm = load(vec![
+      OpCode::Push(Value::U32(5)),
+      OpCode::Call(3),
+      OpCode::Halt,
+      // The argument is at -4 and the new local at 0.
+      OpCode::Push(Value::U32(0)),
+      OpCode::LocalGet(Type::U32, -4),
+      OpCode::LocalSet(Type::U32, 0),
+      OpCode::LocalGet(Type::U32, 0),
+      OpCode::Increment(Type::U32, Overflow::Checked),
+      OpCode::LocalSet(Type::U32, -4),
+      OpCode::Return,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.sp, 4);
+    assert_eq!(vm.stack.pop_value(&Type::U32), Ok(Value::U32(6)));
+  }
+
+  #[test]
+  fn locals_must_lie_below_the_stack_pointer() {
+    let mut vm = load(vec![OpCode::LocalGet(Type::U32, 0)]);
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::LocalOutOfBounds(0));
+
+    let mut vm = load(vec![
+      OpCode::Push(Value::U16(1)),
+      OpCode::LocalSet(Type::U8, -1),
+    ]);
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::LocalOutOfBounds(-1)
+    );
+  }
+
+  #[test]
+  fn throw_unwinds_call_frames_to_the_handler() {
+    let mut vm = load(vec![
+      OpCode::Try(5),
+      OpCode::Push(Value::U8(9)),
+      OpCode::Call(7),
+      OpCode::EndTry,
+      OpCode::Halt,
+      // Handler: the type code is on top of the thrown value.
+      OpCode::Pop(Type::U8, None),
+      OpCode::Halt,
+      OpCode::Push(Value::I32(7)),
+      OpCode::Throw(Type::I32),
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert!(vm.frames.is_empty());
+    assert!(vm.handlers.is_empty());
+    assert_eq!(vm.stack.pop_value(&Type::I32), Ok(Value::I32(7)));
+    assert_eq!(vm.stack.sp, 0);
+  }
+
+  #[test]
+  fn handlers_of_a_returned_function_do_not_catch() {
+    let mut vm = load(vec![
+      OpCode::Call(3),
+      OpCode::Push(Value::U8(1)),
+      OpCode::Throw(Type::U8),
+      OpCode::Try(5),
+      OpCode::Return,
+      OpCode::Halt,
+    ]);
+
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::Uncaught(Value::U8(1))
+    );
+    assert!(vm.handlers.is_empty());
+  }
+
+  #[test]
+  fn end_try_without_try_fails() {
+    let mut vm = load(vec![OpCode::EndTry]);
+
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::NoHandler);
+  }
+
+  #[test]
+  fn shifts_pop_both_operands_with_the_type() {
+    let mut vm = load(vec![
+      OpCode::Push(Value::U16(1)),
+      OpCode::Push(Value::U16(9)),
+      OpCode::ShiftLeft(Type::U16, Overflow::Checked),
+      OpCode::Halt,
+    ]);
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::U16), Ok(Value::U16(512)));
+
+    let mut vm = load(vec![
+      OpCode::Push(Value::U8(1)),
+      OpCode::Push(Value::U8(9)),
+      OpCode::ShiftLeft(Type::U8, Overflow::Checked),
+    ]);
+
+    assert_eq!(
+      vm.run().unwrap_err().kind,
+      VmErrorKind::IntegerOverflow(Type::U8)
+    );
+  }
+
+  #[test]
+  fn load_rejects_unresolved_imports_without_touching_the_vm() {
+    let mut vm = load(vec![OpCode::Halt]);
+    vm.register_extern("std.a", |_: &mut VM| Ok(Some(Value::U8(1))))
+      .unwrap();
+
+    let program = || Program {
+      opcodes: vec![OpCode::External(1), OpCode::Halt],
+      imports: vec![String::from("std.a"), String::from("std.b")],
+      ..Program::empty()
+    };
+
+    assert_eq!(
+      vm.load(program()),
+      Err(VmErrorKind::UnresolvedImports(vec![String::from("std.b")]))
+    );
+    assert_eq!(vm.program.opcodes.len(), 1);
+
+    vm.register_extern("std.b", |_: &mut VM| Ok(Some(Value::U8(2))))
+      .unwrap();
+    vm.load(program()).unwrap();
+
+    assert!(matches!(vm.run(), Ok(StopReason::Halted)));
+    assert_eq!(vm.stack.pop_value(&Type::U8), Ok(Value::U8(2)));
   }
 }
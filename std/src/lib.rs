@@ -1,6 +1,6 @@
-use svm_runtime::VM;
-mod stdio;
+use svm_runtime::{VmErrorKind, VM};
+pub mod stdio;
 
-pub fn stdio_plugin(vm: &mut VM) {
+pub fn stdio_plugin(vm: &mut VM) -> Result<(), VmErrorKind> {
   vm.load_plugin(stdio::vm_plugin)
 }
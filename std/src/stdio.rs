@@ -1,31 +1,58 @@
+use std::io::{self, Stdout, Write};
+
 use svm_lang::Value;
-use svm_runtime::VM;
-
-fn print(vm: &mut VM) -> Option<Value> {
-  let item_type = vm.stack.pop_type();
-  let value = vm.stack.pop_value(&item_type);
-
-  match value {
-    Value::Bool(value) => print!("{}", value),
-    Value::Bytes(value) => print!("Bytes {}", String::from_utf8(value).unwrap()),
-    Value::F32(value) => print!("{}", value),
-    Value::F64(value) => print!("{}", value),
-    Value::I16(value) => print!("{}", value),
-    Value::I32(value) => print!("{}", value),
-    Value::I64(value) => print!("{}", value),
-    Value::I8(value) => print!("{}", value),
-    Value::Isize(value) => print!("{}", value),
-    Value::U16(value) => print!("{}", value),
-    Value::U32(value) => print!("{}", value),
-    Value::U64(value) => print!("{}", value),
-    Value::U8(value) => print!("{}", value),
-    Value::Usize(value) => print!("{}", value),
-    Value::String(value) => print!("{}", value),
+use svm_runtime::{Extern, VmErrorKind, VM};
+
+/// Pops a type code and a value of that type and writes the value to `out`.
+pub struct Print<W: Write> {
+  out: W,
+}
+
+impl<W: Write> Print<W> {
+  pub fn new(out: W) -> Self {
+    Self { out }
+  }
+
+  fn write(&mut self, value: Value) -> io::Result<()> {
+    match value {
+      Value::Bool(value) => write!(self.out, "{}", value),
+      Value::Bytes(value) => write!(self.out, "Bytes {}", String::from_utf8_lossy(&value)),
+      Value::F32(value) => write!(self.out, "{}", value),
+      Value::F64(value) => write!(self.out, "{}", value),
+      Value::I16(value) => write!(self.out, "{}", value),
+      Value::I32(value) => write!(self.out, "{}", value),
+      Value::I64(value) => write!(self.out, "{}", value),
+      Value::I8(value) => write!(self.out, "{}", value),
+      Value::Isize(value) => write!(self.out, "{}", value),
+      Value::U16(value) => write!(self.out, "{}", value),
+      Value::U32(value) => write!(self.out, "{}", value),
+      Value::U64(value) => write!(self.out, "{}", value),
+      Value::U8(value) => write!(self.out, "{}", value),
+      Value::Usize(value) => write!(self.out, "{}", value),
+      Value::String(value) => write!(self.out, "{}", value),
+    }?;
+
+    self.out.flush()
   }
+}
+
+impl<W: Write> Extern for Print<W> {
+  fn call(&mut self, vm: &mut VM) -> Result<Option<Value>, VmErrorKind> {
+    let item_type = vm.stack.pop_type()?;
+    let value = vm.stack.pop_value(&item_type)?;
+
+    self
+      .write(value)
+      .map_err(|error| VmErrorKind::ExternError(format!("Cannot print: {}", error)))?;
+
+    Ok(None)
+  }
+}
 
-  None
+pub fn print() -> Print<Stdout> {
+  Print::new(io::stdout())
 }
 
-pub fn vm_plugin(vm: &mut VM) {
-  vm.register_extern(0, &print);
+pub fn vm_plugin(vm: &mut VM) -> Result<(), VmErrorKind> {
+  vm.register_extern("std.io.print", print())
 }
@@ -0,0 +1,308 @@
+use std::io::{stdin, stdout, BufRead, Write};
+
+use svm_lang::{opcodes::register_name, Type, Value};
+use svm_runtime::{StopReason, VM};
+
+const HELP: &str = "Commands:
+  s, step [n]            execute n instructions (default 1)
+  c, continue            run until a breakpoint or the end of the program
+  b, break <pc|label>    set a breakpoint
+  d, delete <pc|label>   remove a breakpoint
+  l, list [n]            show n instructions around the PC (default 5)
+  p, stack [type ...]    show the stack, decoding the top entries with the given types
+                         (or with their pushed types when running with --checked)
+  r, regs                show the registers
+  set <reg> <type> <v>   store a value in a register
+  bt, frames             show the call stack
+  send <type> <value>    deliver a message to the mailbox
+  save <file>            save a snapshot of the VM state
+  q, quit                exit the debugger";
+
+pub fn run(vm: &mut VM) {
+  println!("Type \"help\" for a list of commands.");
+  show_current(vm);
+
+  let mut input = stdin().lock();
+
+  loop {
+    print!("(svm) ");
+    stdout().flush().unwrap();
+
+    let mut line = String::new();
+
+    if input.read_line(&mut line).unwrap_or(0) == 0 {
+      return;
+    }
+
+    let args = line.split_whitespace().collect::<Vec<_>>();
+
+    let Some(command) = args.first() else {
+      continue;
+    };
+
+    match *command {
+      "s" | "step" => {
+        let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+
+        for _ in 0..count {
+          if let Some(reason) = vm.step() {
+            report(vm, reason);
+            break;
+          }
+        }
+
+        show_current(vm);
+      }
+      "c" | "continue" => {
+        let reason = vm.run_until_break();
+
+        report(vm, reason);
+        show_current(vm);
+      }
+      "b" | "break" => match args.get(1).and_then(|target| resolve(vm, target)) {
+        Some(pc) => {
+          vm.breakpoints.insert(pc);
+          println!("Breakpoint set at {}", describe(vm, pc));
+        }
+        None => println!("Expected a valid PC or label"),
+      },
+      "d" | "delete" => match args.get(1).and_then(|target| resolve(vm, target)) {
+        Some(pc) if vm.breakpoints.remove(&pc) => println!("Breakpoint removed"),
+        Some(_) => println!("No breakpoint at that address"),
+        None => println!("Expected a valid PC or label"),
+      },
+      "l" | "list" => {
+        let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(5);
+
+        list(vm, count);
+      }
+      "p" | "stack" => print_stack(vm, &args[1..]),
+      "r" | "regs" => print_registers(vm),
+      "set" => set_register(vm, &args[1..]),
+      "bt" | "frames" => {
+        for (depth, frame) in vm.frames.iter().enumerate().rev() {
+          println!(
+            "#{} return to {} (sp={})",
+            depth,
+            describe(vm, frame.return_pc),
+            frame.sp
+          );
+        }
+      }
+      "send" => send_message(vm, &args[1..]),
+      "save" => match args.get(1) {
+        Some(path) => match vm.save_snapshot(path) {
+          Ok(()) => println!("Snapshot saved to {:?}", path),
+          Err(error) => println!("Could not save snapshot: {}", error),
+        },
+        None => println!("Expected a file name"),
+      },
+      "h" | "help" => println!("{}", HELP),
+      "q" | "quit" => return,
+      command => println!("Unknown command \"{}\". Type \"help\".", command),
+    }
+  }
+}
+
+fn resolve(vm: &VM, target: &str) -> Option<usize> {
+  if let Ok(pc) = target.parse::<usize>() {
+    return Some(pc);
+  }
+
+  return vm
+    .program()
+    .labels
+    .get(target.trim_start_matches('.').trim_end_matches(':'))
+    .copied();
+}
+
+fn describe(vm: &VM, pc: usize) -> String {
+  match vm.program().label_at(pc) {
+    Some(label) => format!("{:04} <{}>", pc, label),
+    None => format!("{:04}", pc),
+  }
+}
+
+fn show_current(vm: &VM) {
+  match vm.program().opcodes.get(vm.pc) {
+    Some(op) => println!("=> {}: {}", describe(vm, vm.pc), op),
+    None => println!("=> {}: <end of program>", describe(vm, vm.pc)),
+  }
+}
+
+fn report(vm: &VM, reason: StopReason) {
+  match reason {
+    StopReason::Halted => println!("Program halted"),
+    StopReason::EndOfProgram => println!("End of program"),
+    StopReason::Breakpoint(pc) => println!("Breakpoint at {}", describe(vm, pc)),
+    StopReason::Cancelled => println!("Cancelled at {}", describe(vm, vm.pc)),
+    StopReason::TimedOut => println!("Timed out at {}", describe(vm, vm.pc)),
+    StopReason::Blocked => println!(
+      "Waiting for a message at {} (use \"send\" to deliver one)",
+      describe(vm, vm.pc)
+    ),
+    StopReason::Error(error) => println!("{}", error),
+  }
+}
+
+fn list(vm: &VM, count: usize) {
+  let start = vm.pc.saturating_sub(count / 2);
+  let end = (start + count).min(vm.program().opcodes.len());
+
+  for pc in start..end {
+    if let Some(label) = vm.program().label_at(pc) {
+      println!("{}:", label);
+    }
+
+    let marker = match (pc == vm.pc, vm.breakpoints.contains(&pc)) {
+      (true, _) => "=>",
+      (false, true) => " *",
+      _ => "  ",
+    };
+
+    println!("{} {:04}: {}", marker, pc, vm.program().opcodes[pc]);
+  }
+}
+
+fn print_stack(vm: &mut VM, types: &[&str]) {
+  println!("sp={} size={}", vm.stack.sp, vm.stack.size);
+  println!(
+    "{:?}",
+    &vm.stack.data[..vm.stack.sp.min(vm.stack.data.len())]
+  );
+
+  if types.is_empty() {
+    for entry in vm.stack.entries().iter().rev() {
+      let bytes = vm.stack.data[entry.start..entry.end].to_vec();
+
+      println!(
+        "[{}] {}: {:?}",
+        entry.end,
+        entry.item_type,
+        Value::from_stack_bytes(bytes, &entry.item_type)
+      );
+    }
+  }
+
+  let sp = vm.stack.sp;
+
+  for name in types {
+    let Some(item_type) = parse_type(name) else {
+      println!("Unknown type \"{}\"", name);
+      break;
+    };
+
+    let size = match vm.stack.peek(&item_type) {
+      Ok(bytes) => bytes.len(),
+      Err(error) => {
+        println!("{}: {}", item_type, error);
+        break;
+      }
+    };
+
+    match vm.stack.peek_value(&item_type) {
+      Ok(value) => println!("[{}] {}: {:?}", vm.stack.sp, item_type, value),
+      Err(error) => println!("{}: {}", item_type, error),
+    }
+
+    vm.stack.sp -= size;
+  }
+
+  vm.stack.sp = sp;
+}
+
+fn print_registers(vm: &VM) {
+  let width = std::mem::size_of::<usize>();
+
+  for (index, bytes) in vm.stack.registers.chunks_exact(width).enumerate() {
+    let value = usize::from_be_bytes(bytes.try_into().unwrap());
+
+    println!(
+      "%{:<5} = {:#018x} ({})",
+      register_name(index as u8 + 1),
+      value,
+      value
+    );
+  }
+}
+
+fn set_register(vm: &mut VM, args: &[&str]) {
+  let [register, item_type, value] = args else {
+    println!("Usage: set <reg> <type> <value>");
+    return;
+  };
+
+  let name = register.trim_start_matches('%');
+  let count = vm.stack.register_count();
+
+  let Some(register) =
+    (1..=count as u8).find(|r| register_name(*r) == name || format!("r{}", r - 1) == name)
+  else {
+    println!("Unknown register \"{}\"", register);
+    return;
+  };
+
+  let Some(value) = parse_type(item_type).and_then(|t| parse_value(&t, value)) else {
+    println!("Invalid value \"{}\" for type \"{}\"", value, item_type);
+    return;
+  };
+
+  match vm.stack.set_register(register, &value.to_bytes()) {
+    Ok(()) => println!("%{} = {:?}", register_name(register), value),
+    Err(error) => println!("{}", error),
+  }
+}
+
+fn send_message(vm: &mut VM, args: &[&str]) {
+  let [item_type, value] = args else {
+    println!("Usage: send <type> <value>");
+    return;
+  };
+
+  let Some(value) = parse_type(item_type).and_then(|t| parse_value(&t, value)) else {
+    println!("Invalid value \"{}\" for type \"{}\"", value, item_type);
+    return;
+  };
+
+  println!("Message {:?} delivered", value);
+  vm.mailbox.push_back(value);
+}
+
+fn parse_type(name: &str) -> Option<Type> {
+  match name {
+    "bool" => Some(Type::Bool),
+    "u8" => Some(Type::U8),
+    "i8" => Some(Type::I8),
+    "u16" => Some(Type::U16),
+    "i16" => Some(Type::I16),
+    "u32" => Some(Type::U32),
+    "i32" => Some(Type::I32),
+    "u64" => Some(Type::U64),
+    "i64" => Some(Type::I64),
+    "f32" => Some(Type::F32),
+    "f64" => Some(Type::F64),
+    "usize" => Some(Type::Usize),
+    "isize" => Some(Type::Isize),
+    "str" => Some(Type::String),
+    _ => None,
+  }
+}
+
+fn parse_value(item_type: &Type, text: &str) -> Option<Value> {
+  match item_type {
+    Type::Bool => text.parse().ok().map(Value::Bool),
+    Type::U8 => text.parse().ok().map(Value::U8),
+    Type::I8 => text.parse().ok().map(Value::I8),
+    Type::U16 => text.parse().ok().map(Value::U16),
+    Type::I16 => text.parse().ok().map(Value::I16),
+    Type::U32 => text.parse().ok().map(Value::U32),
+    Type::I32 => text.parse().ok().map(Value::I32),
+    Type::U64 => text.parse().ok().map(Value::U64),
+    Type::I64 => text.parse().ok().map(Value::I64),
+    Type::F32 => text.parse().ok().map(Value::F32),
+    Type::F64 => text.parse().ok().map(Value::F64),
+    Type::Usize => text.parse().ok().map(Value::Usize),
+    Type::Isize => text.parse().ok().map(Value::Isize),
+    Type::String | Type::Bytes => None,
+  }
+}
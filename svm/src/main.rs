@@ -1,7 +1,111 @@
-use std::{fs::File, process::exit};
+use std::{fs::File, io::BufWriter, process::exit, time::Duration};
 
-use svm_lang::Program;
-use svm_runtime::VM;
+use svm_lang::{verify, Program};
+use svm_runtime::{
+  JsonTracer, ProcessState, Profiler, Scheduler, StopReason, VmErrorKind, DEFAULT_QUANTUM,
+  DEFAULT_REGISTER_COUNT, MAX_REGISTER_COUNT, VM,
+};
+
+mod debugger;
+
+const USAGE: &str = "Usage: svm [options] <input>
+       svm [options] --resume <snapshot>
+       svm [--checked] [--gas <amount>] [--timeout <ms>] [--quantum <n>] <input> <input>...
+
+With several inputs, each program runs as a process with its own VM. The pid used by SEND
+is the position of the input, starting at 0.
+
+Options:
+  -d, --debug             run in the interactive debugger
+  -c, --checked           check the type of every value popped from the stack
+      --registers <n>     number of general registers, from 1 to 255 (default 4)
+      --no-verify         skip the bytecode verifier when loading a program
+  -t, --trace <file>      write a JSON-lines execution trace
+  -g, --gas <amount>      limit execution to <amount> gas
+      --timeout <ms>      stop after <ms> milliseconds
+  -p, --profile <file>    print a profile report and write folded stacks to <file>
+  -s, --snapshot <file>   save the VM state to <file> if execution stops before the end
+  -r, --resume <file>     resume from a snapshot instead of loading <input>
+  -q, --quantum <n>       instructions each process runs before switching (default 1000)";
+
+struct Options {
+  inputs: Vec<String>,
+  debug: bool,
+  checked: bool,
+  registers: usize,
+  verify: bool,
+  trace: Option<String>,
+  gas: Option<u64>,
+  timeout: Option<Duration>,
+  profile: Option<String>,
+  snapshot: Option<String>,
+  resume: Option<String>,
+  quantum: Option<usize>,
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+  let mut inputs = Vec::new();
+  let mut debug = false;
+  let mut checked = false;
+  let mut registers = DEFAULT_REGISTER_COUNT;
+  let mut verify = true;
+  let mut trace = None;
+  let mut gas = None;
+  let mut timeout = None;
+  let mut profile = None;
+  let mut snapshot = None;
+  let mut resume = None;
+  let mut quantum = None;
+  let mut args = args.iter();
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-d" | "--debug" => debug = true,
+      "-c" | "--checked" => checked = true,
+      "--registers" => registers = args.next()?.parse().ok()?,
+      "--no-verify" => verify = false,
+      "-t" | "--trace" => trace = Some(args.next()?.clone()),
+      "-g" | "--gas" => gas = Some(args.next()?.parse().ok()?),
+      "-p" | "--profile" => profile = Some(args.next()?.clone()),
+      "-s" | "--snapshot" => snapshot = Some(args.next()?.clone()),
+      "-r" | "--resume" => resume = Some(args.next()?.clone()),
+      "-q" | "--quantum" => quantum = Some(args.next()?.parse().ok()?),
+      "--timeout" => timeout = Some(Duration::from_millis(args.next()?.parse().ok()?)),
+      flag if flag.starts_with('-') => return None,
+      path => inputs.push(path.to_string()),
+    }
+  }
+
+  // `MAX_REGISTER_COUNT` is the most `VM::with_registers` accepts; the default is 4.
+  if registers == 0 || registers > MAX_REGISTER_COUNT {
+    return None;
+  }
+
+  if inputs.is_empty() == resume.is_none() {
+    return None;
+  }
+
+  let single_process = debug || trace.is_some() || profile.is_some() || snapshot.is_some();
+
+  if inputs.len() > 1 && single_process {
+    return None;
+  }
+
+  Some(Options {
+    inputs,
+    debug,
+    checked,
+    registers,
+    verify,
+    trace,
+    gas,
+    timeout,
+    profile,
+    snapshot,
+    resume,
+    quantum,
+  })
+}
 
 fn open_file(file: String) -> File {
   if let Ok(file) = File::open(file.clone()) {
@@ -15,18 +119,172 @@ fn open_file(file: String) -> File {
 fn main() {
   let args: Vec<String> = std::env::args().collect();
 
-  if args.len() < 2 {
-    println!("Usage: svm <input>");
+  let Some(options) = parse_args(&args[1..]) else {
+    println!("{}", USAGE);
+    return;
+  };
+
+  if options.inputs.len() > 1 {
+    run_processes(&options);
     return;
   }
 
-  let mut vm = VM::new();
+  let mut vm = new_vm(options.registers);
+  vm.stack.set_checked(options.checked);
+
+  if let Some(input) = options.inputs.first() {
+    load_program(&mut vm, input, options.verify);
+  }
+
+  if let Some(path) = &options.resume {
+    if let Err(error) = vm.load_snapshot(path) {
+      println!("Could not resume from {:?}: {}", path, error);
+      exit(1);
+    }
+  }
 
-  vm.load_plugin(svm_std::stdio_plugin);
+  if let Some(path) = options.trace {
+    match File::create(&path) {
+      Ok(file) => vm.tracer = Some(Box::new(JsonTracer::new(BufWriter::new(file)))),
+      Err(error) => {
+        println!("Could not create trace file {:?}: {}", path, error);
+        exit(1);
+      }
+    }
+  }
 
-  vm.program = Program::from_file(open_file(args.get(1).unwrap().clone()));
+  if options.gas.is_some() {
+    vm.set_gas(options.gas);
+  }
 
-  vm.run();
+  if options.profile.is_some() {
+    vm.profiler = Some(Profiler::new());
+  }
+
+  if options.debug {
+    debugger::run(&mut vm);
+    return;
+  }
+
+  vm.set_timeout(options.timeout);
+
+  let result = vm.run();
+
+  match &result {
+    Ok(StopReason::TimedOut) => println!("Program timed out at PC={}", vm.pc),
+    Ok(StopReason::Blocked) => println!("Program blocked waiting for a message at PC={}", vm.pc),
+    Err(error) => println!("{}", error),
+    _ => {}
+  }
 
   println!("Program terminated with stack: {:?}", vm.stack);
+
+  let suspended = match &result {
+    Ok(reason) => matches!(reason, StopReason::TimedOut | StopReason::Cancelled),
+    Err(error) => error.kind == VmErrorKind::OutOfGas,
+  };
+
+  if let (true, Some(path)) = (suspended, &options.snapshot) {
+    match vm.save_snapshot(path) {
+      Ok(()) => println!("Snapshot saved to {:?}", path),
+      Err(error) => println!("Could not save snapshot {:?}: {}", path, error),
+    }
+  }
+
+  if let Some(gas) = vm.remaining_gas() {
+    println!("Remaining gas: {}", gas);
+  }
+
+  if let (Some(profiler), Some(path)) = (&vm.profiler, &options.profile) {
+    println!("\n{}", profiler.report(vm.program()));
+
+    if let Err(error) = std::fs::write(path, profiler.folded_stacks(vm.program())) {
+      println!("Could not write profile {:?}: {}", path, error);
+      exit(1);
+    }
+  }
+
+  if !matches!(result, Ok(StopReason::Halted | StopReason::EndOfProgram)) {
+    exit(1);
+  }
+}
+
+fn new_vm(registers: usize) -> VM {
+  let mut vm = match VM::with_registers(registers) {
+    Ok(vm) => vm,
+    Err(error) => {
+      println!("Could not create VM: {}", error);
+      exit(1);
+    }
+  };
+
+  if let Err(error) = vm.load_plugin(svm_std::stdio_plugin) {
+    println!("Could not load plugin: {}", error);
+    exit(1);
+  }
+
+  return vm;
+}
+
+fn load_program(vm: &mut VM, input: &str, verify_program: bool) {
+  let program = Program::from_file(open_file(input.to_string()));
+
+  if verify_program {
+    let externs = vm.extern_names().collect::<Vec<_>>();
+
+    if let Err(errors) = verify(&program, &externs) {
+      println!("Program {:?} failed verification:", input);
+      errors.iter().for_each(|error| println!("  {}", error));
+      exit(1);
+    }
+  }
+
+  if let Err(error) = vm.load(program) {
+    println!("Could not load program {:?}: {}", input, error);
+    exit(1);
+  }
+
+  if let Ok(symbols) = File::open(format!("{}.sym", input)) {
+    vm.load_labels(symbols);
+  }
+}
+
+fn run_processes(options: &Options) {
+  let mut scheduler = Scheduler::new(options.quantum.unwrap_or(DEFAULT_QUANTUM));
+
+  for input in &options.inputs {
+    let mut vm = new_vm(options.registers);
+    vm.stack.set_checked(options.checked);
+
+    load_program(&mut vm, input, options.verify);
+    vm.set_gas(options.gas);
+    vm.set_timeout(options.timeout);
+
+    scheduler.spawn(vm);
+  }
+
+  let result = scheduler.run();
+
+  if let Err(error) = &result {
+    println!("{}", error);
+  }
+
+  for (pid, process) in scheduler.processes.iter().enumerate() {
+    let state = match &process.state {
+      ProcessState::Ready => String::from("ready"),
+      ProcessState::Blocked => String::from("blocked"),
+      ProcessState::Done(StopReason::Error(error)) => format!("failed at PC={}", error.pc),
+      ProcessState::Done(StopReason::TimedOut) => String::from("timed out"),
+      ProcessState::Done(_) => String::from("finished"),
+    };
+
+    println!(
+      "Process {} ({}) {}: {:?}",
+      pid, options.inputs[pid], state, process.vm.stack
+    );
+  }
+
+  if result.is_err() {
+    exit(1);
+  }
 }
@@ -35,9 +35,13 @@ fn main() {
     .unwrap_or(format!("{}.bin", input));
 
   let file = open_file(input);
-  let mut output = create_file(output);
+  let program = compile(file);
 
-  output
-    .write_all(compile(file).to_bytes().as_slice())
+  create_file(output.clone())
+    .write_all(program.to_bytes().as_slice())
+    .expect("Could not write to file");
+
+  create_file(format!("{}.sym", output))
+    .write_all(program.labels_to_string().as_bytes())
     .expect("Could not write to file");
 }